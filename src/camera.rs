@@ -1,7 +1,9 @@
 use crate::math::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct CropFactor(f32);
 impl CropFactor {
     pub const FULL_FORMAT: Self = Self(1.0_f32);
@@ -12,14 +14,14 @@ impl CropFactor {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum Focus {
     AutoFocus,
     Distance(f32),
 }
 
 /// implements a camera from which to render from
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Camera {
     /// position in 3d space
     pub position: Vec3,
@@ -110,6 +112,16 @@ impl Camera {
         }
     }
 
+    /// the width of the rendered image
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// the height of the rendered image
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
     /// not recommended, directly sets aperture!
     /// use set_fstop instead
     pub fn set_aperture(&mut self, aperture: f32) {
@@ -158,8 +170,88 @@ impl Camera {
         fwd.cross(right)
     }
 
+    /// the distance from the camera to the plane that is in focus
+    fn focus_distance(&self) -> f32 {
+        match self.focus {
+            Focus::AutoFocus => {
+                2.0 //TODO!
+            }
+            Focus::Distance(d) => d,
+        }
+    }
+
+    /// the width of a pixel on the plane that is in focus, in world units
+    fn pixel_scale(&self) -> f32 {
+        //width of our screen at focal distance
+        let focal_width = 2.0 * self.tan_half_fov * self.focus_distance();
+
+        //figure out by how much we have to scale real_width and real_height to arrive at focal_width / focal_height
+        focal_width / self.width as f32
+    }
+
+    /// a random point on the lens, and the density with which it was picked per area
+    /// without an aperture, this is always the position, with a density of 1
+    pub fn sample_lens(&self, sampler: &mut dyn Sampler) -> (Vec3, f32) {
+        let lens_pos = Vec3::random_in_unit_disk(sampler) * (self.aperture / 2.0); //aperture/2 == lens radius
+        (self.position + lens_pos, self.lens_pdf())
+    }
+
+    /// the density per area with which `sample_lens` picks a point
+    pub fn lens_pdf(&self) -> f32 {
+        let radius = self.aperture / 2.0;
+        if radius > 0.0 {
+            1.0 / (std::f32::consts::PI * radius * radius)
+        } else {
+            1.0
+        }
+    }
+
+    /// the inverse of `get_ray`: the pixel coordinates of the ray starting at `lens`,
+    /// a point on the lens, going into `direction`
+    /// None if the ray does not go through the image
+    pub fn raster_position(&self, lens: Vec3, direction: Vec3) -> Option<(f32, f32)> {
+        let direction = direction.normalised();
+        let cosine = direction.dot(self.direction);
+        if cosine <= 0.0 {
+            return None;
+        }
+
+        //where the ray meets the plane in focus, relative to its center
+        let distance = self.focus_distance() - (lens - self.position).dot(self.direction);
+        let focus_point = lens + direction * (distance / cosine);
+        let offset = focus_point - (self.position + self.direction * self.focus_distance());
+
+        let scale = self.pixel_scale();
+        let x =
+            offset.dot(self.right) / (scale * self.right.len_squared()) + (self.width / 2) as f32;
+        let y = offset.dot(self.up) / (-scale * self.up.len_squared()) + (self.height / 2) as f32;
+
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            None
+        } else {
+            Some((x, y))
+        }
+    }
+
+    /// the density per solid angle of camera rays from `lens` into `direction`,
+    /// if the rays were spread evenly over the whole image
+    pub fn direction_pdf(&self, lens: Vec3, direction: Vec3) -> f32 {
+        if self.raster_position(lens, direction).is_none() {
+            return 0.0;
+        }
+        let cosine = direction.normalised().dot(self.direction);
+        let distance = self.focus_distance() - (lens - self.position).dot(self.direction);
+
+        //the image on the plane in focus, every pixel spans scale * right and scale * up
+        let scale = self.pixel_scale();
+        let area =
+            (self.width * self.height) as f32 * scale * scale * self.right.len() * self.up.len();
+
+        distance * distance / (area * cosine * cosine * cosine)
+    }
+
     /// gets a new ray from the camera at the screen coordinates x and y
-    pub fn get_ray(&self, x: f32, y: f32) -> Ray {
+    pub fn get_ray(&self, x: f32, y: f32, sampler: &mut dyn Sampler) -> Ray {
         //yes, this is very verbose on purpose, I know it can be optimised
         //but tbh, the compiler probably does that for us
 
@@ -179,18 +271,8 @@ impl Camera {
         // tan 45 = width/2
         // 2 * tan 45 = width
 
-        let focus_dist = match self.focus {
-            Focus::AutoFocus => {
-                2.0 //TODO!
-            }
-            Focus::Distance(d) => d,
-        };
-
-        //width of our screen at focal distance
-        let focal_width = 2.0 * self.tan_half_fov * focus_dist;
-
-        //figure out by how much we have to scale real_width and real_height to arrive at focal_width / focal_height
-        let scale = focal_width / self.width as f32;
+        let focus_dist = self.focus_distance();
+        let scale = self.pixel_scale();
 
         //HINT: no need to scale by aspect ratio because x and y don't go between 0..1, but 0..width / 0..height!
         //else it would be:
@@ -210,8 +292,7 @@ impl Camera {
             + (x - (self.width / 2) as f32) * right //this is where real_width is scaled down to focal_width
             + (y - (self.height / 2) as f32) * up;
 
-        let lens_pos = Vec3::random_in_unit_disk() * (self.aperture / 2.0); //aperture/2 == lens radius
-        let start = self.position + lens_pos; //start ray at random point in "lens"
+        let (start, _) = self.sample_lens(sampler); //start ray at random point in "lens"
 
         //direction of the ray from us to pixel pos
         let pixel_dir = pixel_pos - start;
@@ -219,3 +300,36 @@ impl Camera {
         Ray::new(start, pixel_dir)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::sampler::RandomSampler;
+
+    #[test]
+    fn test_raster_position() {
+        let camera = Camera::new_physical(
+            Vec3::new(-7.0, 12.0, -7.0),
+            Vec3::new(7.0, -7.0, 7.0),
+            80,
+            60,
+            Focus::Distance(14.0),
+            35.0,
+            8,
+            CropFactor::FULL_FORMAT,
+        );
+        let mut sampler = RandomSampler::new(0);
+
+        //every camera ray lands on the pixel it was made for, wherever it starts on the lens
+        for &(x, y) in &[(0.5, 0.5), (40.0, 30.0), (79.9, 12.25), (3.0, 59.5)] {
+            let ray = camera.get_ray(x, y, &mut sampler);
+            let (raster_x, raster_y) = camera.raster_position(ray.origin, ray.direction).unwrap();
+            assert!((raster_x - x).abs() < 1e-2, "{} != {}", raster_x, x);
+            assert!((raster_y - y).abs() < 1e-2, "{} != {}", raster_y, y);
+        }
+
+        assert!(camera
+            .raster_position(camera.position, -camera.direction)
+            .is_none());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::camera::Camera;
+use crate::film::{AdaptiveSampling, Film};
+use crate::integrators::integrator::IntegratorKind;
+use crate::math::pdf::MisHeuristic;
+use crate::pathtracer::BounceLimits;
+use crate::sampling::sampler::SamplerKind;
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// first bytes of every checkpoint file
+const MAGIC: &[u8; 4] = b"RTCP";
+/// bump this whenever the layout of `Checkpoint` changes
+const VERSION: u32 = 4;
+
+/// everything that can go wrong while saving or resuming a checkpoint
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    /// the file is not a checkpoint, or from an incompatible version
+    Format(String),
+    /// the checkpoint was made from a different scene
+    SceneMismatch,
+    /// the checkpoint was made with a different camera (position, lens or image size)
+    CameraMismatch,
+    /// the checkpoint was made with a different setting, the name of the first one that differs
+    SettingsMismatch(&'static str),
+    /// only incremental renders accumulate over multiple frames
+    NotIncremental,
+    /// the render learns something over the frames that checkpoints do not save, what that is
+    UnsavedState(&'static str),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "{}", e),
+            CheckpointError::Format(reason) => write!(f, "not a valid checkpoint: {}", reason),
+            CheckpointError::SceneMismatch => write!(f, "checkpoint was made from another scene"),
+            CheckpointError::CameraMismatch => {
+                write!(f, "checkpoint was made with another camera or image size")
+            }
+            CheckpointError::SettingsMismatch(setting) => {
+                write!(f, "checkpoint was made with another {}", setting)
+            }
+            CheckpointError::NotIncremental => {
+                write!(f, "checkpoints can only be resumed in incremental mode")
+            }
+            CheckpointError::UnsavedState(state) => {
+                write!(
+                    f,
+                    "checkpoints do not save {}, they can not be resumed",
+                    state
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for CheckpointError {
+    fn from(e: bincode::Error) -> Self {
+        CheckpointError::Format(e.to_string())
+    }
+}
+
+/// the settings of a render that change what a frame adds to the image,
+/// frames only add up if they were all rendered with the same ones
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenderSettings {
+    /// samples per pixel and frame
+    pub samples: u32,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub bounce_limits: BounceLimits,
+    pub roulette_depth: u32,
+    pub mis: MisHeuristic,
+    pub integrator: IntegratorKind,
+    pub photon_count: u32,
+    pub photon_radius: f32,
+    pub spectral: bool,
+    pub guiding: bool,
+}
+
+impl RenderSettings {
+    /// the name of the first setting that differs from `other`, none if they are all the same
+    pub fn difference(&self, other: &RenderSettings) -> Option<&'static str> {
+        let differences = [
+            (self.samples != other.samples, "number of samples"),
+            (self.adaptive != other.adaptive, "adaptive sampling"),
+            (self.seed != other.seed, "seed"),
+            (self.sampler != other.sampler, "sampler"),
+            (self.bounce_limits != other.bounce_limits, "bounce limit"),
+            (
+                self.roulette_depth != other.roulette_depth,
+                "roulette depth",
+            ),
+            (self.mis != other.mis, "mis heuristic"),
+            (self.integrator != other.integrator, "integrator"),
+            (self.photon_count != other.photon_count, "number of photons"),
+            (self.photon_radius != other.photon_radius, "photon radius"),
+            (self.spectral != other.spectral, "spectral mode"),
+            (self.guiding != other.guiding, "path guiding"),
+        ];
+        differences
+            .iter()
+            .find(|(differs, _)| *differs)
+            .map(|(_, setting)| *setting)
+    }
+
+    /// what a render with these settings learns over the frames that is not in a checkpoint,
+    /// none if it can be resumed
+    pub fn unsaved_state(&self) -> Option<&'static str> {
+        match self.integrator {
+            IntegratorKind::Path if self.guiding => Some("the path guide"),
+            IntegratorKind::Metropolis => Some("the Metropolis chains"),
+            _ => None,
+        }
+    }
+}
+
+/// the state of an incremental render, enough to continue accumulating later
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// the next frame that will be rendered, the buffers contain `frame - 1` frames
+    pub frame: u32,
+    /// see `hash_scene`
+    pub scene_hash: u64,
+    pub camera: Camera,
+    pub settings: RenderSettings,
+    pub color_buffer: Vec<f32>,
+    pub albedo_buffer: Vec<f32>,
+    pub normal_buffer: Vec<f32>,
+    pub depth_buffer: Vec<f32>,
+    /// the per pixel sample counts the buffers were averaged with
+    pub film: Film,
+}
+
+impl Checkpoint {
+    /// writes the checkpoint to a temporary file first and then replaces `path`,
+    /// so crashing while saving never destroys the previous checkpoint
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CheckpointError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(CheckpointError::Format("wrong file type".to_string()));
+        }
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != VERSION {
+            return Err(CheckpointError::Format(format!(
+                "version {} is not supported",
+                version
+            )));
+        }
+
+        let checkpoint: Checkpoint = bincode::deserialize_from(reader)?;
+
+        let size = (checkpoint.camera.width() * checkpoint.camera.height() * 3) as usize;
+        let buffers = [
+            &checkpoint.color_buffer,
+            &checkpoint.albedo_buffer,
+            &checkpoint.normal_buffer,
+            &checkpoint.depth_buffer,
+        ];
+        let film_matches = checkpoint
+            .film
+            .has_size(checkpoint.camera.width(), checkpoint.camera.height());
+        if buffers.iter().any(|buffer| buffer.len() != size) || !film_matches {
+            return Err(CheckpointError::Format(
+                "buffer size does not match the image size".to_string(),
+            ));
+        }
+        if checkpoint.frame < 2 {
+            return Err(CheckpointError::Format("no frame was rendered".to_string()));
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// makes sure the checkpoint belongs to the scene, camera and settings we are about to render
+    pub fn check_compatible(
+        &self,
+        scene_hash: u64,
+        camera: &Camera,
+        settings: &RenderSettings,
+    ) -> Result<(), CheckpointError> {
+        if self.scene_hash != scene_hash {
+            return Err(CheckpointError::SceneMismatch);
+        }
+        if self.camera != *camera {
+            return Err(CheckpointError::CameraMismatch);
+        }
+        if let Some(setting) = self.settings.difference(settings) {
+            return Err(CheckpointError::SettingsMismatch(setting));
+        }
+        //the frames after the checkpoint would start learning from scratch
+        if let Some(state) = settings.unsaved_state() {
+            return Err(CheckpointError::UnsavedState(state));
+        }
+        Ok(())
+    }
+}
+
+/// 64 bit FNV-1a hash of the scene source
+/// std's `DefaultHasher` may change between rust versions, this does not
+pub fn hash_scene(source: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+    extend_hash(OFFSET_BASIS, source)
+}
+
+/// continues `hash_scene` with more bytes, e.g. the files the scene refers to
+pub fn extend_hash(hash: u64, bytes: &[u8]) -> u64 {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    bytes
+        .iter()
+        .fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::vec3::Vec3;
+
+    fn settings() -> RenderSettings {
+        RenderSettings {
+            samples: 4,
+            adaptive: None,
+            seed: 7,
+            sampler: SamplerKind::default(),
+            bounce_limits: BounceLimits::new(8),
+            roulette_depth: 3,
+            mis: MisHeuristic::default(),
+            integrator: IntegratorKind::Path,
+            photon_count: 1000,
+            photon_radius: 0.1,
+            spectral: false,
+            guiding: false,
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera::new_virtual(
+            Vec3::new(0.0, 1.0, -4.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            60.0,
+            4,
+            2,
+        )
+    }
+
+    fn checkpoint(settings: RenderSettings) -> Checkpoint {
+        let buffer = |offset: f32| (0..4 * 2 * 3).map(|i| i as f32 + offset).collect();
+        Checkpoint {
+            frame: 3,
+            scene_hash: hash_scene(b"scene"),
+            camera: camera(),
+            settings,
+            color_buffer: buffer(0.5),
+            albedo_buffer: buffer(1.0),
+            normal_buffer: buffer(2.0),
+            depth_buffer: buffer(3.0),
+            film: Film::new(4, 2),
+        }
+    }
+
+    /// a path in the temp directory no other test uses
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "raytrace-{}-{}.checkpoint",
+            std::process::id(),
+            name
+        ))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_path("round-trip");
+        let saved = checkpoint(settings());
+        saved.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.frame, saved.frame);
+        assert_eq!(loaded.scene_hash, saved.scene_hash);
+        assert!(loaded.camera == saved.camera);
+        assert_eq!(loaded.settings, saved.settings);
+        assert_eq!(loaded.color_buffer, saved.color_buffer);
+        assert_eq!(loaded.albedo_buffer, saved.albedo_buffer);
+        assert_eq!(loaded.normal_buffer, saved.normal_buffer);
+        assert_eq!(loaded.depth_buffer, saved.depth_buffer);
+        assert!(loaded.film == saved.film);
+        assert!(loaded
+            .check_compatible(hash_scene(b"scene"), &camera(), &settings())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let checkpoint = checkpoint(settings());
+        let error = |scene: &[u8], settings: &RenderSettings| {
+            checkpoint
+                .check_compatible(hash_scene(scene), &camera(), settings)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error(b"another scene", &settings()),
+            "checkpoint was made from another scene"
+        );
+        let mut other = settings();
+        other.seed = 8;
+        assert_eq!(
+            error(b"scene", &other),
+            "checkpoint was made with another seed"
+        );
+        other = settings();
+        other.integrator = IntegratorKind::Bidirectional;
+        assert_eq!(
+            error(b"scene", &other),
+            "checkpoint was made with another integrator"
+        );
+    }
+
+    #[test]
+    fn test_unsaved_state() {
+        let mut guided = settings();
+        guided.guiding = true;
+        let mut metropolis = settings();
+        metropolis.integrator = IntegratorKind::Metropolis;
+
+        for (settings, state) in [
+            (guided, "the path guide"),
+            (metropolis, "the Metropolis chains"),
+        ] {
+            let error = checkpoint(settings.clone())
+                .check_compatible(hash_scene(b"scene"), &camera(), &settings)
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("checkpoints do not save {}, they can not be resumed", state)
+            );
+        }
+    }
+
+    #[test]
+    fn test_older_version() {
+        let path = temp_path("older-version");
+        checkpoint(settings()).save(&path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[4..8].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        let loaded = Checkpoint::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            loaded.err().unwrap().to_string(),
+            format!(
+                "not a valid checkpoint: version {} is not supported",
+                VERSION - 1
+            )
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use crate::integrators::integrator::IntegratorKind;
+use crate::math::pdf::MisHeuristic;
+use crate::output::Aov;
+use crate::sampling::sampler::SamplerKind;
+use crate::tiles::TileOrder;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    name = "raytrace",
+    version,
+    about = "A path tracer",
+    disable_help_subcommand = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Render the scene in a window, the camera can be moved while rendering
+    Preview(PreviewArgs),
+    /// Render the scene without a window and write the image to a file
+    Render(RenderArgs),
+    /// Load the scene and print statistics about it
+    Info(InfoArgs),
+    /// Render the scene a few times and report how fast that was
+    Bench(BenchArgs),
+}
+
+/// what to render, shared by all subcommands that render
+/// the values override the settings of the scene file
+#[derive(Args)]
+pub struct SceneArgs {
+    /// Load the scene from a scene file instead of rendering the built-in scene
+    #[arg(long, value_name = "FILE")]
+    pub scene: Option<PathBuf>,
+    /// Image width in pixels
+    #[arg(short = 'W', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
+    /// Image height in pixels
+    #[arg(short = 'H', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
+    /// Samples per pixel
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: Option<u32>,
+    /// Seed for the random numbers, the same seed always renders the same image
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// random, stratified, halton, sobol or blue_noise
+    #[arg(long, value_name = "NAME")]
+    pub sampler: Option<SamplerKind>,
+    /// Paths end after this many bounces
+    #[arg(long, value_name = "BOUNCES")]
+    pub max_depth: Option<u32>,
+    /// Paths end after this many bounces off diffuse materials
+    #[arg(long, value_name = "BOUNCES")]
+    pub max_diffuse_depth: Option<u32>,
+    /// Paths end after this many bounces off glossy materials
+    #[arg(long, value_name = "BOUNCES")]
+    pub max_glossy_depth: Option<u32>,
+    /// Paths end after going through transparent materials this many times
+    #[arg(long, value_name = "BOUNCES")]
+    pub max_transmission_depth: Option<u32>,
+    /// Paths end after scattering this many times inside volumes
+    #[arg(long, value_name = "BOUNCES")]
+    pub max_volume_depth: Option<u32>,
+    /// Paths with little throughput may randomly end after this many bounces,
+    /// a value of at least --max-depth turns that off
+    #[arg(long, value_name = "BOUNCES")]
+    pub roulette_depth: Option<u32>,
+    /// path, bidirectional, metropolis, photon_mapping, direct, ambient_occlusion, albedo, normal
+    /// or bvh_heatmap
+    #[arg(long, value_name = "NAME")]
+    pub integrator: Option<IntegratorKind>,
+    /// Number of photons every frame emits with photon mapping
+    #[arg(long, value_name = "COUNT")]
+    pub photons: Option<u32>,
+    /// Distance photons are gathered from in the first frame, it shrinks over the frames
+    #[arg(long, value_name = "DISTANCE")]
+    pub photon_radius: Option<f32>,
+    /// Trace wavelengths instead of rgb colors, the scene file can turn this on too
+    #[arg(long)]
+    pub spectral: bool,
+    /// Guide the paths towards where light arrived from in earlier frames, only the path
+    /// integrator learns that; needs incremental frames, the scene file can turn this on too
+    #[arg(long)]
+    pub guiding: bool,
+    /// balance or power, how light sampling and material sampling are combined
+    #[arg(long, value_name = "HEURISTIC")]
+    pub mis: Option<MisHeuristic>,
+    /// Number of render threads, all cores if missing
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+}
+
+/// how the samples are distributed over the image
+#[derive(Args)]
+pub struct SamplingArgs {
+    /// Stop sampling pixels once their relative error is below THRESHOLD, e.g. 0.01
+    #[arg(long, value_name = "THRESHOLD")]
+    pub adaptive: Option<f32>,
+    /// Samples before a pixel may stop (with --adaptive)
+    #[arg(long, default_value_t = 16, value_name = "NUMBER")]
+    pub min_samples: u32,
+    /// Samples after which a pixel stops (with --adaptive)
+    #[arg(long, default_value_t = 1024, value_name = "NUMBER")]
+    pub max_samples: u32,
+    /// Edge length of the render tiles in pixels
+    #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_size: u32,
+    /// scanline, spiral or hilbert
+    #[arg(long, default_value = "spiral", value_name = "ORDER")]
+    pub tile_order: TileOrder,
+}
+
+/// when to stop a progressive render, and how to survive an interrupted one
+#[derive(Args)]
+pub struct ProgressiveArgs {
+    /// Render progressively until TIME has passed, in seconds or with a unit, e.g. 90s, 10m or 2h
+    #[arg(long, value_name = "TIME", value_parser = parse_duration)]
+    pub time_budget: Option<Duration>,
+    /// Render progressively until the estimated relative error is below ERROR, e.g. 0.01
+    #[arg(long, value_name = "ERROR")]
+    pub noise_target: Option<f32>,
+    /// Periodically save the accumulated frames of a progressive render
+    #[arg(long, value_name = "FILE")]
+    pub checkpoint: Option<PathBuf>,
+    /// Seconds between two checkpoints
+    #[arg(long, default_value_t = 60, value_name = "SECONDS")]
+    pub checkpoint_interval: u64,
+    /// Continue from a checkpoint, keeps saving checkpoints to FILE unless --checkpoint is given
+    #[arg(long, value_name = "FILE")]
+    pub resume: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct PreviewArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+    #[command(flatten)]
+    pub sampling: SamplingArgs,
+    #[command(flatten)]
+    pub progressive: ProgressiveArgs,
+    /// Render one sample per frame and average the frames
+    #[arg(short, long)]
+    pub incremental: bool,
+    /// Show the noisy image instead of the denoised one
+    #[arg(long)]
+    pub no_denoise: bool,
+}
+
+#[derive(Args)]
+pub struct RenderArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+    #[command(flatten)]
+    pub sampling: SamplingArgs,
+    #[command(flatten)]
+    pub progressive: ProgressiveArgs,
+    /// The image to write, the format is chosen by the extension
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+    /// Write the noisy image instead of the denoised one
+    #[arg(long)]
+    pub no_denoise: bool,
+    /// Also write these of color, albedo, normal and depth,
+    /// as layers for .exr, as separate files otherwise
+    #[arg(long, value_name = "AOVS", value_delimiter = ',')]
+    pub aov: Vec<Aov>,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+    #[command(flatten)]
+    pub sampling: SamplingArgs,
+    /// How many frames to render
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    pub frames: u32,
+}
+
+/// parses a duration in seconds, or with a unit: s, m or h
+fn parse_duration(string: &str) -> Result<Duration, String> {
+    let (number, unit) = match string.find(|c: char| c.is_alphabetic()) {
+        Some(index) => string.split_at(index),
+        None => (string, "s"),
+    };
+    let factor = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 60.0 * 60.0,
+        _ => return Err(format!("unknown time unit '{}', use s, m or h", unit)),
+    };
+
+    match number.parse::<f64>() {
+        Ok(value) if value >= 0.0 => Ok(Duration::from_secs_f64(value * factor)),
+        _ => Err(format!("'{}' is not a duration", string)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from([
+            "raytrace",
+            "render",
+            "-o",
+            "out.exr",
+            "-W",
+            "64",
+            "-H",
+            "48",
+            "--aov",
+            "albedo,depth",
+            "--time-budget",
+            "2m",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Render(args) => {
+                assert_eq!(args.scene.width, Some(64));
+                assert_eq!(args.aov, vec![Aov::Albedo, Aov::Depth]);
+                assert_eq!(args.progressive.time_budget, Some(Duration::from_secs(120)));
+            }
+            _ => panic!("expected the render command"),
+        }
+
+        //unknown flags are errors, not ignored
+        assert!(Cli::try_parse_from(["raytrace", "preview", "--bogus"]).is_err());
+    }
+}
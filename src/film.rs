@@ -0,0 +1,237 @@
+use crate::math::vec3::Vec3;
+use crate::tiles::Tile;
+use serde::{Deserialize, Serialize};
+
+/// luminances below this count as this dark when estimating the relative error,
+/// else pixels that are almost black would never converge
+const MIN_LUMINANCE: f32 = 0.01;
+
+/// running statistics over the luminance of all samples of a pixel (Welford's algorithm)
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PixelStats {
+    /// how many samples were taken
+    pub samples: u32,
+    mean: f32,
+    /// sum of squared differences from the mean
+    m2: f32,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, luminance: f32) {
+        self.samples += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.samples as f32;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    /// sample variance of the luminance
+    pub fn variance(&self) -> f32 {
+        if self.samples < 2 {
+            return f32::INFINITY;
+        }
+        self.m2 / (self.samples - 1) as f32
+    }
+
+    /// standard error of the mean, relative to the mean
+    pub fn relative_error(&self) -> f32 {
+        (self.variance() / self.samples as f32).sqrt() / self.mean.max(MIN_LUMINANCE)
+    }
+}
+
+/// keeps sampling a pixel only while its estimated error is above a threshold
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveSampling {
+    /// every pixel gets at least this many samples, the error estimate is unreliable before
+    pub min_samples: u32,
+    /// no pixel gets more than this many samples, even if it is still noisy
+    pub max_samples: u32,
+    /// the relative error at which a pixel counts as converged
+    pub threshold: f32,
+}
+
+impl AdaptiveSampling {
+    /// true if the pixel does not need any more samples
+    pub fn is_done(&self, stats: &PixelStats) -> bool {
+        if stats.samples >= self.max_samples {
+            return true;
+        }
+        stats.samples >= self.min_samples && stats.relative_error() <= self.threshold
+    }
+}
+
+/// light that reached the camera from a path started at a light
+/// it lands on whichever pixel it hits, not on the pixel that was being sampled
+#[derive(Debug, Copy, Clone)]
+pub struct Splat {
+    pub x: u32,
+    pub y: u32,
+    pub color: Vec3,
+}
+
+/// the sample statistics of every pixel of the image, accumulated over all frames
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<PixelStats>,
+    /// sum of all splats of every pixel, RGB
+    splats: Vec<f32>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        Film {
+            width,
+            height,
+            pixels: vec![PixelStats::default(); (width * height) as usize],
+            splats: vec![0.0; (width * height * 3) as usize],
+        }
+    }
+
+    /// true if the film belongs to an image of the given size
+    pub fn has_size(&self, width: u32, height: u32) -> bool {
+        self.width == width
+            && self.height == height
+            && self.pixels.len() == (width * height) as usize
+            && self.splats.len() == (width * height * 3) as usize
+    }
+
+    /// forgets all samples, e.g. because the camera moved
+    pub fn clear(&mut self) {
+        for stats in self.pixels.iter_mut() {
+            *stats = PixelStats::default();
+        }
+        for splat in self.splats.iter_mut() {
+            *splat = 0.0;
+        }
+    }
+
+    pub fn add_splat(&mut self, splat: &Splat) {
+        let index = ((splat.y * self.width + splat.x) * 3) as usize;
+        self.splats[index] += splat.color.x;
+        self.splats[index + 1] += splat.color.y;
+        self.splats[index + 2] += splat.color.z;
+    }
+
+    /// the image: the averaged samples of `color` plus the splats
+    /// every sample may splat onto any pixel, so the splats are averaged over all samples of the image
+    pub fn with_splats(&self, color: &[f32]) -> Vec<f32> {
+        let total_samples = self.total_samples();
+        if total_samples == 0 {
+            return color.to_vec();
+        }
+        let scale = self.pixel_count() as f32 / total_samples as f32;
+        color
+            .iter()
+            .zip(self.splats.iter())
+            .map(|(color, splat)| color + splat * scale)
+            .collect()
+    }
+
+    /// copies the statistics of the pixels inside `tile`, row by row
+    pub fn tile_stats(&self, tile: &Tile) -> Vec<PixelStats> {
+        let mut stats = Vec::with_capacity(tile.pixel_count());
+        for y in tile.y..tile.y + tile.height {
+            let start = (y * self.width + tile.x) as usize;
+            stats.extend_from_slice(&self.pixels[start..start + tile.width as usize]);
+        }
+        stats
+    }
+
+    /// replaces the statistics of the pixels inside `tile`, the inverse of `tile_stats`
+    pub fn store_tile(&mut self, tile: &Tile, stats: &[PixelStats]) {
+        for (row, tile_row) in stats.chunks(tile.width as usize).enumerate() {
+            let start = ((tile.y + row as u32) * self.width + tile.x) as usize;
+            self.pixels[start..start + tile.width as usize].copy_from_slice(tile_row);
+        }
+    }
+
+    pub fn pixel_count(&self) -> usize {
+        self.pixels.len()
+    }
+
+    /// the number of samples of all pixels together
+    pub fn total_samples(&self) -> u64 {
+        self.pixels.iter().map(|stats| stats.samples as u64).sum()
+    }
+
+    /// estimated relative error of the whole image, the average over all pixels
+    /// infinite as long as any pixel has less than 2 samples
+    pub fn relative_error(&self) -> f32 {
+        let sum: f64 = self
+            .pixels
+            .iter()
+            .map(|stats| stats.relative_error() as f64)
+            .sum();
+        (sum / self.pixels.len().max(1) as f64) as f32
+    }
+
+    /// the highest sample count of any pixel
+    pub fn max_samples(&self) -> u32 {
+        self.pixels
+            .iter()
+            .map(|stats| stats.samples)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// RGB image of the sample count of every pixel, see `heatmap`
+    pub fn sample_heatmap(&self, max_samples: u32) -> Vec<f32> {
+        heatmap(&self.pixels, max_samples)
+    }
+}
+
+/// maps sample counts to colors, from blue (no samples) to red (`max_samples`)
+pub fn heatmap(stats: &[PixelStats], max_samples: u32) -> Vec<f32> {
+    stats
+        .iter()
+        .flat_map(|stats| {
+            let color = heat_color(stats.samples as f32 / max_samples.max(1) as f32);
+            vec![color.x, color.y, color.z]
+        })
+        .collect()
+}
+
+/// maps [0, 1] to blue, green and red, larger values are red too
+pub fn heat_color(t: f32) -> Vec3 {
+    let blue = Vec3::new(0.0, 0.0, 1.0);
+    let green = Vec3::new(0.0, 1.0, 0.0);
+    let red = Vec3::new(1.0, 0.0, 0.0);
+
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 {
+        Vec3::lerp(blue, green, 2.0 * t)
+    } else {
+        Vec3::lerp(green, red, 2.0 * t - 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_stats() {
+        let mut stats = PixelStats::default();
+        for value in &[1.0, 2.0, 3.0, 4.0] {
+            stats.add(*value);
+        }
+        assert_eq!(stats.samples, 4);
+        assert!((stats.mean - 2.5).abs() < 1e-6);
+        assert!((stats.variance() - 5.0 / 3.0).abs() < 1e-6);
+
+        //a constant pixel converges as soon as the minimum is reached
+        let adaptive = AdaptiveSampling {
+            min_samples: 4,
+            max_samples: 16,
+            threshold: 0.01,
+        };
+        let mut constant = PixelStats::default();
+        for _ in 0..3 {
+            constant.add(0.5);
+            assert!(!adaptive.is_done(&constant));
+        }
+        constant.add(0.5);
+        assert!(adaptive.is_done(&constant));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::math::onb::ONB;
+use crate::math::vec3::Vec3;
+use crate::sampling::sampler::Sampler;
+use std::ops::BitOr;
+
+/// what the lobes of a bsdf do, as flags that can be combined
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lobes(u8);
+
+impl Lobes {
+    /// back to the side the light arrives from
+    pub const REFLECTION: Lobes = Lobes(1);
+    /// through the surface
+    pub const TRANSMISSION: Lobes = Lobes(1 << 1);
+    /// into all directions, like paper
+    pub const DIFFUSE: Lobes = Lobes(1 << 2);
+    /// around a single direction, like brushed metal
+    pub const GLOSSY: Lobes = Lobes(1 << 3);
+    /// into a single direction, like a mirror
+    /// its density is a dirac delta: only `sample` finds that direction, `eval` and `pdf` are 0
+    pub const SPECULAR: Lobes = Lobes(1 << 4);
+
+    /// true if all flags of `other` are set
+    pub fn contains(self, other: Lobes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// true if any flag of `other` is set
+    pub fn intersects(self, other: Lobes) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// true if there are only specular lobes, connecting anything else to them is pointless
+    pub fn is_specular(self) -> bool {
+        self.contains(Lobes::SPECULAR) && !self.intersects(Lobes::DIFFUSE | Lobes::GLOSSY)
+    }
+}
+
+impl BitOr for Lobes {
+    type Output = Lobes;
+
+    fn bitor(self, rhs: Lobes) -> Lobes {
+        Lobes(self.0 | rhs.0)
+    }
+}
+
+/// a direction picked by a bsdf
+#[derive(Debug, Copy, Clone)]
+pub struct BsdfSample {
+    /// where the light arrives from
+    pub wi: Vec3,
+    /// the bsdf times the cosine of `wi`, like `eval`
+    pub f: Vec3,
+    /// the density `wi` was picked with
+    /// for specular lobes it is the probability of picking the lobe, `f` is scaled by it too
+    pub pdf: f32,
+    /// the lobe that was picked
+    pub lobes: Lobes,
+}
+
+/// how light scatters at a point, in its local shading frame where the normal is z
+/// `wo` is the direction the light leaves into, towards the eye; `wi` where it arrives from.
+/// both point away from the surface
+pub trait Bxdf {
+    /// the bsdf times the cosine of `wi` with the normal, for volumes just the phase function
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3;
+
+    /// picks `wi` for `wo`, directions that contribute more are more likely
+    /// None if the light is absorbed
+    fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample>;
+
+    /// the density with which `sample` picks `wi` for `wo`
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32;
+
+    /// all lobes that `sample` can pick
+    fn lobes(&self) -> Lobes;
+}
+
+/// the bxdf of a point, with the shading frame it is in
+/// all directions are in world space
+pub struct Bsdf {
+    frame: ONB,
+    /// the color of the surface, for the denoiser
+    albedo: Vec3,
+    bxdf: Box<dyn Bxdf>,
+    /// it scatters each wavelength differently, the path only holds for the hero wavelength
+    dispersive: bool,
+}
+
+impl Bsdf {
+    /// `normal` is the shading normal
+    pub fn new(normal: Vec3, albedo: Vec3, bxdf: Box<dyn Bxdf>) -> Self {
+        Bsdf {
+            frame: ONB::from_w(normal.normalised()),
+            albedo,
+            bxdf,
+            dispersive: false,
+        }
+    }
+
+    /// the same bsdf, scattering each wavelength differently
+    pub fn with_dispersion(mut self) -> Self {
+        self.dispersive = true;
+        self
+    }
+
+    /// `a` and `b` blended by `weight`, 0 is all `a` and 1 all `b`
+    /// either can be None for a part that absorbs all light, like a light
+    pub fn mix(a: Option<Bsdf>, b: Option<Bsdf>, weight: f32) -> Option<Bsdf> {
+        let weight = weight.clamp(0.0, 1.0);
+        if weight <= 0.0 {
+            return a;
+        }
+        if weight >= 1.0 {
+            return b;
+        }
+
+        //the frame of the bigger part, for anything that needs a single normal
+        let bigger = if weight < 0.5 {
+            a.as_ref().or(b.as_ref())
+        } else {
+            b.as_ref().or(a.as_ref())
+        };
+        let normal = bigger?.normal();
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let albedo = |bsdf: &Option<Bsdf>| bsdf.as_ref().map_or(black, Bsdf::albedo);
+        let albedo = Vec3::lerp(albedo(&a), albedo(&b), weight);
+        let dispersive = [&a, &b]
+            .iter()
+            .any(|bsdf| bsdf.as_ref().is_some_and(Bsdf::is_dispersive));
+
+        let mut bxdf = BxdfSum::default();
+        for (bsdf, weight) in [(a, 1.0 - weight), (b, weight)] {
+            if let Some(bsdf) = bsdf {
+                let frame = ONB::from_w(normal.normalised());
+                bxdf.add(weight, weight, Box::new(Reframed { frame, bsdf }));
+            }
+        }
+        let mixed = Bsdf::new(normal, albedo, Box::new(bxdf));
+        Some(if dispersive {
+            mixed.with_dispersion()
+        } else {
+            mixed
+        })
+    }
+
+    /// the shading normal
+    pub fn normal(&self) -> Vec3 {
+        self.frame.w
+    }
+
+    /// the color of the surface, without any lighting
+    pub fn albedo(&self) -> Vec3 {
+        self.albedo
+    }
+
+    /// true if it scatters each wavelength differently
+    pub fn is_dispersive(&self) -> bool {
+        self.dispersive
+    }
+
+    /// see `Bxdf::lobes`
+    pub fn lobes(&self) -> Lobes {
+        self.bxdf.lobes()
+    }
+
+    /// see `Bxdf::eval`
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.bxdf.eval(self.local(wo), self.local(wi))
+    }
+
+    /// see `Bxdf::sample`
+    pub fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let sample = self.bxdf.sample(self.local(wo), sampler)?;
+        if sample.pdf <= 0.0 || sample.f.max_component() <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi: self.frame.to_local(sample.wi).normalised(),
+            ..sample
+        })
+    }
+
+    /// see `Bxdf::pdf`
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        self.bxdf.pdf(self.local(wo), self.local(wi))
+    }
+
+    /// `direction` in the shading frame
+    fn local(&self, direction: Vec3) -> Vec3 {
+        self.frame.coordinates(direction.normalised())
+    }
+}
+
+/// several bxdfs whose light adds up, each scaled by a weight
+/// `sample` picks one of them as likely as its probability says, relative to the others
+#[derive(Default)]
+pub struct BxdfSum {
+    /// weight, probability and the bxdf
+    parts: Vec<(f32, f32, Box<dyn Bxdf>)>,
+}
+
+impl BxdfSum {
+    /// adds `bxdf` scaled by `weight`, left out if it or `probability` is 0
+    pub fn add(&mut self, weight: f32, probability: f32, bxdf: Box<dyn Bxdf>) {
+        if weight > 0.0 && probability > 0.0 {
+            self.parts.push((weight, probability, bxdf));
+        }
+    }
+
+    fn total_probability(&self) -> f32 {
+        self.parts
+            .iter()
+            .map(|(_, probability, _)| probability)
+            .sum()
+    }
+}
+
+impl Bxdf for BxdfSum {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.parts
+            .iter()
+            .fold(Vec3::new(0.0, 0.0, 0.0), |f, (weight, _, bxdf)| {
+                f + *weight * bxdf.eval(wo, wi)
+            })
+    }
+
+    fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let total = self.total_probability();
+        let mut u = sampler.next_1d() * total;
+        let (weight, probability, bxdf) = self
+            .parts
+            .iter()
+            .find(|(_, probability, _)| {
+                u -= probability;
+                u < 0.0
+            })
+            .or(self.parts.last())?;
+
+        let sample = bxdf.sample(wo, sampler)?;
+        //no other lobe can find the single direction of a specular one
+        if sample.lobes.is_specular() {
+            return Some(BsdfSample {
+                f: *weight * sample.f,
+                pdf: probability / total * sample.pdf,
+                ..sample
+            });
+        }
+        Some(BsdfSample {
+            f: self.eval(wo, sample.wi),
+            pdf: self.pdf(wo, sample.wi),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let total = self.total_probability();
+        self.parts
+            .iter()
+            .map(|(_, probability, bxdf)| probability / total * bxdf.pdf(wo, wi))
+            .sum()
+    }
+
+    fn lobes(&self) -> Lobes {
+        self.parts
+            .iter()
+            .fold(Lobes(0), |lobes, (_, _, bxdf)| lobes | bxdf.lobes())
+    }
+}
+
+/// a bsdf seen from another shading frame, so bsdfs with different normals can be combined
+struct Reframed {
+    frame: ONB,
+    bsdf: Bsdf,
+}
+
+impl Bxdf for Reframed {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.bsdf
+            .eval(self.frame.to_local(wo), self.frame.to_local(wi))
+    }
+
+    fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let sample = self.bsdf.sample(self.frame.to_local(wo), sampler)?;
+        Some(BsdfSample {
+            wi: self.frame.coordinates(sample.wi),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        self.bsdf
+            .pdf(self.frame.to_local(wo), self.frame.to_local(wi))
+    }
+
+    fn lobes(&self) -> Lobes {
+        self.bsdf.lobes()
+    }
+}
@@ -0,0 +1,120 @@
+use crate::gfx::texture::Texture;
+use crate::math::distribution::Distribution2D;
+use crate::math::vec3::Vec3;
+use crate::sampling::sampler::Sampler;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// how finely textures without a resolution of their own are sampled for the distribution
+const DEFAULT_RESOLUTION: (usize, usize) = (64, 32);
+
+/// the sky: an equirectangular texture all around the scene
+/// bright parts of it, like the sun, are sampled directly as lights
+#[derive(Clone)]
+pub struct Environment {
+    texture: Arc<dyn Texture>,
+    /// texels as likely as their luminance, None if the sky is black
+    distribution: Option<Arc<Distribution2D>>,
+}
+
+impl Environment {
+    pub fn new(texture: Arc<dyn Texture>) -> Self {
+        let (width, height) = texture.resolution().unwrap_or(DEFAULT_RESOLUTION);
+
+        //the rows near the poles cover less of the sphere
+        let mut function = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let v = (y as f32 + 0.5) / height as f32;
+            let sin_theta = (PI * v).sin();
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let luminance = texture.texture((u, v)).luminance().max(0.0);
+                function.push(luminance * sin_theta);
+            }
+        }
+
+        let distribution = Distribution2D::new(&function, width, height);
+        Environment {
+            texture,
+            distribution: if distribution.integral() > 0.0 {
+                Some(Arc::new(distribution))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// the color of the sky in `direction`
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        self.texture.texture(to_uv(direction))
+    }
+
+    /// true if the sky emits any light that can be sampled
+    pub fn can_sample(&self) -> bool {
+        self.distribution.is_some()
+    }
+
+    /// picks a direction, brighter parts of the sky more likely
+    /// # Return Value
+    /// Returns Tuple of (direction, density per solid angle), None if the sky is black
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> Option<(Vec3, f32)> {
+        let distribution = self.distribution.as_ref()?;
+        let ((u, v), pdf) = distribution.sample(sampler.next_2d());
+        let sin_theta = (PI * v).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some((from_uv((u, v)), pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    /// the density per solid angle with which `sample` picks `direction`
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 0.0,
+        };
+        let (u, v) = to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        //the texture is stretched over the sphere, by 2 pi horizontally and by pi vertically
+        distribution.pdf((u, v)) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+/// where the sky texture is looked up for `direction`
+fn to_uv(direction: Vec3) -> (f32, f32) {
+    let direction = direction.normalised();
+    let x = direction.x;
+    let z = direction.z;
+    let u = 1.0 - ((z.atan2(x) + PI) / (2.0 * PI));
+
+    //clamp to [-1, 1] just in case (asin might return nan)
+    let y = -direction.y.clamp(-1.0, 1.0);
+    let v = (y.asin() + std::f32::consts::FRAC_PI_2) / PI;
+
+    (u, v)
+}
+
+/// the direction the sky texture shows at `uv`, the inverse of `to_uv`
+fn from_uv(uv: (f32, f32)) -> Vec3 {
+    let (u, v) = uv;
+    let phi = (1.0 - u) * 2.0 * PI - PI;
+    let theta = v * PI;
+    let sin_theta = theta.sin();
+    Vec3::new(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uv_round_trip() {
+        for &(u, v) in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.3, 0.95)] {
+            let (u2, v2) = to_uv(from_uv((u, v)));
+            assert!((u - u2).abs() < 1e-4 && (v - v2).abs() < 1e-4);
+        }
+    }
+}
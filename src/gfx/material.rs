@@ -1,17 +1,15 @@
+use crate::gfx::bsdf::{Bsdf, BsdfSample, Bxdf, BxdfSum, Lobes};
+use crate::gfx::microfacet::Ggx;
+use crate::gfx::spectrum::Wavelengths;
 use crate::gfx::texture::Texture;
 
+use std::f32::consts::PI;
 use std::sync::Arc;
 
 use crate::hit::HitResult;
 use crate::math::onb::ONB;
 use crate::math::vec3::Vec3;
-use crate::ray::Ray;
-
-/*
-TODO: refactor Material to trait?
-
-TODO: refactor the "scatter" method, break it into subfunctions and implement it properly!
-*/
+use crate::sampling::sampler::Sampler;
 
 /*
     http://www.codinglabs.net/article_physically_based_rendering.aspx
@@ -55,16 +53,35 @@ TODO: refactor the "scatter" method, break it into subfunctions and implement it
 
 */
 
+/// what kind of bounce scattering off a material is, each kind has its own depth limit
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BounceKind {
+    Diffuse,
+    Glossy,
+    Transmission,
+    Volume,
+}
+
 pub trait Material: Send + Sync {
     /// returns color of emitted light
     fn emitted(&self, _hit: &HitResult) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
-    /// returns tuple of (albedo, normal, scattered ray, pdf)
-    fn scattered(&self, _ray: &Ray, hit: &HitResult) -> Option<(Vec3, Vec3, Ray, f32)>;
 
-    /// returns the value of the pdf used for scattering at that position
-    fn scattering_pdf(&self, _ray: &Ray, hit: &HitResult, scattered_ray: &Ray) -> f32;
+    /// how light scatters at `hit`, None if it does not, like on lights
+    /// in spectral mode, the path carries `wavelengths`; a bsdf that scatters each of them
+    /// differently is made for the hero and marked as dispersive
+    fn bsdf(&self, hit: &HitResult, wavelengths: Option<Wavelengths>) -> Option<Bsdf>;
+
+    /// true if `emitted` is not black, objects made of this can be sampled as lights
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// which depth limit scattering off this material at `hit` counts against
+    fn bounce_kind(&self, _hit: &HitResult) -> BounceKind {
+        BounceKind::Diffuse
+    }
 }
 
 fn map_normal(normalmap: Option<&Arc<dyn Texture>>, normal: Vec3, uv_coords: (f32, f32)) -> Vec3 {
@@ -89,6 +106,12 @@ fn fresnel_schlick(refraction: f32, cosine: f32) -> f32 {
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
+/// schlick's approximation for a colored reflectance `f0` at normal incidence, like metals have
+fn fresnel_schlick_color(f0: Vec3, cosine: f32) -> Vec3 {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    f0 + (white - f0) * (1.0 - cosine).max(0.0).powi(5)
+}
+
 /* ========================== */
 
 #[derive(Clone)]
@@ -104,43 +127,59 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scattering_pdf(&self, _ray: &Ray, hit: &HitResult, scattered_ray: &Ray) -> f32 {
+    fn bsdf(&self, hit: &HitResult, _wavelengths: Option<Wavelengths>) -> Option<Bsdf> {
         let uv_coords = hit.uv_coords.unwrap();
         let normal = map_normal(self.normalmap.as_ref(), hit.normal, uv_coords);
-
-        // lambertian scattering pdf is cos(theta)/pi
-
-        let cosine = normal.dot(scattered_ray.direction);
-        if cosine < 0.0 {
-            0.0
-        } else {
-            cosine / std::f32::consts::PI
-        }
+        let albedo = self.albedo.texture(uv_coords);
+        Some(Bsdf::new(
+            normal,
+            albedo,
+            Box::new(LambertianBxdf { albedo }),
+        ))
     }
+}
 
-    fn scattered(&self, _ray: &Ray, hit: &HitResult) -> Option<(Vec3, Vec3, Ray, f32)> {
-        let uv_coords = hit.uv_coords.unwrap();
+/// the same amount of light into every direction above the surface
+struct LambertianBxdf {
+    albedo: Vec3,
+}
 
-        let normal = map_normal(self.normalmap.as_ref(), hit.normal, uv_coords);
+impl Bxdf for LambertianBxdf {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        // lambertian brdf is albedo/pi
+        self.albedo * self.pdf(wo, wi)
+    }
 
-        //lambert
+    fn sample(&self, _wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
         //randomly choose a vector in hemisphere above hit with pdf cos(theta)/pi
         //(choosing in hemisphere would be 1/2pi)
-        let direction = ONB::from_w(normal).to_local(Vec3::random_cosine_direction());
-        let albedo = self.albedo.texture(uv_coords);
-
-        //we generated the direction randomly with cos(t)/pi, so return that as our used pdf
-        let pdf = normal.dot(direction) / std::f32::consts::PI;
+        let wi = Vec3::random_cosine_direction(sampler);
+        let pdf = wi.z / PI;
+        Some(BsdfSample {
+            wi,
+            f: self.albedo * pdf,
+            pdf,
+            lobes: Lobes::DIFFUSE | Lobes::REFLECTION,
+        })
+    }
 
-        let epsilon = normal * 0.001;
-        let scattered = Ray::new(hit.hit_position + epsilon, direction);
+    fn pdf(&self, _wo: Vec3, wi: Vec3) -> f32 {
+        if wi.z < 0.0 {
+            0.0
+        } else {
+            wi.z / PI
+        }
+    }
 
-        Some((albedo, normal, scattered, pdf))
+    fn lobes(&self) -> Lobes {
+        Lobes::DIFFUSE | Lobes::REFLECTION
     }
 }
 
 /* ========================== */
 
+/// a conductor with a GGX microfacet brdf, `metallic` blends its reflectance at normal incidence
+/// from that of a dielectric coating to the albedo
 #[derive(Clone)]
 pub struct Metal {
     albedo: Arc<dyn Texture>,
@@ -166,90 +205,649 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitResult, _scattered_ray: &Ray) -> f32 {
-        0.0
+    fn bsdf(&self, hit: &HitResult, _wavelengths: Option<Wavelengths>) -> Option<Bsdf> {
+        let uv_coords = hit.uv_coords.unwrap();
+        let normal = map_normal(self.normalmap.as_ref(), hit.normal, uv_coords);
+
+        //.x => red channel ; this texture should be grayscale !
+        let metallic = self.metallic.texture(uv_coords).x;
+        let albedo = self.albedo.texture(uv_coords);
+        let f0 = Vec3::lerp(Vec3::new(0.04, 0.04, 0.04), albedo, metallic);
+        let bxdf = MicrofacetReflection {
+            ggx: Ggx::new(self.roughness.texture(uv_coords).x),
+            f0,
+        };
+        Some(Bsdf::new(normal, albedo, Box::new(bxdf)))
+    }
+    fn bounce_kind(&self, _hit: &HitResult) -> BounceKind {
+        BounceKind::Glossy
     }
-    fn scattered(&self, _ray: &Ray, _hit: &HitResult) -> Option<(Vec3, Vec3, Ray, f32)> {
-        /*
-        if let Metal(metal_params) = &self.metallic {
-            //.x => red channel ; this texture should be grayscale !
-            //idea: combine 3 gray textures into 1 with r, g, b channels?
-            let roughness = metal_params.roughness.texture(uv_coords).x;
+}
 
-            let reflected = ray.direction.normalised().reflect(normal)
-                + roughness * Vec3::random_in_unit_sphere();
+/// reflection off the microfacets of a rough conductor
+/// (Walter et al., "Microfacet Models for Refraction through Rough Surfaces", 2007)
+struct MicrofacetReflection {
+    ggx: Ggx,
+    /// the reflectance at normal incidence
+    f0: Vec3,
+}
 
-            //if, for some reason, we reflect *into* the object, absorb the ray
-            //tutorial says this is correct, but leads to black spots around the edge of the sphere :/
-            if reflected.dot(normal) < 0.0 {
-                return None;
-            }
+impl Bxdf for MicrofacetReflection {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let h = (wo + wi).normalised();
+        let fresnel = fresnel_schlick_color(self.f0, wo.dot(h));
+        fresnel * self.ggx.d(h) * self.ggx.g2(wo, wi) / (4.0 * wo.z)
+    }
 
-            //.x => red channel ; this texture should be grayscale !
-            let metallic = metal_params.metallic.texture(uv_coords).x;
-            direction = Vec3::lerp(direction, reflected, metallic);
+    fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
         }
-        */
-        None
+        //reflect off a microfacet the eye sees, if that goes into the surface the ray is absorbed
+        let h = self.ggx.sample_visible_normal(wo, sampler.next_2d());
+        let wi = (-wo).reflect(h);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf: self.pdf(wo, wi),
+            lobes: Lobes::GLOSSY | Lobes::REFLECTION,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        //the density of the visible normal the reflection is around
+        let h = (wo + wi).normalised();
+        let cosine = wo.dot(h);
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        self.ggx.visible_normal_pdf(wo, h) / (4.0 * cosine)
+    }
+
+    fn lobes(&self) -> Lobes {
+        Lobes::GLOSSY | Lobes::REFLECTION
     }
 }
 
 /* ========================== */
 
+/// the wavelength in µm the refractive index of a dielectric is given at, the sodium D line
+const D_LINE: f32 = 0.5893;
+
+/// glass, water and the like: it reflects some of the light and refracts the rest, `albedo` tints
+/// the refracted part. smooth without a roughness texture, else a GGX microfacet surface
+/// with `dispersion`, the refractive index depends on the wavelength and it splits light up
+/// into its colors in spectral mode
 #[derive(Clone)]
 pub struct Dielectric {
     albedo: Arc<dyn Texture>,
     normalmap: Option<Arc<dyn Texture>>,
+    roughness: Option<Arc<dyn Texture>>,
     refractive_index: f32,
+    /// the B of cauchy's equation n = A + B / λ², in µm², 0 for none
+    /// about 0.0042 for crown glass and 0.01 for flint glass
+    dispersion: f32,
 }
 
 impl Dielectric {
     pub fn new(
         albedo: Arc<dyn Texture>,
         normalmap: Option<Arc<dyn Texture>>,
+        roughness: Option<Arc<dyn Texture>>,
         refractive_index: f32,
+        dispersion: f32,
     ) -> Self {
         Self {
             albedo,
             normalmap,
+            roughness,
             refractive_index,
+            dispersion,
         }
     }
+
+    /// the refractive index at `lambda` in nm, `refractive_index` is the one at the D line
+    pub fn refractive_index_at(&self, lambda: f32) -> f32 {
+        let micrometers = lambda / 1000.0;
+        self.refractive_index
+            + self.dispersion * (1.0 / (micrometers * micrometers) - 1.0 / (D_LINE * D_LINE))
+    }
 }
 
 impl Material for Dielectric {
-    fn scattered(&self, _ray: &Ray, _hit: &HitResult) -> Option<(Vec3, Vec3, Ray, f32)> {
-        /*
-        if let Some(refraction_index) = self.refraction {
-            let (refr_normal, n_in, n_out, cosine);
-            if ray.direction.dot(normal) > 0.0 {
-                //object -> air
-                refr_normal = -normal; //outward normal
-                n_in = refraction_index; //object
-                n_out = 1.0; //air
-                cosine = refraction_index * ray.direction.normalised().dot(normal);
-            // why refraction * v·n ?
-            } else {
-                //air -> object
-                refr_normal = normal;
-                n_in = 1.0;
-                n_out = refraction_index;
-                cosine = -ray.direction.normalised().dot(normal); // why negative?
+    fn bsdf(&self, hit: &HitResult, wavelengths: Option<Wavelengths>) -> Option<Bsdf> {
+        let uv_coords = hit.uv_coords.unwrap();
+        let normal = map_normal(self.normalmap.as_ref(), hit.normal, uv_coords);
+        let tint = self.albedo.texture(uv_coords);
+        //each wavelength would refract into its own direction, only the hero is followed
+        let hero = wavelengths.filter(|_| self.dispersion != 0.0);
+        let eta = match hero {
+            Some(wavelengths) => self.refractive_index_at(wavelengths.hero()),
+            None => self.refractive_index,
+        };
+        let bxdf: Box<dyn Bxdf> = match &self.roughness {
+            Some(roughness) => Box::new(RoughDielectric {
+                ggx: Ggx::new(roughness.texture(uv_coords).x),
+                eta,
+                tint,
+            }),
+            None => Box::new(SmoothDielectric { eta, tint }),
+        };
+        let bsdf = Bsdf::new(normal, tint, bxdf);
+        Some(if hero.is_some() {
+            bsdf.with_dispersion()
+        } else {
+            bsdf
+        })
+    }
+    fn bounce_kind(&self, _hit: &HitResult) -> BounceKind {
+        BounceKind::Transmission
+    }
+}
+
+/// the fraction of light that is reflected when it arrives with `cosine` on a surface,
+/// `eta` is the ratio of the refractive index behind it to the one in front of it
+/// 1 if all of it is reflected
+fn dielectric_reflectance(cosine: f32, eta: f32) -> f32 {
+    let sin_out_squared = (1.0 - cosine * cosine).max(0.0) / (eta * eta);
+    if sin_out_squared >= 1.0 {
+        return 1.0;
+    }
+    //schlick's approximation needs the angle in the thinner medium
+    let cos_out = (1.0 - sin_out_squared).sqrt();
+    fresnel_schlick(eta, if eta < 1.0 { cos_out } else { cosine })
+}
+
+/// `wo` and `wi` as seen from the side of `wo`, and the ratio of the refractive index
+/// on the other side to the one on that side
+/// the normal points out of the object, `eta` is its refractive index
+fn facing(wo: Vec3, wi: Vec3, eta: f32) -> (Vec3, Vec3, f32) {
+    let flip = Vec3::new(1.0, 1.0, -1.0);
+    if wo.z < 0.0 {
+        //object -> air
+        (wo * flip, wi * flip, 1.0 / eta)
+    } else {
+        //air -> object
+        (wo, wi, eta)
+    }
+}
+
+/// a smooth surface between air and an object
+/// light is not scaled by the squared ratio of the refractive indices when it crosses the
+/// surface, entering and leaving an object cancel out
+struct SmoothDielectric {
+    eta: f32,
+    tint: Vec3,
+}
+
+impl Bxdf for SmoothDielectric {
+    fn eval(&self, _wo: Vec3, _wi: Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let (wo_facing, _, eta) = facing(wo, wo, self.eta);
+        let side = if wo.z < 0.0 { -1.0 } else { 1.0 };
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+
+        //reflect or refract, as likely as fresnel says
+        let reflectance = dielectric_reflectance(wo_facing.z, eta);
+        if sampler.next_1d() < reflectance {
+            let wi = (-wo_facing).reflect(normal);
+            Some(BsdfSample {
+                wi: Vec3::new(wi.x, wi.y, wi.z * side),
+                f: Vec3::new(reflectance, reflectance, reflectance),
+                pdf: reflectance,
+                lobes: Lobes::SPECULAR | Lobes::REFLECTION,
+            })
+        } else {
+            //total reflection was handled by the reflectance of 1
+            let wi = (-wo_facing).refract(normal, 1.0, eta)?;
+            Some(BsdfSample {
+                wi: Vec3::new(wi.x, wi.y, wi.z * side),
+                f: self.tint * (1.0 - reflectance),
+                pdf: 1.0 - reflectance,
+                lobes: Lobes::SPECULAR | Lobes::TRANSMISSION,
+            })
+        }
+    }
+
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f32 {
+        0.0
+    }
+
+    fn lobes(&self) -> Lobes {
+        Lobes::SPECULAR | Lobes::REFLECTION | Lobes::TRANSMISSION
+    }
+}
+
+/// a rough surface between air and an object, made of microfacets that are smooth dielectrics
+/// (Walter et al., "Microfacet Models for Refraction through Rough Surfaces", 2007)
+struct RoughDielectric {
+    ggx: Ggx,
+    eta: f32,
+    tint: Vec3,
+}
+
+impl RoughDielectric {
+    /// the microfacet that refracts `wo` into `wi`, facing `wo`
+    /// both as seen from the side of `wo`, `wi` below the surface
+    fn refracting_normal(wo: Vec3, wi: Vec3, eta: f32) -> Option<Vec3> {
+        let h = (wo + wi * eta).normalised();
+        let h = if h.z < 0.0 { -h } else { h };
+        if wo.dot(h) <= 0.0 || wi.dot(h) >= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+
+    /// the factor between the densities of the microfacet normal and the refracted direction
+    fn refraction_jacobian(wo: Vec3, wi: Vec3, h: Vec3, eta: f32) -> f32 {
+        let denominator = wi.dot(h) + wo.dot(h) / eta;
+        wi.dot(h).abs() / (denominator * denominator)
+    }
+}
+
+impl Bxdf for RoughDielectric {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let (wo, wi, eta) = facing(wo, wi, self.eta);
+        if wo.z == 0.0 || wi.z == 0.0 {
+            return black;
+        }
+
+        if wi.z > 0.0 {
+            let h = (wo + wi).normalised();
+            let reflectance = dielectric_reflectance(wo.dot(h).max(0.0), eta);
+            let f = reflectance * self.ggx.d(h) * self.ggx.g2(wo, wi) / (4.0 * wo.z);
+            Vec3::new(f, f, f)
+        } else {
+            let h = match Self::refracting_normal(wo, wi, eta) {
+                Some(h) => h,
+                None => return black,
+            };
+            let transmittance = 1.0 - dielectric_reflectance(wo.dot(h), eta);
+            let jacobian = Self::refraction_jacobian(wo, wi, h, eta);
+            self.tint * transmittance * self.ggx.d(h) * self.ggx.g2(wo, wi) * wo.dot(h) * jacobian
+                / wo.z
+        }
+    }
+
+    fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let (wo_facing, _, eta) = facing(wo, wo, self.eta);
+        let side = if wo.z < 0.0 { -1.0 } else { 1.0 };
+        if wo_facing.z == 0.0 {
+            return None;
+        }
+
+        //reflect off or refract through a microfacet the eye sees, as likely as fresnel says
+        let h = self.ggx.sample_visible_normal(wo_facing, sampler.next_2d());
+        let cosine = wo_facing.dot(h);
+        if cosine <= 0.0 {
+            return None;
+        }
+        let reflectance = dielectric_reflectance(cosine, eta);
+        let (wi, lobes) = if sampler.next_1d() < reflectance {
+            let wi = (-wo_facing).reflect(h);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            (wi, Lobes::GLOSSY | Lobes::REFLECTION)
+        } else {
+            let wi = (-wo_facing).refract(h, 1.0, eta)?;
+            if wi.z >= 0.0 {
+                return None;
             }
+            (wi, Lobes::GLOSSY | Lobes::TRANSMISSION)
+        };
 
-            let p = rand::thread_rng().gen_range(0.0, 1.0);
-            if p <= self.fresnel_schlick(cosine) {
-                //total reflection might occur, in that case, don't refract!
-                if let Some(d) = ray.direction.refract(refr_normal, n_in, n_out) {
-                    direction = d;
-                }
+        let wi = Vec3::new(wi.x, wi.y, wi.z * side);
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf: self.pdf(wo, wi),
+            lobes,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let (wo, wi, eta) = facing(wo, wi, self.eta);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        //the density of the visible normal the light was reflected or refracted around
+        if wi.z > 0.0 {
+            let h = (wo + wi).normalised();
+            let cosine = wo.dot(h);
+            if cosine <= 0.0 {
+                return 0.0;
             }
+            let reflectance = dielectric_reflectance(cosine, eta);
+            reflectance * self.ggx.visible_normal_pdf(wo, h) / (4.0 * cosine)
+        } else {
+            let h = match Self::refracting_normal(wo, wi, eta) {
+                Some(h) => h,
+                None => return 0.0,
+            };
+            let transmittance = 1.0 - dielectric_reflectance(wo.dot(h), eta);
+            let jacobian = Self::refraction_jacobian(wo, wi, h, eta);
+            transmittance * self.ggx.visible_normal_pdf(wo, h) * jacobian
         }
-        */
-        None
     }
-    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitResult, _scattered_ray: &Ray) -> f32 {
-        0.0
+
+    fn lobes(&self) -> Lobes {
+        Lobes::GLOSSY | Lobes::REFLECTION | Lobes::TRANSMISSION
+    }
+}
+
+/* ========================== */
+
+/// the roughness of the clearcoat, a thin smooth varnish on top of everything else
+const CLEARCOAT_ROUGHNESS: f32 = 0.1;
+/// the reflectance of the clearcoat at normal incidence, like that of most varnishes
+const CLEARCOAT_F0: f32 = 0.04;
+
+/// the one material for everything, as artists paint it: a dielectric base that is diffuse,
+/// glossy and possibly transmissive, blended into a metal and optionally varnished
+/// every parameter is a grayscale texture from 0 to 1 except the colored `base_color`,
+/// `specular` is the reflectance of the dielectric, 0.5 is the 4% of most materials
+/// (Burley, "Physically Based Shading at Disney", 2012 and "Extending the Disney BRDF to a BSDF
+/// with Integrated Subsurface Scattering", 2015)
+#[derive(Clone)]
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    normalmap: Option<Arc<dyn Texture>>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    specular: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+}
+
+impl Principled {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        base_color: Arc<dyn Texture>,
+        normalmap: Option<Arc<dyn Texture>>,
+        metallic: Arc<dyn Texture>,
+        roughness: Arc<dyn Texture>,
+        specular: Arc<dyn Texture>,
+        sheen: Arc<dyn Texture>,
+        clearcoat: Arc<dyn Texture>,
+        transmission: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            base_color,
+            normalmap,
+            metallic,
+            roughness,
+            specular,
+            sheen,
+            clearcoat,
+            transmission,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn bsdf(&self, hit: &HitResult, _wavelengths: Option<Wavelengths>) -> Option<Bsdf> {
+        let uv_coords = hit.uv_coords.unwrap();
+        let normal = map_normal(self.normalmap.as_ref(), hit.normal, uv_coords);
+
+        let base_color = self.base_color.texture(uv_coords);
+        let metallic = self.metallic.texture(uv_coords).x.clamp(0.0, 1.0);
+        let roughness = self.roughness.texture(uv_coords).x.clamp(0.0, 1.0);
+        let specular = self.specular.texture(uv_coords).x.clamp(0.0, 1.0);
+        let sheen = self.sheen.texture(uv_coords).x.clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.texture(uv_coords).x.clamp(0.0, 1.0);
+        let transmission = self.transmission.texture(uv_coords).x.clamp(0.0, 1.0);
+
+        //metal covers everything, the dielectric below is either diffuse or transmissive
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let dielectric_f0 = (0.08 * specular).max(1.0e-4);
+        let f0 = Vec3::lerp(white * dielectric_f0, base_color, metallic);
+        let eta = (1.0 + dielectric_f0.sqrt()) / (1.0 - dielectric_f0.sqrt());
+
+        //the probabilities roughly follow how much light each lobe scatters
+        //the diffuse base only gets the light the specular layer on top of it does not reflect
+        let mut bxdf = BxdfSum::default();
+        bxdf.add(
+            diffuse_weight,
+            diffuse_weight * base_color.luminance().max(0.1),
+            Box::new(Coated {
+                bxdf: Box::new(PrincipledDiffuse {
+                    base_color,
+                    roughness,
+                    sheen: sheen * Vec3::lerp(white, base_color, 0.5),
+                }),
+                f0: dielectric_f0,
+                coverage: 1.0,
+            }),
+        );
+        bxdf.add(
+            1.0 - transmission_weight,
+            (1.0 - transmission_weight) * (0.25 + 0.75 * metallic),
+            Box::new(MicrofacetReflection {
+                ggx: Ggx::new(roughness),
+                f0,
+            }),
+        );
+        bxdf.add(
+            transmission_weight,
+            transmission_weight,
+            Box::new(RoughDielectric {
+                ggx: Ggx::new(roughness),
+                eta,
+                tint: base_color,
+            }),
+        );
+        if clearcoat <= 0.0 {
+            return Some(Bsdf::new(normal, base_color, Box::new(bxdf)));
+        }
+
+        //the varnish reflects some light, everything else goes through it to the base and back
+        let mut coated = BxdfSum::default();
+        coated.add(
+            clearcoat,
+            0.25 * clearcoat,
+            Box::new(MicrofacetReflection {
+                ggx: Ggx::new(CLEARCOAT_ROUGHNESS),
+                f0: white * CLEARCOAT_F0,
+            }),
+        );
+        coated.add(
+            1.0,
+            1.0,
+            Box::new(Coated {
+                bxdf: Box::new(bxdf),
+                f0: CLEARCOAT_F0,
+                coverage: clearcoat,
+            }),
+        );
+        Some(Bsdf::new(normal, base_color, Box::new(coated)))
+    }
+
+    /// the kind of the lobe that scatters most of the light
+    fn bounce_kind(&self, hit: &HitResult) -> BounceKind {
+        let uv_coords = hit.uv_coords.unwrap();
+        let metallic = self.metallic.texture(uv_coords).x;
+        let transmission = self.transmission.texture(uv_coords).x;
+        if metallic >= 0.5 {
+            BounceKind::Glossy
+        } else if transmission >= 0.5 {
+            BounceKind::Transmission
+        } else {
+            BounceKind::Diffuse
+        }
+    }
+}
+
+/// `bxdf` below a dielectric layer, the light it scatters passed that layer on the way in and out
+/// the layer only covers `coverage` of the surface and reflects `f0` at normal incidence
+struct Coated {
+    bxdf: Box<dyn Bxdf>,
+    f0: f32,
+    coverage: f32,
+}
+
+impl Coated {
+    /// the fraction of light arriving with `cosine` that passes the layer
+    fn transmittance(&self, cosine: f32) -> f32 {
+        let reflectance = self.f0 + (1.0 - self.f0) * (1.0 - cosine.abs()).max(0.0).powi(5);
+        1.0 - self.coverage * reflectance
+    }
+}
+
+impl Bxdf for Coated {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.bxdf.eval(wo, wi) * self.transmittance(wo.z) * self.transmittance(wi.z)
+    }
+
+    fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let sample = self.bxdf.sample(wo, sampler)?;
+        Some(BsdfSample {
+            f: sample.f * self.transmittance(wo.z) * self.transmittance(sample.wi.z),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        self.bxdf.pdf(wo, wi)
+    }
+
+    fn lobes(&self) -> Lobes {
+        self.bxdf.lobes()
+    }
+}
+
+/// the diffuse base of `Principled`: lambertian, but brighter at grazing angles on rough
+/// surfaces and darker on smooth ones, plus the sheen cloth has at grazing angles
+struct PrincipledDiffuse {
+    base_color: Vec3,
+    roughness: f32,
+    sheen: Vec3,
+}
+
+impl Bxdf for PrincipledDiffuse {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let h = (wo + wi).normalised();
+        let cosine = wi.dot(h);
+
+        let f90 = 0.5 + 2.0 * self.roughness * cosine * cosine;
+        let retro_reflection =
+            (1.0 + (f90 - 1.0) * (1.0 - wi.z).powi(5)) * (1.0 + (f90 - 1.0) * (1.0 - wo.z).powi(5));
+        let sheen = self.sheen * (1.0 - cosine).max(0.0).powi(5);
+        (self.base_color * retro_reflection / PI + sheen) * wi.z
+    }
+
+    fn sample(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let wi = Vec3::random_cosine_direction(sampler);
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf: self.pdf(wo, wi),
+            lobes: Lobes::DIFFUSE | Lobes::REFLECTION,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            0.0
+        } else {
+            wi.z / PI
+        }
+    }
+
+    fn lobes(&self) -> Lobes {
+        Lobes::DIFFUSE | Lobes::REFLECTION
+    }
+}
+
+/* ========================== */
+
+/// two materials blended by a mask, like rust on metal or dirt on paint
+/// `weight` 0 is all `first` and 1 all `second`, it is grayscale like the other masks.
+/// either can be a mix again
+#[derive(Clone)]
+pub struct MixMaterial {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    weight: Arc<dyn Texture>,
+}
+
+impl MixMaterial {
+    pub fn new(
+        first: Arc<dyn Material>,
+        second: Arc<dyn Material>,
+        weight: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            weight,
+        }
+    }
+
+    fn weight(&self, hit: &HitResult) -> f32 {
+        self.weight
+            .texture(hit.uv_coords.unwrap())
+            .x
+            .clamp(0.0, 1.0)
+    }
+}
+
+impl Material for MixMaterial {
+    fn emitted(&self, hit: &HitResult) -> Vec3 {
+        let weight = self.weight(hit);
+        Vec3::lerp(self.first.emitted(hit), self.second.emitted(hit), weight)
+    }
+
+    fn bsdf(&self, hit: &HitResult, wavelengths: Option<Wavelengths>) -> Option<Bsdf> {
+        let weight = self.weight(hit);
+        //a material that is not there does not need its bsdf
+        let first = if weight < 1.0 {
+            self.first.bsdf(hit, wavelengths)
+        } else {
+            None
+        };
+        let second = if weight > 0.0 {
+            self.second.bsdf(hit, wavelengths)
+        } else {
+            None
+        };
+        Bsdf::mix(first, second, weight)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.first.is_emissive() || self.second.is_emissive()
+    }
+
+    /// the kind of the material that covers more of the surface at `hit`
+    fn bounce_kind(&self, hit: &HitResult) -> BounceKind {
+        if self.weight(hit) < 0.5 {
+            self.first.bounce_kind(hit)
+        } else {
+            self.second.bounce_kind(hit)
+        }
     }
 }
 
@@ -267,13 +865,309 @@ impl Emissive {
 }
 
 impl Material for Emissive {
-    fn scattered(&self, _ray: &Ray, _hit: &HitResult) -> Option<(Vec3, Vec3, Ray, f32)> {
+    fn bsdf(&self, _hit: &HitResult, _wavelengths: Option<Wavelengths>) -> Option<Bsdf> {
         None
     }
-    fn scattering_pdf(&self, _r: &Ray, _h: &HitResult, _s: &Ray) -> f32 {
-        0.0
-    }
     fn emitted(&self, hit: &HitResult) -> Vec3 {
         self.emitted.texture(hit.uv_coords.unwrap())
     }
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::texture::ConstantTexture;
+    use crate::sampling::sampler::RandomSampler;
+
+    fn constant(value: f32) -> Arc<dyn Texture> {
+        Arc::new(ConstantTexture::new(Vec3::new(value, value, value)))
+    }
+
+    /// `sample` picks directions with the density `pdf` gives them, and estimates the light that
+    /// is scattered like the directions of the whole sphere do with `eval`
+    fn check_sampling(material: &dyn Material, wo: Vec3) {
+        let hit = HitResult {
+            ray_param: 1.0,
+            hit_position: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            material: None,
+            uv_coords: Some((0.5, 0.5)),
+        };
+        let bsdf = material.bsdf(&hit, None).unwrap();
+
+        //importance sampled, absorbed samples scatter nothing
+        let mut sampler = RandomSampler::new(7);
+        let samples = 40000;
+        let mut scattered = 0;
+        let mut sampled = Vec3::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            if let Some(sample) = bsdf.sample(wo, &mut sampler) {
+                let density = bsdf.pdf(wo, sample.wi);
+                let tolerance = 1e-2 * sample.pdf.max(1.0);
+                assert!(
+                    (sample.pdf - density).abs() <= tolerance,
+                    "{} {}",
+                    sample.pdf,
+                    density
+                );
+                sampled += sample.f / sample.pdf;
+                scattered += 1;
+            }
+        }
+        let sampled = sampled / samples as f32;
+
+        //directions spread evenly over the sphere
+        let steps = 600;
+        let mut pdf_integral = 0.0;
+        let mut f_integral = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            let cos_theta = 1.0 - 2.0 * (i as f32 + 0.5) / steps as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..steps {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / steps as f32;
+                let wi = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                pdf_integral += bsdf.pdf(wo, wi);
+                f_integral += bsdf.eval(wo, wi);
+            }
+        }
+        let solid_angle = 4.0 * PI / (steps * steps) as f32;
+        let pdf_integral = pdf_integral * solid_angle;
+        let f_integral = f_integral * solid_angle;
+
+        //the density integrates to 1, less the fraction of directions that are absorbed
+        let fraction = scattered as f32 / samples as f32;
+        assert!(
+            (pdf_integral - fraction).abs() < 0.02,
+            "{} {}",
+            pdf_integral,
+            fraction
+        );
+        assert!(
+            (sampled - f_integral).len() <= 0.02 * f_integral.len().max(0.1),
+            "{:?} {:?}",
+            sampled,
+            f_integral
+        );
+    }
+
+    #[test]
+    fn test_rough_sampling_matches_pdf() {
+        let wo = Vec3::new(-0.5, 0.8, -0.2).normalised();
+        let metal = Metal::new(constant(0.9), None, constant(1.0), constant(0.5));
+        check_sampling(&metal, wo);
+
+        let glass = Dielectric::new(constant(1.0), None, Some(constant(0.5)), 1.5, 0.0);
+        check_sampling(&glass, wo);
+        //from inside the glass, where light can be reflected totally
+        check_sampling(&glass, -wo);
+
+        //about as smooth as the integrals over the sphere resolve, smoother peaks are narrower
+        let smooth = Dielectric::new(constant(1.0), None, Some(constant(0.2)), 1.5, 0.0);
+        check_sampling(&smooth, wo);
+        check_sampling(&smooth, -wo);
+    }
+
+    #[test]
+    fn test_rough_glass_furnace() {
+        let hit = HitResult {
+            ray_param: 1.0,
+            hit_position: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            material: None,
+            uv_coords: Some((0.5, 0.5)),
+        };
+
+        //white glass reflects and transmits all the light, except what scatters between the
+        //microfacets more than once: the rougher, the more, and more from inside
+        let mut sampler = RandomSampler::new(3);
+        for (roughness, outside, inside) in [(0.05, 0.99, 0.99), (0.5, 0.9, 0.75)] {
+            let glass = Dielectric::new(constant(1.0), None, Some(constant(roughness)), 1.5, 0.0);
+            let bsdf = glass.bsdf(&hit, None).unwrap();
+            for cosine in [1.0f32, 0.7, 0.3, 0.1, -0.1, -0.3, -0.7, -1.0] {
+                let wo = Vec3::new((1.0 - cosine * cosine).sqrt(), cosine, 0.0);
+                let samples = 20000;
+                let mut albedo = Vec3::new(0.0, 0.0, 0.0);
+                for _ in 0..samples {
+                    if let Some(sample) = bsdf.sample(wo, &mut sampler) {
+                        //relative, the peaks of smooth glass are very high
+                        let density = bsdf.pdf(wo, sample.wi);
+                        assert!(
+                            (sample.pdf - density).abs() <= 1e-2 * sample.pdf.max(1.0),
+                            "{} {} {} {}",
+                            roughness,
+                            cosine,
+                            sample.pdf,
+                            density
+                        );
+                        albedo += sample.f / sample.pdf;
+                    }
+                }
+                //the same in every channel
+                let albedo = albedo.max_component() / samples as f32;
+                let expected = if cosine > 0.0 { outside } else { inside };
+                assert!(
+                    (expected..=1.001).contains(&albedo),
+                    "{} {} {}",
+                    roughness,
+                    cosine,
+                    albedo
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_principled_sampling_matches_pdf() {
+        let wo = Vec3::new(-0.5, 0.8, -0.2).normalised();
+        let plastic = Principled::new(
+            Arc::new(ConstantTexture::new(Vec3::new(0.8, 0.2, 0.1))),
+            None,
+            constant(0.3),
+            constant(0.4),
+            constant(0.5),
+            constant(0.5),
+            constant(1.0),
+            constant(0.0),
+        );
+        check_sampling(&plastic, wo);
+
+        let glass = Principled::new(
+            constant(1.0),
+            None,
+            constant(0.0),
+            constant(0.3),
+            constant(0.5),
+            constant(0.0),
+            constant(0.0),
+            constant(0.7),
+        );
+        check_sampling(&glass, wo);
+        check_sampling(&glass, -wo);
+    }
+
+    #[test]
+    fn test_principled_conserves_energy() {
+        let white = Principled::new(
+            constant(1.0),
+            None,
+            constant(0.0),
+            constant(0.3),
+            constant(1.0),
+            constant(1.0),
+            constant(1.0),
+            constant(0.0),
+        );
+        let hit = HitResult {
+            ray_param: 1.0,
+            hit_position: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            material: None,
+            uv_coords: Some((0.5, 0.5)),
+        };
+        let bsdf = white.bsdf(&hit, None).unwrap();
+
+        //even at grazing angles no more light is scattered than arrives
+        let mut sampler = RandomSampler::new(3);
+        for cosine in [1.0f32, 0.5, 0.1, 0.02] {
+            let wo = Vec3::new((1.0 - cosine * cosine).sqrt(), cosine, 0.0);
+            let samples = 20000;
+            let mut albedo = Vec3::new(0.0, 0.0, 0.0);
+            for _ in 0..samples {
+                if let Some(sample) = bsdf.sample(wo, &mut sampler) {
+                    albedo += sample.f / sample.pdf;
+                }
+            }
+            let albedo = albedo / samples as f32;
+            assert!(albedo.max_component() <= 1.0, "{} {:?}", cosine, albedo);
+        }
+    }
+
+    #[test]
+    fn test_mix_sampling_matches_pdf() {
+        let wo = Vec3::new(-0.5, 0.8, -0.2).normalised();
+        let metal = Arc::new(Metal::new(
+            constant(0.9),
+            None,
+            constant(1.0),
+            constant(0.3),
+        ));
+        let glass = Arc::new(Dielectric::new(
+            constant(1.0),
+            None,
+            Some(constant(0.5)),
+            1.5,
+            0.0,
+        ));
+        let paint = Arc::new(Lambertian::new(constant(0.5), None));
+        let light = Arc::new(Emissive::new(constant(4.0)));
+
+        //nested, with a part that absorbs everything
+        let rusty = Arc::new(MixMaterial::new(metal, paint, constant(0.4)));
+        let mix = MixMaterial::new(rusty, glass, constant(0.3));
+        check_sampling(&mix, wo);
+        check_sampling(&mix, -wo);
+
+        let glowing = MixMaterial::new(Arc::new(mix), light, constant(0.5));
+        check_sampling(&glowing, wo);
+        assert!(glowing.is_emissive());
+
+        let hit = HitResult {
+            ray_param: 1.0,
+            hit_position: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            material: None,
+            uv_coords: Some((0.5, 0.5)),
+        };
+        let metal = Arc::new(Metal::new(
+            constant(0.9),
+            None,
+            constant(1.0),
+            constant(0.3),
+        ));
+        let paint = Arc::new(Lambertian::new(constant(0.5), None));
+        let rust = MixMaterial::new(metal.clone(), paint.clone(), constant(0.8));
+        assert_eq!(rust.bounce_kind(&hit), BounceKind::Diffuse);
+        let scratched = MixMaterial::new(metal, paint, constant(0.2));
+        assert_eq!(scratched.bounce_kind(&hit), BounceKind::Glossy);
+    }
+
+    #[test]
+    fn test_dispersion_refracts_by_wavelength() {
+        let glass = Dielectric::new(constant(1.0), None, None, 1.5, 0.01);
+        let hit = HitResult {
+            ray_param: 1.0,
+            hit_position: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            material: None,
+            uv_coords: Some((0.5, 0.5)),
+        };
+        assert!(!glass.bsdf(&hit, None).unwrap().is_dispersive());
+
+        //the sine of the refracted direction for the hero wavelength `lambda`
+        let wo = Vec3::new(0.6, 0.8, 0.0);
+        let refracted = |lambda: f32| {
+            let wavelengths = Wavelengths {
+                lambda: [lambda, 500.0, 700.0],
+            };
+            let bsdf = glass.bsdf(&hit, Some(wavelengths)).unwrap();
+            assert!(bsdf.is_dispersive());
+            let mut sampler = RandomSampler::new(5);
+            loop {
+                if let Some(sample) = bsdf.sample(wo, &mut sampler) {
+                    if sample.wi.y < 0.0 {
+                        return sample.wi.x.abs();
+                    }
+                }
+            }
+        };
+        //blue slows down more and is bent closer to the normal, as snell's law says
+        for lambda in [400.0, 700.0] {
+            let sine = 0.6 / glass.refractive_index_at(lambda);
+            assert!((refracted(lambda) - sine).abs() < 1e-4);
+        }
+        assert!(refracted(400.0) < refracted(700.0));
+    }
 }
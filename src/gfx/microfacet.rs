@@ -0,0 +1,119 @@
+use crate::math::vec3::Vec3;
+use std::f32::consts::PI;
+
+/// the smallest width of the distribution, smoother surfaces would divide by almost 0
+const MIN_ALPHA: f32 = 1.0e-3;
+
+/// the Trowbridge-Reitz (GGX) distribution of the normals of the microfacets of a rough surface
+/// all directions are in the local shading frame, the surface normal is z
+/// (Walter et al., "Microfacet Models for Refraction through Rough Surfaces", 2007)
+#[derive(Debug, Copy, Clone)]
+pub struct Ggx {
+    alpha: f32,
+}
+
+impl Ggx {
+    /// `roughness` from 0 (smooth) to 1, squared like most artists expect it
+    pub fn new(roughness: f32) -> Self {
+        Ggx {
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+        }
+    }
+
+    /// the density of microfacets with the normal `h`, per solid angle and projected area
+    pub fn d(&self, h: Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let alpha2 = self.alpha * self.alpha;
+        //cos² (alpha² - 1) + 1, without the cancellation that breaks very smooth peaks
+        let denominator = h.x * h.x + h.y * h.y + h.z * h.z * alpha2;
+        alpha2 / (PI * denominator * denominator)
+    }
+
+    /// the area of microfacets hidden from `v` by other ones, relative to the visible area
+    fn lambda(&self, v: Vec3) -> f32 {
+        let cos2 = v.z * v.z;
+        if cos2 <= 0.0 {
+            return f32::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// the fraction of the microfacets that `v` sees
+    pub fn g1(&self, v: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(v))
+    }
+
+    /// the fraction of the microfacets that both `wo` and `wi` see
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// picks the normal of a microfacet `wo` sees, facets it sees more of more likely
+    /// `wo` must be above the surface
+    /// (Heitz, "Sampling the GGX Distribution of Visible Normals", 2018)
+    pub fn sample_visible_normal(&self, wo: Vec3, u: (f32, f32)) -> Vec3 {
+        //stretch the view so the distribution becomes a hemisphere
+        let v = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).normalised();
+        let length2 = v.x * v.x + v.y * v.y;
+        let t1 = if length2 > 0.0 {
+            Vec3::new(-v.y, v.x, 0.0) / length2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = v.cross(t1);
+
+        //a point on the disk the hemisphere projects to, the half hidden behind it is squashed
+        let radius = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = radius * phi.cos();
+        let p2 = radius * phi.sin();
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * p2;
+
+        //back onto the hemisphere, then unstretched
+        let height = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let h = p1 * t1 + p2 * t2 + height * v;
+        Vec3::new(self.alpha * h.x, self.alpha * h.y, h.z.max(0.0)).normalised()
+    }
+
+    /// the density with which `sample_visible_normal` picks `h` for `wo`, per solid angle
+    pub fn visible_normal_pdf(&self, wo: Vec3, h: Vec3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visible_normals_integrate_to_one() {
+        let ggx = Ggx::new(0.5);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+
+        //integrate the pdf over the hemisphere of normals
+        let steps = 400;
+        let mut sum = 0.0;
+        for i in 0..steps {
+            let cos_theta = (i as f32 + 0.5) / steps as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..steps {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / steps as f32;
+                let h = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                sum += ggx.visible_normal_pdf(wo, h);
+            }
+        }
+        let integral = sum * 2.0 * PI / (steps * steps) as f32;
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+
+        //sampled normals face the viewer
+        let h = ggx.sample_visible_normal(wo, (0.3, 0.7));
+        assert!((h.len() - 1.0).abs() < 1e-5 && wo.dot(h) > 0.0);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::math::vec3::Vec3;
+use std::sync::OnceLock;
+
+/// the wavelengths in nm that are traced, everything the eye can see
+pub const LAMBDA_MIN: f32 = 360.0;
+pub const LAMBDA_MAX: f32 = 830.0;
+
+/// the spectra of rgb colors are given in this many bins from 380 to 720 nm
+const BINS: usize = 10;
+const BINS_MIN: f32 = 380.0;
+const BINS_MAX: f32 = 720.0;
+
+/// smooth reflectance spectra of the primaries and their mixtures
+/// (Smits, "An RGB-to-Spectrum Conversion for Reflectances", 1999)
+const WHITE: [f32; BINS] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const CYAN: [f32; BINS] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const MAGENTA: [f32; BINS] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const YELLOW: [f32; BINS] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const RED: [f32; BINS] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const GREEN: [f32; BINS] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0000,
+];
+const BLUE: [f32; BINS] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// the wavelengths a camera ray carries in spectral mode: a hero wavelength picked at random,
+/// and two more spread evenly over the visible range, so every sample sees all of it
+/// (Wilkie et al., "Hero Wavelength Spectral Sampling", 2014)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Wavelengths {
+    /// in nm, the hero wavelength first
+    pub lambda: [f32; 3],
+}
+
+impl Wavelengths {
+    /// the wavelengths picked by the uniform random number `u`
+    pub fn sample(u: f32) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; 3];
+        for (index, value) in lambda.iter_mut().enumerate() {
+            let offset = u + index as f32 / 3.0;
+            *value = LAMBDA_MIN + (offset - offset.floor()) * range;
+        }
+        Wavelengths { lambda }
+    }
+
+    /// the wavelength the path is sampled for, the others only come along
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// the value at every wavelength of the reflectance spectrum that looks like `rgb`
+    pub fn spectrum(&self, rgb: Vec3) -> Vec3 {
+        let [a, b, c] = self.lambda;
+        Vec3::new(
+            rgb_to_spectrum(rgb, a),
+            rgb_to_spectrum(rgb, b),
+            rgb_to_spectrum(rgb, c),
+        )
+    }
+
+    /// the value at every wavelength of the spectrum of light that looks like `rgb`,
+    /// like emission or the sky
+    pub fn illuminant(&self, rgb: Vec3) -> Vec3 {
+        let [a, b, c] = self.lambda;
+        Vec3::new(
+            illuminant_to_spectrum(rgb, a),
+            illuminant_to_spectrum(rgb, b),
+            illuminant_to_spectrum(rgb, c),
+        )
+    }
+
+    /// `radiance` of a path that split up by wavelength, like in a prism: it only holds for the
+    /// hero, the others would have gone elsewhere. the hero stands in for all three instead
+    /// (pbrt-v4, `SampledWavelengths::TerminateSecondary`)
+    pub fn terminate_secondary(&self, radiance: Vec3) -> Vec3 {
+        Vec3::new(3.0 * radiance.x, 0.0, 0.0)
+    }
+
+    /// the color of the light `radiance` carried at these wavelengths
+    /// this is one sample of the integral over all wavelengths, averaging samples converges to it
+    pub fn to_rgb(self, radiance: Vec3) -> Vec3 {
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        let values = [radiance.x, radiance.y, radiance.z];
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for (lambda, value) in self.lambda.iter().zip(values.iter()) {
+            xyz += *value * color_matching(*lambda) / (3.0 * pdf);
+        }
+
+        //balanced so that a constant spectrum is white
+        xyz_to_rgb(xyz) / *white_rgb()
+    }
+}
+
+/// the value at `lambda` of a smooth spectrum that looks like `rgb`,
+/// the white part plus the mixtures of the primaries that are left over
+pub fn rgb_to_spectrum(rgb: Vec3, lambda: f32) -> f32 {
+    let bin = ((lambda - BINS_MIN) / (BINS_MAX - BINS_MIN) * BINS as f32).max(0.0) as usize;
+    let bin = bin.min(BINS - 1);
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+
+    if r <= g && r <= b {
+        let value = r * WHITE[bin];
+        if g <= b {
+            value + (g - r) * CYAN[bin] + (b - g) * BLUE[bin]
+        } else {
+            value + (b - r) * CYAN[bin] + (g - b) * GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        let value = g * WHITE[bin];
+        if r <= b {
+            value + (r - g) * MAGENTA[bin] + (b - r) * BLUE[bin]
+        } else {
+            value + (b - g) * MAGENTA[bin] + (r - b) * RED[bin]
+        }
+    } else {
+        let value = b * WHITE[bin];
+        if r <= g {
+            value + (r - b) * YELLOW[bin] + (g - r) * GREEN[bin]
+        } else {
+            value + (g - b) * YELLOW[bin] + (r - g) * RED[bin]
+        }
+    }
+}
+
+/// the value at `lambda` of a spectrum of light that looks like `rgb`
+/// light is not bounded by 1 like the reflectances the spectra above were fitted for,
+/// it is a mix of the primaries instead, weighted so it keeps its color at any brightness
+pub fn illuminant_to_spectrum(rgb: Vec3, lambda: f32) -> f32 {
+    let bin = ((lambda - BINS_MIN) / (BINS_MAX - BINS_MIN) * BINS as f32).max(0.0) as usize;
+    let bin = bin.min(BINS - 1);
+    let [to_red, to_green, to_blue] = primaries_from_rgb();
+    let value =
+        to_red.dot(rgb) * RED[bin] + to_green.dot(rgb) * GREEN[bin] + to_blue.dot(rgb) * BLUE[bin];
+    //colors more saturated than the primaries would need negative light
+    value.max(0.0)
+}
+
+/// the CIE 1931 color matching functions at `lambda`, as XYZ
+/// (Wyman et al., "Simple Analytic Approximations to the CIE XYZ Color Matching Functions", 2013)
+pub fn color_matching(lambda: f32) -> Vec3 {
+    //a gaussian with different widths left and right of its center
+    let lobe = |center: f32, left: f32, right: f32| {
+        let width = if lambda < center { left } else { right };
+        let t = (lambda - center) / width;
+        (-0.5 * t * t).exp()
+    };
+
+    Vec3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// linear sRGB from XYZ
+fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.240_454 * xyz.x - 1.537_138 * xyz.y - 0.498_531 * xyz.z,
+        -0.969_266 * xyz.x + 1.876_011 * xyz.y + 0.041_556 * xyz.z,
+        0.055_643 * xyz.x - 0.204_026 * xyz.y + 1.057_225 * xyz.z,
+    )
+}
+
+/// the rgb of a spectrum that is 1 everywhere
+fn white_rgb() -> &'static Vec3 {
+    static WHITE_RGB: OnceLock<Vec3> = OnceLock::new();
+    WHITE_RGB.get_or_init(|| spectrum_rgb(|_| 1.0))
+}
+
+/// the rgb of the spectrum that is 1 everywhere times `spectrum`
+fn spectrum_rgb(spectrum: impl Fn(f32) -> f32) -> Vec3 {
+    let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+    let mut lambda = LAMBDA_MIN;
+    while lambda < LAMBDA_MAX {
+        xyz += spectrum(lambda + 0.5) * color_matching(lambda + 0.5);
+        lambda += 1.0;
+    }
+    xyz_to_rgb(xyz)
+}
+
+/// the rows of the matrix that gives the weights of the red, green and blue spectra
+/// for an rgb color, the inverse of the one whose columns are their colors
+fn primaries_from_rgb() -> &'static [Vec3; 3] {
+    static PRIMARIES: OnceLock<[Vec3; 3]> = OnceLock::new();
+    PRIMARIES.get_or_init(|| {
+        let color = |primary: &'static [f32; BINS]| {
+            let rgb = spectrum_rgb(|lambda| {
+                let bin = ((lambda - BINS_MIN) / (BINS_MAX - BINS_MIN) * BINS as f32).max(0.0);
+                primary[(bin as usize).min(BINS - 1)]
+            });
+            rgb / *white_rgb()
+        };
+        let (red, green, blue) = (color(&RED), color(&GREEN), color(&BLUE));
+        let determinant = red.dot(green.cross(blue));
+        [
+            green.cross(blue) / determinant,
+            blue.cross(red) / determinant,
+            red.cross(green) / determinant,
+        ]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        //averaging over all wavelengths gives back the color of the spectrum
+        let colors = [
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(0.8, 0.1, 0.1),
+            Vec3::new(0.2, 0.6, 0.3),
+        ];
+        for rgb in &colors {
+            let steps = 3000;
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            for i in 0..steps {
+                let wavelengths = Wavelengths::sample((i as f32 + 0.5) / steps as f32);
+                sum += wavelengths.to_rgb(wavelengths.spectrum(*rgb));
+            }
+            let average = sum / steps as f32;
+            let error = average - *rgb;
+            assert!(error.len() < 0.1, "{:?} became {:?}", rgb, average);
+        }
+    }
+
+    #[test]
+    fn test_illuminant_round_trip() {
+        //light keeps its color however bright it is
+        let colors = [
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(20.0, 20.0, 20.0),
+            Vec3::new(15.0, 6.0, 2.0),
+            Vec3::new(0.5, 2.0, 8.0),
+        ];
+        for rgb in &colors {
+            let steps = 3000;
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            for i in 0..steps {
+                let wavelengths = Wavelengths::sample((i as f32 + 0.5) / steps as f32);
+                sum += wavelengths.to_rgb(wavelengths.illuminant(*rgb));
+            }
+            let average = sum / steps as f32;
+            let error = average - *rgb;
+            assert!(
+                error.len() < 0.02 * rgb.len(),
+                "{:?} became {:?}",
+                rgb,
+                average
+            );
+        }
+    }
+}
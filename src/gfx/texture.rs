@@ -16,6 +16,11 @@ fn luminance() -> f32 {
 pub trait Texture: Send + Sync {
     /// returns a color as vec3 from UV coordinates
     fn texture(&self, uv_coords: (f32, f32)) -> Vec3;
+
+    /// the number of (columns, rows) of texels, None if the texture is not made of texels
+    fn resolution(&self) -> Option<(usize, usize)> {
+        None
+    }
 }
 
 pub enum TextureFilter {
@@ -78,12 +83,22 @@ pub struct ImageTexture {
 }
 impl ImageTexture {
     pub fn new<P: AsRef<Path>>(filepath: P) -> Self {
+        Self::load(filepath).expect("failed to load image!")
+    }
+
+    pub fn load<P: AsRef<Path>>(filepath: P) -> Result<Self, image2::Error> {
         //reads the image as float (64bit) RGB (LDR is "promoted" to HDR! HDR stays HDR)
         //this is *linear* colorspace!
-        let ptr = image2::io::read_f32(filepath).expect("failed to load image!");
+        //image2 does not check if stb_image actually loaded something,
+        //so at least make sure the file is there before handing it a path
+        if let Err(e) = std::fs::File::open(filepath.as_ref()) {
+            return Err(image2::Error::Message(e.to_string()));
+        }
+
+        let ptr = image2::io::read_f32(filepath)?;
         let mut buf = ImageBuf::new(ptr.width(), ptr.height());
         ptr.convert_type(&mut buf);
-        Self { data: buf }
+        Ok(Self { data: buf })
     }
 }
 impl Texture for ImageTexture {
@@ -114,4 +129,8 @@ impl Texture for ImageTexture {
 
         (1.0 - beta) * interp1 + beta * interp2
     }
+
+    fn resolution(&self) -> Option<(usize, usize)> {
+        Some((self.data.width(), self.data.height()))
+    }
 }
@@ -4,6 +4,7 @@ use crate::gfx::material::Material;
 use crate::hittables::aabb::AABB;
 use crate::math::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
 
 #[derive(Clone)]
 pub struct HitResult {
@@ -14,21 +15,78 @@ pub struct HitResult {
     pub uv_coords: Option<(f32, f32)>,
 }
 
+impl HitResult {
+    /// a ray leaving the hit into `direction`, moved off the surface to the side it goes to
+    /// so it does not hit the surface again
+    pub fn spawn_ray(&self, direction: Vec3) -> Ray {
+        let epsilon = if direction.dot(self.normal) >= 0.0 {
+            self.normal * 0.001
+        } else {
+            self.normal * -0.001
+        };
+        Ray::new(self.hit_position + epsilon, direction)
+    }
+}
+
 pub trait Hit: Send + Sync {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult>;
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitResult>;
     fn bounding_box(&self) -> Option<AABB>;
     fn center(&self) -> Vec3;
+
+    /// how many primitives (spheres, triangles, ...) this is made of, for statistics
+    fn primitive_count(&self) -> usize {
+        1
+    }
+
+    /// true if this emits light and can be sampled with `random_direction`
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    /// the solid angle density with which `random_direction` picks `direction` from `origin`
+    /// 0 if `direction` misses this object
+    fn pdf_value(&self, _origin: Vec3, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    /// a random unit direction from `origin` towards this object, see `pdf_value`
+    fn random_direction(&self, _origin: Vec3, _sampler: &mut dyn Sampler) -> Vec3 {
+        unimplemented!("only lights can be sampled")
+    }
+
+    /// the surface area, `random_point` picks every point with the density 1 / area
+    fn area(&self) -> f32 {
+        0.0
+    }
+
+    /// a uniformly distributed random point on the surface, as if a ray had hit it there
+    /// the ray parameter of the result is meaningless
+    fn random_point(&self, _sampler: &mut dyn Sampler) -> HitResult {
+        unimplemented!("only lights can be sampled")
+    }
 }
 
 //hit a list of specific hittable
 //useful for hitting triangles of a mesh
 impl<T: Hit> Hit for Vec<T> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
         let mut closest = t_max;
         let mut result = None;
 
         for object in self {
-            if let Some(hit) = object.hit(ray, t_min, closest) {
+            if let Some(hit) = object.hit(ray, t_min, closest, sampler) {
                 closest = hit.ray_param;
                 result = Some(hit);
             }
@@ -73,12 +131,22 @@ impl<T: Hit> Hit for Vec<T> {
     fn center(&self) -> Vec3 {
         self.bounding_box().unwrap().center()
     }
+
+    fn primitive_count(&self) -> usize {
+        self.iter().map(Hit::primitive_count).sum()
+    }
 }
 
 /// simply calls Hit on the object in the Arc
 impl Hit for Arc<dyn Hit> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
-        self.as_ref().hit(ray, t_min, t_max)
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
+        self.as_ref().hit(ray, t_min, t_max, sampler)
     }
     fn bounding_box(&self) -> Option<AABB> {
         self.as_ref().bounding_box()
@@ -86,4 +154,22 @@ impl Hit for Arc<dyn Hit> {
     fn center(&self) -> Vec3 {
         self.as_ref().center()
     }
+    fn primitive_count(&self) -> usize {
+        self.as_ref().primitive_count()
+    }
+    fn is_light(&self) -> bool {
+        self.as_ref().is_light()
+    }
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+    fn random_direction(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.as_ref().random_direction(origin, sampler)
+    }
+    fn area(&self) -> f32 {
+        self.as_ref().area()
+    }
+    fn random_point(&self, sampler: &mut dyn Sampler) -> HitResult {
+        self.as_ref().random_point(sampler)
+    }
 }
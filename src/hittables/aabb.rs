@@ -1,6 +1,7 @@
 use crate::hit::{Hit, HitResult};
 use crate::math::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
 
 /// Implements an Axis-Aligned Bounding-Box
 #[derive(Debug, Copy, Clone)]
@@ -55,10 +56,9 @@ impl AABB {
             Axis::Z
         }
     }
-}
 
-impl Hit for AABB {
-    fn hit(&self, ray: &Ray, mut t_min: f32, mut t_max: f32) -> Option<HitResult> {
+    /// where `ray` enters and leaves the box between `t_min` and `t_max`, see `hit`
+    pub fn intersect(&self, ray: &Ray, mut t_min: f32, mut t_max: f32) -> Option<HitResult> {
         //instead of dividing by direction, multiply by its inverse
         let inverse_dx = 1.0 / ray.direction.x;
         let inverse_dy = 1.0 / ray.direction.y;
@@ -104,6 +104,18 @@ impl Hit for AABB {
             uv_coords: None,
         })
     }
+}
+
+impl Hit for AABB {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self) -> Option<AABB> {
         Some(*self)
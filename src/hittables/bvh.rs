@@ -2,6 +2,7 @@ use crate::hit::{Hit, HitResult};
 use crate::hittables::aabb::{Axis, AABB};
 use crate::math::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
 
 /*
     This is more idiomatic, but it increases rendering time.
@@ -16,6 +17,15 @@ pub struct BvhTree<T: Hit + Sized> {
     objects: Vec<T>,
 }
 
+/// the shape of a bvh tree, see `BvhTree::stats`
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BvhStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    /// the longest path from the root to a leaf, the root alone has depth 1
+    pub depth: usize,
+}
+
 /// A Node of the Bounding Volume Hierarchy Tree
 #[derive(Clone)]
 struct BvhNode {
@@ -111,29 +121,57 @@ impl<T: Hit> BvhTree<T> {
         }
     }
 
-    fn hit_node(&self, idx: u32, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
+    /// counts the nodes and leaves and measures the depth of the tree
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats {
+            nodes: self.nodes.len(),
+            ..BvhStats::default()
+        };
+
+        let mut stack = vec![(0, 1)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            stats.depth = stats.depth.max(depth);
+            if node.count > 0 {
+                stats.leaves += 1;
+            } else {
+                stack.push((node.left, depth + 1));
+                stack.push((node.left + 1, depth + 1));
+            }
+        }
+        stats
+    }
+
+    fn hit_node(
+        &self,
+        idx: u32,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
         let node = &self.nodes[idx as usize];
 
         //only proceed if the bounding box was hit
-        if node.bb.hit(ray, t_min, t_max).is_some() {
+        if node.bb.hit(ray, t_min, t_max, sampler).is_some() {
             //early stop if single leaf
             if node.count == 1 {
-                return self.objects[node.left as usize].hit(ray, t_min, t_max);
+                return self.objects[node.left as usize].hit(ray, t_min, t_max, sampler);
             }
 
             let (left_hit, right_hit) = match node.count {
                 0 => {
                     //recurse further
                     (
-                        self.hit_node(node.left, ray, t_min, t_max),
-                        self.hit_node(node.left + 1, ray, t_min, t_max),
+                        self.hit_node(node.left, ray, t_min, t_max, sampler),
+                        self.hit_node(node.left + 1, ray, t_min, t_max, sampler),
                     )
                 }
                 2 => {
                     //hit children only
                     (
-                        self.objects[node.left as usize].hit(ray, t_min, t_max),
-                        self.objects[(node.left + 1) as usize].hit(ray, t_min, t_max),
+                        self.objects[node.left as usize].hit(ray, t_min, t_max, sampler),
+                        self.objects[(node.left + 1) as usize].hit(ray, t_min, t_max, sampler),
                     )
                 }
                 _ => unreachable!(),
@@ -156,105 +194,74 @@ impl<T: Hit> BvhTree<T> {
         }
     }
 
-    //debug
-    pub fn get_left_node_index(&self, idx: usize) -> usize {
-        if self.nodes[idx].count != 0 {
-            panic!("dont do that");
-        }
-
-        self.nodes[idx].left as usize
+    /// the objects in the order of the leaves, see `for_each_along`
+    pub fn objects(&self) -> &[T] {
+        &self.objects
     }
 
-    //debug
-    pub fn get_right_node_index(&self, idx: usize) -> usize {
-        if self.nodes[idx].count != 0 {
-            panic!("dont do that");
+    /// calls `found` with the index and the object of every leaf whose box `ray` passes through
+    /// between `t_min` and `t_max`, whether it hits the object or not
+    pub fn for_each_along<F: FnMut(usize, &T)>(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        mut found: F,
+    ) {
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if node.bb.intersect(ray, t_min, t_max).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.left);
+                stack.push(node.left + 1);
+            } else {
+                for object in node.left..node.left + node.count {
+                    found(object as usize, &self.objects[object as usize]);
+                }
+            }
         }
-        self.nodes[idx].left as usize + 1
     }
 
-    //debug
-    pub fn debug_hit(
+    /// how many bounding boxes and objects `hit` tests the ray against
+    /// objects count once, even if they have their own bvh
+    pub fn traversal_cost(
         &self,
-        idx: usize,
         ray: &Ray,
         t_min: f32,
         t_max: f32,
-    ) -> (Vec3, Vec3, Vec3, f32) {
-        let node = &self.nodes[idx];
-
-        let root_hit = node.bb.hit(ray, t_min, t_max);
-        let (mut color, mut albedo, mut normal, mut depth) = if let Some(hit) = &root_hit {
-            (
-                Vec3::rgb(255, 0, 0),
-                Vec3::rgb(255, 0, 0),
-                hit.normal,
-                1.0 / hit.ray_param,
-            )
-        } else {
-            (
-                Vec3::rgb(0, 0, 0),
-                Vec3::rgb(0, 0, 0),
-                Vec3::rgb(0, 0, 0),
-                0.0,
-            )
-        };
-
-        if root_hit.is_some() {
-            let (left_hit, right_hit) = if node.count == 0 {
-                let left_node = &self.nodes[node.left as usize];
-                let right_node = &self.nodes[node.left as usize + 1];
-                (
-                    left_node.bb.hit(ray, t_min, t_max),
-                    right_node.bb.hit(ray, t_min, t_max),
-                )
-            } else if node.count == 1 {
-                let left = &self.objects[node.left as usize];
-                (left.hit(ray, t_min, t_max), None)
+        sampler: &mut dyn Sampler,
+    ) -> u32 {
+        let mut cost = 0;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            cost += 1;
+            if node.bb.hit(ray, t_min, t_max, sampler).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.left);
+                stack.push(node.left + 1);
             } else {
-                let left = &self.objects[node.left as usize];
-                let right = &self.objects[node.left as usize + 1];
-                (left.hit(ray, t_min, t_max), right.hit(ray, t_min, t_max))
-            };
-
-            //get closer hit
-            match (left_hit, right_hit) {
-                (Some(lh), Some(rh)) => {
-                    if lh.ray_param < rh.ray_param {
-                        color = Vec3::rgb(0, 255, 0);
-                        albedo = color;
-                        normal = lh.normal;
-                        depth = 1.0 / lh.ray_param;
-                    } else {
-                        color = Vec3::rgb(0, 0, 255);
-                        albedo = color;
-                        normal = rh.normal;
-                        depth = 1.0 / rh.ray_param;
-                    }
-                }
-                (Some(lh), None) => {
-                    color = Vec3::rgb(0, 255, 0);
-                    albedo = color;
-                    normal = lh.normal;
-                    depth = 1.0 / lh.ray_param;
-                }
-                (None, Some(rh)) => {
-                    color = Vec3::rgb(0, 0, 255);
-                    albedo = color;
-                    normal = rh.normal;
-                    depth = 1.0 / rh.ray_param;
-                }
-                _ => {}
-            };
+                cost += node.count;
+            }
         }
-
-        (color, albedo, normal, depth)
+        cost
     }
 }
 
 impl<T: Hit> Hit for BvhTree<T> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
-        self.hit_node(0, ray, t_min, t_max)
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
+        self.hit_node(0, ray, t_min, t_max, sampler)
     }
 
     fn bounding_box(&self) -> Option<AABB> {
@@ -264,4 +271,8 @@ impl<T: Hit> Hit for BvhTree<T> {
     fn center(&self) -> Vec3 {
         self.nodes[0].bb.center()
     }
+
+    fn primitive_count(&self) -> usize {
+        self.objects.iter().map(Hit::primitive_count).sum()
+    }
 }
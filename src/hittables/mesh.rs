@@ -6,27 +6,45 @@ use std::sync::Arc;
 use crate::hit::{Hit, HitResult};
 use crate::hittables::aabb::AABB;
 use crate::hittables::bvh::BvhTree;
+use crate::math::distribution::Distribution1D;
 use crate::math::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
 
 #[derive(Clone)]
 pub struct Mesh {
     position: Vec3,
     faces: BvhTree<Triangle>,
+    /// picks the faces in the order of the tree, each as likely as its area
+    areas: Distribution1D,
+    /// the area of all faces
+    area: f32,
+    /// the faces emit light, the mesh can be sampled like any other light
+    emissive: bool,
 }
 
 impl Mesh {
-    pub fn new<P: AsRef<Path>>(file: P) -> Self {
-        let (models, _mats) = tobj::load_obj(file.as_ref(), true).expect("couldn't load file");
-
+    /// the material used if the mesh does not specify one
+    pub fn default_material() -> Arc<dyn Material> {
         let r: f32 = (123.0f32 / 255.0f32).powf(2.2f32);
         let g: f32 = (63.0f32 / 255.0f32).powf(2.2f32);
 
-        //load material
-        let material = Arc::new(Lambertian::new(
+        Arc::new(Lambertian::new(
             Arc::new(ConstantTexture::new(Vec3::new(r, g, 0.0))),
             None,
-        ));
+        ))
+    }
+
+    /// loads the first model of an obj file and gives every face the same material
+    pub fn load<P: AsRef<Path>>(
+        file: P,
+        material: Arc<dyn Material>,
+    ) -> Result<Self, tobj::LoadError> {
+        let (models, _mats) = tobj::load_obj(file.as_ref(), true)?;
+
+        if models.is_empty() {
+            return Err(tobj::LoadError::GenericFailure);
+        }
 
         // just assume there is only 1 model in the obj!
         let mesh: Vec<Triangle> = models[0]
@@ -116,27 +134,43 @@ impl Mesh {
             })
             .collect();
 
+        if mesh.is_empty() {
+            return Err(tobj::LoadError::GenericFailure);
+        }
+
+        let emissive = material.is_emissive();
         let bvh = BvhTree::from_hittables(mesh);
+        let areas: Vec<f32> = bvh.objects().iter().map(Triangle::area).collect();
 
-        Mesh {
+        Ok(Mesh {
             position: Vec3::new(0.0, 0.0, 0.0),
+            area: areas.iter().sum(),
+            areas: Distribution1D::new(areas),
             faces: bvh,
-        }
+            emissive,
+        })
     }
 }
 
 //Vec<Hit> implements hittable!
 impl Hit for Mesh {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
         //instead of offsetting every face by mesh's position
         //we offset the ray in the opposite direction
         let modified_ray = Ray {
             origin: ray.origin - self.position,
             direction: ray.direction,
+            wavelengths: ray.wavelengths,
         };
 
         //if we hit, undo the offsetting of the ray and correct the hit position
-        if let Some(mut hit) = self.faces.hit(&modified_ray, t_min, t_max) {
+        if let Some(mut hit) = self.faces.hit(&modified_ray, t_min, t_max, sampler) {
             hit.hit_position += self.position;
             return Some(hit);
         }
@@ -151,6 +185,41 @@ impl Hit for Mesh {
     fn center(&self) -> Vec3 {
         self.position
     }
+
+    fn primitive_count(&self) -> usize {
+        self.faces.primitive_count()
+    }
+
+    fn is_light(&self) -> bool {
+        self.emissive && self.area > 0.0
+    }
+
+    //uniform over the area of all faces: every face the direction passes through could have
+    //been picked, each one with the share of its area
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let ray = Ray::new(origin - self.position, direction);
+        let mut pdf = 0.0;
+        self.faces
+            .for_each_along(&ray, 0.0001, f32::MAX, |_, face| {
+                pdf += face.pdf_value(ray.origin, ray.direction) * face.area() / self.area;
+            });
+        pdf
+    }
+
+    fn random_direction(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        (self.random_point(sampler).hit_position - origin).normalised()
+    }
+
+    fn area(&self) -> f32 {
+        self.area
+    }
+
+    fn random_point(&self, sampler: &mut dyn Sampler) -> HitResult {
+        let (_, _, index) = self.areas.sample(sampler.next_1d());
+        let mut hit = self.faces.objects()[index].random_point(sampler);
+        hit.hit_position += self.position;
+        hit
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -179,68 +248,14 @@ struct Triangle {
 }
 
 impl Hit for Triangle {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
-        let span_a = self.b.position - self.a.position;
-        let span_b = self.c.position - self.a.position;
-        let tri_normal = span_a.cross(span_b).normalised();
-
-        let parameter =
-            -(ray.origin - self.a.position).dot(tri_normal) / ray.direction.dot(tri_normal);
-
-        //no hit if outside [min, max]
-        if parameter < t_min || parameter > t_max {
-            return None;
-        }
-
-        let hit_position = ray.point_at(parameter);
-        let relative_hit = hit_position - self.a.position;
-
-        // get UV coords (in [0,1] if hit)
-        let ada = span_a.dot(span_a);
-        let bdb = span_b.dot(span_b);
-        let rda = relative_hit.dot(span_a);
-        let rdb = relative_hit.dot(span_b);
-        let adb = span_a.dot(span_b);
-
-        let denom = 1.0 / ((adb * adb) - (ada * bdb));
-
-        let alpha = ((adb * rdb) - (bdb * rda)) * denom; //along spanA
-        let beta = ((adb * rda) - (ada * rdb)) * denom; //along spanB
-
-        // u, v must be positive, smaller 1, and if a triangle, their sum must by < 1 too
-        if alpha < 0.0 || beta < 0.0 || (alpha + beta) > 1.0 {
-            None
-        } else {
-            //linear interpolate normal
-            let normal = match (self.a.normal, self.b.normal, self.c.normal) {
-                (Some(an), Some(bn), Some(cn)) => {
-                    (1.0 - alpha - beta) * an + alpha * bn + beta * cn
-                }
-                _ => tri_normal,
-            };
-
-            //linear interpolate uv coordinates
-            let uvcoords = match (self.a.uv_coords, self.b.uv_coords, self.c.uv_coords) {
-                (Some(auv), Some(buv), Some(cuv)) => {
-                    //linear interpolate u coordinate
-                    let u_coord = (1.0 - alpha - beta) * auv.0 + alpha * buv.0 + beta * cuv.0;
-
-                    //linear interpolate v coordinate
-                    let v_coord = (1.0 - alpha - beta) * auv.1 + alpha * buv.1 + beta * cuv.1;
-
-                    (u_coord, v_coord)
-                }
-                _ => (alpha, beta),
-            };
-
-            Some(HitResult {
-                ray_param: parameter,
-                hit_position,
-                normal,
-                material: Some(self.material.clone()),
-                uv_coords: Some(uvcoords),
-            })
-        }
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
+        self.intersect(ray, t_min, t_max)
     }
 
     fn bounding_box(&self) -> Option<AABB> {
@@ -291,4 +306,120 @@ impl Hit for Triangle {
     fn center(&self) -> Vec3 {
         self.bounding_box().unwrap().center()
     }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let ray = Ray::new(origin, direction);
+        let hit = match self.intersect(&ray, 0.0001, f32::MAX) {
+            Some(hit) => hit,
+            None => return 0.0,
+        };
+
+        //uniform over the area, converted to solid angle with the cosine of the flat face
+        let cosine = self.geometric_normal().dot(ray.direction).abs();
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        hit.ray_param * hit.ray_param / (cosine * self.area())
+    }
+
+    fn random_direction(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        (self.random_point(sampler).hit_position - origin).normalised()
+    }
+
+    fn area(&self) -> f32 {
+        let span_a = self.b.position - self.a.position;
+        let span_b = self.c.position - self.a.position;
+        0.5 * span_a.cross(span_b).len()
+    }
+
+    fn random_point(&self, sampler: &mut dyn Sampler) -> HitResult {
+        //uniform point on the triangle
+        let (r1, r2) = sampler.next_2d();
+        let root = r1.sqrt();
+        let (alpha, beta) = (root * (1.0 - r2), root * r2);
+        let position = self.a.position
+            + alpha * (self.b.position - self.a.position)
+            + beta * (self.c.position - self.a.position);
+
+        self.surface_hit(0.0, position, alpha, beta)
+    }
+}
+
+impl Triangle {
+    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
+        let span_a = self.b.position - self.a.position;
+        let span_b = self.c.position - self.a.position;
+        let tri_normal = span_a.cross(span_b).normalised();
+
+        let parameter =
+            -(ray.origin - self.a.position).dot(tri_normal) / ray.direction.dot(tri_normal);
+
+        //no hit if outside [min, max]
+        if parameter < t_min || parameter > t_max {
+            return None;
+        }
+
+        let hit_position = ray.point_at(parameter);
+        let relative_hit = hit_position - self.a.position;
+
+        // get UV coords (in [0,1] if hit)
+        let ada = span_a.dot(span_a);
+        let bdb = span_b.dot(span_b);
+        let rda = relative_hit.dot(span_a);
+        let rdb = relative_hit.dot(span_b);
+        let adb = span_a.dot(span_b);
+
+        let denom = 1.0 / ((adb * adb) - (ada * bdb));
+
+        let alpha = ((adb * rdb) - (bdb * rda)) * denom; //along spanA
+        let beta = ((adb * rda) - (ada * rdb)) * denom; //along spanB
+
+        // u, v must be positive, smaller 1, and if a triangle, their sum must by < 1 too
+        if alpha < 0.0 || beta < 0.0 || (alpha + beta) > 1.0 {
+            None
+        } else {
+            Some(self.surface_hit(parameter, hit_position, alpha, beta))
+        }
+    }
+
+    fn geometric_normal(&self) -> Vec3 {
+        let span_a = self.b.position - self.a.position;
+        let span_b = self.c.position - self.a.position;
+        span_a.cross(span_b).normalised()
+    }
+
+    /// the hit at `hit_position`, `alpha` along the edge to b and `beta` along the edge to c
+    fn surface_hit(&self, ray_param: f32, hit_position: Vec3, alpha: f32, beta: f32) -> HitResult {
+        //linear interpolate normal
+        let normal = match (self.a.normal, self.b.normal, self.c.normal) {
+            (Some(an), Some(bn), Some(cn)) => (1.0 - alpha - beta) * an + alpha * bn + beta * cn,
+            _ => self.geometric_normal(),
+        };
+
+        //linear interpolate uv coordinates
+        let uvcoords = match (self.a.uv_coords, self.b.uv_coords, self.c.uv_coords) {
+            (Some(auv), Some(buv), Some(cuv)) => {
+                //linear interpolate u coordinate
+                let u_coord = (1.0 - alpha - beta) * auv.0 + alpha * buv.0 + beta * cuv.0;
+
+                //linear interpolate v coordinate
+                let v_coord = (1.0 - alpha - beta) * auv.1 + alpha * buv.1 + beta * cuv.1;
+
+                (u_coord, v_coord)
+            }
+            _ => (alpha, beta),
+        };
+
+        HitResult {
+            ray_param,
+            hit_position,
+            normal,
+            material: Some(self.material.clone()),
+            uv_coords: Some(uvcoords),
+        }
+    }
 }
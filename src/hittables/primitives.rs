@@ -5,8 +5,10 @@ use std::sync::Arc;
 use crate::gfx::material::Material;
 use crate::hit::{Hit, HitResult};
 use crate::hittables::aabb::AABB;
+use crate::math::onb::ONB;
 use crate::math::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
 
 /*
 TODO: refactor this to something like...
@@ -63,8 +65,9 @@ pub struct Sphere {
     pub material: Arc<dyn Material>,
 }
 
-impl Hit for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
+impl Sphere {
+    /// the intersection of `ray` with the sphere, the same as `hit`
+    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
         // x² + y² + z² = radius  | sphere at 0,0,0
         // (x-c_x)² + (y-c_y)² + (z-c_z)² = radius | sphere at c
         // => (p-c)² == (p-c)·(p-c) = radius  | dot product!
@@ -93,27 +96,41 @@ impl Hit for Sphere {
                 return None;
             }
 
-            let hit_position = ray.point_at(parameter);
+            Some(self.surface_hit(parameter, ray.point_at(parameter)))
+        }
+    }
 
-            //divide by radius instead of .normalise() => can invert normals with negative radius
-            let normal = (hit_position - self.center) / self.radius;
+    /// the hit result at `hit_position`, which must be on the surface
+    fn surface_hit(&self, parameter: f32, hit_position: Vec3) -> HitResult {
+        //divide by radius instead of .normalise() => can invert normals with negative radius
+        let normal = (hit_position - self.center) / self.radius;
 
-            let u = 1.0
-                - ((normal.z.atan2(normal.x) + std::f32::consts::PI)
-                    / (2.0 * std::f32::consts::PI));
+        let u = 1.0
+            - ((normal.z.atan2(normal.x) + std::f32::consts::PI) / (2.0 * std::f32::consts::PI));
 
-            //negative because our y axis (image) is flipped
-            let v = ((-normal.y).asin() + std::f32::consts::FRAC_PI_2) / std::f32::consts::PI;
+        //negative because our y axis (image) is flipped
+        let v = ((-normal.y).asin() + std::f32::consts::FRAC_PI_2) / std::f32::consts::PI;
 
-            Some(HitResult {
-                ray_param: parameter,
-                hit_position,
-                normal,
-                material: Some(self.material.clone()),
-                uv_coords: Some((u, v)),
-            })
+        HitResult {
+            ray_param: parameter,
+            hit_position,
+            normal,
+            material: Some(self.material.clone()),
+            uv_coords: Some((u, v)),
         }
     }
+}
+
+impl Hit for Sphere {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self) -> Option<AABB> {
         Some(AABB::new(
@@ -125,6 +142,60 @@ impl Hit for Sphere {
     fn center(&self) -> Vec3 {
         self.center
     }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let distance_squared = (self.center - origin).len_squared();
+        let radius_squared = self.radius * self.radius;
+        //from inside, the sphere is not sampled at all
+        if distance_squared <= radius_squared {
+            return 0.0;
+        }
+        if self
+            .intersect(&Ray::new(origin, direction), 0.0001, f32::MAX)
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        //uniform over the cone of directions the sphere covers
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_theta_max))
+    }
+
+    fn random_direction(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let to_center = self.center - origin;
+        let distance_squared = to_center.len_squared();
+        let radius_squared = self.radius * self.radius;
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).max(0.0).sqrt();
+
+        //uniform in the cone around the direction to the center
+        let (r1, r2) = sampler.next_2d();
+        let z = 1.0 + r2 * (cos_theta_max - 1.0);
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * r1;
+        let local = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z);
+
+        ONB::from_w(to_center.normalised()).to_local(local)
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius * self.radius
+    }
+
+    fn random_point(&self, sampler: &mut dyn Sampler) -> HitResult {
+        //uniform on the unit sphere
+        let (r1, r2) = sampler.next_2d();
+        let z = 1.0 - 2.0 * r2;
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * r1;
+        let direction = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z);
+
+        self.surface_hit(0.0, self.center + self.radius.abs() * direction)
+    }
 }
 
 /// represents a flat plane in 3d space
@@ -149,8 +220,9 @@ pub struct Triangle {
     pub material: Arc<dyn Material>,
 }
 
-impl Hit for Triangle {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
+impl Triangle {
+    /// the intersection of `ray` with the triangle, the same as `hit`
+    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
         // (x - llc) · normal = 0
         // x => ray(t) = origin + t * direction
         // (origin + t * direction - llc) · normal = 0
@@ -202,6 +274,18 @@ impl Hit for Triangle {
             })
         }
     }
+}
+
+impl Hit for Triangle {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self) -> Option<AABB> {
         let a = self.llc;
@@ -225,4 +309,51 @@ impl Hit for Triangle {
     fn center(&self) -> Vec3 {
         self.bounding_box().unwrap().center()
     }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let ray = Ray::new(origin, direction);
+        let hit = match self.intersect(&ray, 0.0001, f32::MAX) {
+            Some(hit) => hit,
+            None => return 0.0,
+        };
+
+        //uniform over the area, converted to solid angle
+        let cosine = hit.normal.dot(ray.direction).abs();
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        hit.ray_param * hit.ray_param / (cosine * self.area())
+    }
+
+    fn random_direction(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        //uniform point on the triangle
+        let (r1, r2) = sampler.next_2d();
+        let root = r1.sqrt();
+        let point = self.llc + root * (1.0 - r2) * self.span_a + root * r2 * self.span_b;
+
+        (point - origin).normalised()
+    }
+
+    fn area(&self) -> f32 {
+        0.5 * self.span_a.cross(self.span_b).len()
+    }
+
+    fn random_point(&self, sampler: &mut dyn Sampler) -> HitResult {
+        //the same point `random_direction` would aim at
+        let (r1, r2) = sampler.next_2d();
+        let root = r1.sqrt();
+        let (u, v) = (root * (1.0 - r2), root * r2);
+
+        HitResult {
+            ray_param: 0.0,
+            hit_position: self.llc + u * self.span_a + v * self.span_b,
+            normal: self.span_a.cross(self.span_b).normalised(),
+            material: Some(self.material.clone()),
+            uv_coords: Some((u, v)),
+        }
+    }
 }
@@ -1,10 +1,13 @@
-use crate::gfx::material::Material;
+use crate::gfx::bsdf::{Bsdf, BsdfSample, Bxdf, Lobes};
+use crate::gfx::material::{BounceKind, Material};
+use crate::gfx::spectrum::Wavelengths;
 use crate::gfx::texture::Texture;
 use crate::hit::Hit;
 use crate::hit::HitResult;
 use crate::hittables::aabb::AABB;
 use crate::math::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
 use std::sync::Arc;
 
 pub struct ConstantVolume {
@@ -24,13 +27,19 @@ impl ConstantVolume {
 }
 
 impl Hit for ConstantVolume {
-    fn hit(&self, ray: &Ray, _t_min: f32, _t_max: f32) -> Option<HitResult> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        _t_min: f32,
+        _t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
         let t_min = std::f32::MIN;
         let t_max = std::f32::MAX;
 
-        if let Some(hit1) = self.boundary.hit(ray, t_min, t_max) {
+        if let Some(hit1) = self.boundary.hit(ray, t_min, t_max, sampler) {
             let t_min = hit1.ray_param + 0.0001;
-            if let Some(hit2) = self.boundary.hit(ray, t_min, t_max) {
+            if let Some(hit2) = self.boundary.hit(ray, t_min, t_max, sampler) {
                 let t1 = hit1.ray_param.max(t_min);
                 let t2 = hit2.ray_param.min(t_max);
 
@@ -41,7 +50,7 @@ impl Hit for ConstantVolume {
                 let t1 = hit1.ray_param.max(0.0);
 
                 let distance = t2 - t1;
-                let hit_distance = -(1.0 / self.density) * rand::random::<f32>().ln();
+                let hit_distance = -(1.0 / self.density) * (1.0 - sampler.next_1d()).ln();
 
                 if hit_distance < distance {
                     let ray_param = t1 + hit_distance;
@@ -77,18 +86,48 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scattered(&self, _ray: &Ray, hit: &HitResult) -> Option<(Vec3, Vec3, Ray, f32)> {
+    fn bsdf(&self, _hit: &HitResult, _wavelengths: Option<Wavelengths>) -> Option<Bsdf> {
         let albedo = self.albedo.texture((0.0, 0.0));
-        let normal = hit.normal;
-        let scattered_ray = Ray::new(hit.hit_position, Vec3::random_in_unit_sphere());
+        //points inside volumes have no normal, the phase function looks the same in any frame
+        let frame_normal = Vec3::new(0.0, 0.0, 1.0);
+        Some(Bsdf::new(
+            frame_normal,
+            albedo,
+            Box::new(IsotropicPhase { albedo }),
+        ))
+    }
+    fn bounce_kind(&self, _hit: &HitResult) -> BounceKind {
+        BounceKind::Volume
+    }
+}
 
-        //1 over 4 pi, because generated randomly in unit sphere (area = 4pi)
-        let pdf = 1.0 / (4.0 * std::f32::consts::PI);
+/// scatters into every direction alike
+struct IsotropicPhase {
+    albedo: Vec3,
+}
 
-        Some((albedo, normal, scattered_ray, pdf))
+impl Bxdf for IsotropicPhase {
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.albedo * self.pdf(wo, wi)
     }
-    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitResult, _scattered_ray: &Ray) -> f32 {
+
+    fn sample(&self, _wo: Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let wi = Vec3::random_in_unit_sphere(sampler).normalised();
+        let pdf = self.pdf(wi, wi);
+        Some(BsdfSample {
+            wi,
+            f: self.albedo * pdf,
+            pdf,
+            lobes: Lobes::DIFFUSE | Lobes::REFLECTION | Lobes::TRANSMISSION,
+        })
+    }
+
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f32 {
         //1 over 4 pi, because chance to scatter was same in every direction
         1.0 / (4.0 * std::f32::consts::PI)
     }
+
+    fn lobes(&self) -> Lobes {
+        Lobes::DIFFUSE | Lobes::REFLECTION | Lobes::TRANSMISSION
+    }
 }
@@ -0,0 +1,48 @@
+use crate::film::Splat;
+use crate::hit::Hit;
+use crate::integrators::integrator::Integrator;
+use crate::math::pdf::{CosinePDF, PDF};
+use crate::math::vec3::Vec3;
+use crate::pathtracer::PathTracer;
+use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
+
+/// how much of the hemisphere above the surface the camera sees is not blocked by other objects
+/// white is fully open, black fully occluded; the sky counts as open
+pub struct AmbientOcclusion;
+
+impl Integrator for AmbientOcclusion {
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        _splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32) {
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let world = tracer.world();
+
+        sampler.start_vertex(0);
+        let hit = match world.hit(ray, 0.0001, f32::MAX, sampler) {
+            Some(hit) => hit,
+            None => return (white, white, -ray.direction, 0.0, 1),
+        };
+
+        //the side of the surface the ray came from
+        let normal = if hit.normal.dot(ray.direction) > 0.0 {
+            -hit.normal
+        } else {
+            hit.normal
+        };
+
+        //cosine weighted, so the fraction of unblocked rays is the occlusion, no weights needed
+        let direction = CosinePDF::new(normal).generate(sampler);
+        let occlusion_ray = Ray::new(hit.hit_position, direction);
+        let color = match world.hit(&occlusion_ray, 0.0001, f32::MAX, sampler) {
+            Some(_) => Vec3::new(0.0, 0.0, 0.0),
+            None => white,
+        };
+
+        (color, white, normal, 1.0 / hit.ray_param, 2)
+    }
+}
@@ -0,0 +1,619 @@
+use crate::film::Splat;
+use crate::gfx::bsdf::Bsdf;
+use crate::gfx::material::BounceKind;
+use crate::hit::{Hit, HitResult};
+use crate::integrators::integrator::Integrator;
+use crate::math::pdf::{CosinePDF, MisHeuristic, PDF};
+use crate::math::vec3::Vec3;
+use crate::pathtracer::{BounceLimits, PathTracer};
+use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
+
+/// keeps rays from hitting the surface they start on, or the one they are connected to
+const EPSILON: f32 = 0.001;
+
+/// what a vertex of a subpath is
+enum VertexKind {
+    /// a point on the lens
+    Camera,
+    /// the first vertex of a light subpath, a point on a light emitting `emitted`,
+    /// at the wavelengths of the path in spectral mode
+    Light { emitted: Vec3 },
+    /// a point where `ray` hit a surface or scattered inside a volume
+    Surface {
+        hit: HitResult,
+        ray: Ray,
+        /// how it scatters, None if it does not
+        bsdf: Option<Bsdf>,
+        /// the density with which sampling the lights picks this point, 0 if it is no light
+        light_pdf: f32,
+    },
+}
+
+/// a vertex of a camera or light subpath
+/// all densities are per area, or per volume for vertices inside volumes
+struct Vertex {
+    kind: VertexKind,
+    point: Vec3,
+    normal: Vec3,
+    /// the throughput from the start of the subpath to this vertex
+    beta: Vec3,
+    /// the density of sampling this vertex from the previous one of its subpath
+    pdf_fwd: f32,
+    /// the density of sampling this vertex from the next one, as if the path was made
+    /// from the other end
+    pdf_rev: f32,
+    /// the material only scatters into a single direction, nothing can be connected to it
+    delta: bool,
+}
+
+impl Vertex {
+    /// a vertex on the lens, `lens_pdf` is the density with which it was picked
+    fn camera(point: Vec3, tracer: &PathTracer, beta: Vec3, lens_pdf: f32) -> Self {
+        Vertex {
+            kind: VertexKind::Camera,
+            point,
+            normal: tracer.camera.direction,
+            beta,
+            pdf_fwd: lens_pdf,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    /// true if the densities of this vertex are per area, they are per volume inside volumes
+    fn is_on_surface(&self) -> bool {
+        match &self.kind {
+            VertexKind::Camera => false,
+            VertexKind::Light { .. } => true,
+            VertexKind::Surface { hit, .. } => match &hit.material {
+                Some(mat) => mat.bounce_kind(hit) != BounceKind::Volume,
+                None => true,
+            },
+        }
+    }
+
+    /// true if the bsdf here scatters each wavelength differently
+    fn is_dispersive(&self) -> bool {
+        matches!(&self.kind, VertexKind::Surface { bsdf: Some(bsdf), .. } if bsdf.is_dispersive())
+    }
+
+    /// the light emitted at this vertex, the same into every direction
+    fn emitted(&self) -> Vec3 {
+        match &self.kind {
+            VertexKind::Light { emitted } => *emitted,
+            VertexKind::Surface { hit, ray, .. } => match &hit.material {
+                Some(mat) => ray.illuminant(mat.emitted(hit)),
+                None => Vec3::new(0.0, 0.0, 0.0),
+            },
+            VertexKind::Camera => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// how much of what arrives from `direction` is sent on, including the cosine
+    /// for the camera, this is its importance: the lens pdf times the direction pdf
+    fn f_cos(&self, tracer: &PathTracer, direction: Vec3) -> Vec3 {
+        match &self.kind {
+            VertexKind::Camera => {
+                let camera = &tracer.camera;
+                let importance = camera.lens_pdf() * camera.direction_pdf(self.point, direction);
+                Vec3::new(importance, importance, importance)
+            }
+            VertexKind::Light { .. } => {
+                let cosine = self.normal.dot(direction.normalised()).abs();
+                Vec3::new(cosine, cosine, cosine)
+            }
+            VertexKind::Surface {
+                ray,
+                bsdf: Some(bsdf),
+                ..
+            } if !self.delta => ray.spectrum(bsdf.eval(-ray.direction, direction)),
+            VertexKind::Surface { .. } => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// the density of sampling `next` from this vertex
+    fn pdf_to(&self, tracer: &PathTracer, next: &Vertex) -> f32 {
+        let direction = next.point - self.point;
+        let pdf = match &self.kind {
+            VertexKind::Camera => tracer.camera.direction_pdf(self.point, direction),
+            VertexKind::Light { .. } => emission_pdf(self.normal, direction),
+            VertexKind::Surface {
+                ray,
+                bsdf: Some(bsdf),
+                ..
+            } if !self.delta => bsdf.pdf(-ray.direction, direction),
+            VertexKind::Surface { .. } => 0.0,
+        };
+        to_area(pdf, self, next)
+    }
+
+    /// the density of sampling `next` from this vertex, if the path had arrived from `previous`
+    /// instead of where it did; only materials that reflect like lambertians do not care
+    fn pdf_from_to(&self, tracer: &PathTracer, previous: &Vertex, next: &Vertex) -> f32 {
+        let pdf = match &self.kind {
+            VertexKind::Surface {
+                bsdf: Some(bsdf), ..
+            } if !self.delta => bsdf.pdf(previous.point - self.point, next.point - self.point),
+            VertexKind::Surface { .. } => 0.0,
+            _ => return self.pdf_to(tracer, next),
+        };
+        to_area(pdf, self, next)
+    }
+
+    /// the density of a light at this vertex emitting towards `next`
+    fn emission_pdf_to(&self, next: &Vertex) -> f32 {
+        to_area(
+            emission_pdf(self.normal, next.point - self.point),
+            self,
+            next,
+        )
+    }
+
+    /// the density of sampling this vertex as the start of a light subpath
+    fn light_pdf(&self) -> f32 {
+        match &self.kind {
+            VertexKind::Light { .. } => self.pdf_fwd,
+            VertexKind::Surface { light_pdf, .. } => *light_pdf,
+            VertexKind::Camera => 0.0,
+        }
+    }
+}
+
+/// converts the density `pdf` per solid angle of `from` sampling the direction to `to`
+/// into a density per area at `to`
+fn to_area(pdf: f32, from: &Vertex, to: &Vertex) -> f32 {
+    let offset = to.point - from.point;
+    let distance_squared = offset.len_squared();
+    if distance_squared == 0.0 {
+        return 0.0;
+    }
+    if to.is_on_surface() {
+        pdf * to.normal.dot(offset).abs() / (distance_squared * distance_squared.sqrt())
+    } else {
+        pdf / distance_squared
+    }
+}
+
+/// lights emit to both sides, cosine weighted
+fn emission_pdf(normal: Vec3, direction: Vec3) -> f32 {
+    0.5 * normal.dot(direction.normalised()).abs() / std::f32::consts::PI
+}
+
+/// bidirectional path tracing: one subpath is started at the camera, another one at a light,
+/// and every vertex of the one is connected to every vertex of the other
+/// all the ways a path could have been made are weighted against each other,
+/// connections to the camera can land on any pixel and are splatted
+pub struct BdptIntegrator {
+    /// no path is longer than the total, no subpath bounces more often than the limit of a kind
+    bounce_limits: BounceLimits,
+    /// after how many bounces subpaths may be ended by russian roulette
+    roulette_depth: u32,
+    /// combines the different ways of making the same path
+    mis: MisHeuristic,
+}
+
+impl BdptIntegrator {
+    pub fn new(bounce_limits: BounceLimits, roulette_depth: u32, mis: MisHeuristic) -> Self {
+        BdptIntegrator {
+            bounce_limits,
+            roulette_depth,
+            mis,
+        }
+    }
+
+    /// picks a light by its power and a point on it uniformly
+    /// it emits at the wavelengths of `ray`, the camera ray
+    fn sample_light(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Vertex> {
+        let (hit, pdf) = tracer.sample_light_point(sampler)?;
+        let emitted = match &hit.material {
+            Some(mat) => ray.illuminant(mat.emitted(&hit)),
+            None => return None,
+        };
+
+        Some(Vertex {
+            kind: VertexKind::Light { emitted },
+            point: hit.hit_position,
+            normal: hit.normal,
+            beta: emitted / pdf,
+            pdf_fwd: pdf,
+            pdf_rev: 0.0,
+            delta: false,
+        })
+    }
+
+    /// extends `path` by tracing `ray` and scattering until it has `max_vertices` vertices,
+    /// it bounces too often of a kind or russian roulette ends it
+    /// `beta` is the throughput of `ray`, which was sampled with the density `pdf` per solid angle
+    /// returns the throughput and direction of the last ray if it escaped into the sky
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        tracer: &PathTracer,
+        mut ray: Ray,
+        mut beta: Vec3,
+        mut pdf: f32,
+        max_vertices: usize,
+        path: &mut Vec<Vertex>,
+        sampler: &mut dyn Sampler,
+        rays: &mut u32,
+    ) -> Option<(Vec3, Vec3)> {
+        //camera subpaths start with the camera vertex 0, light subpaths with the light vertex 0,
+        //their vertices use alternating blocks of dimensions
+        let side = match path[0].kind {
+            VertexKind::Camera => 0,
+            _ => 1,
+        };
+        let mut kind_bounces = [0u32; 4];
+        //the throughput of the scattering alone, without what the subpath started with
+        let mut attenuation = Vec3::new(1.0, 1.0, 1.0);
+
+        while path.len() < max_vertices {
+            sampler.start_vertex(2 * path.len() as u32 + side);
+            *rays += 1;
+            let hit = match tracer.world().hit(&ray, 0.0001, f32::MAX, sampler) {
+                Some(hit) => hit,
+                None => return Some((beta, ray.direction)),
+            };
+            let mat = hit
+                .material
+                .clone()
+                .expect("How did you manage to not have a material?!");
+
+            let light_pdf = if mat.is_emissive() {
+                tracer.lights().point_pdf(&ray, &hit, sampler)
+            } else {
+                0.0
+            };
+
+            //a vertex of a kind that bounced too often can only be hit, not scattered at
+            let kind = mat.bounce_kind(&hit);
+            let bsdf = if kind_bounces[kind as usize] < self.bounce_limits.of_kind(kind) {
+                kind_bounces[kind as usize] += 1;
+                mat.bsdf(&hit, ray.wavelengths)
+            } else {
+                None
+            };
+
+            //scatter even at the last vertex, nothing can be connected to specular bounces
+            let sample = bsdf
+                .as_ref()
+                .and_then(|bsdf| bsdf.sample(-ray.direction, sampler));
+            let scattered_ray = sample.map(|sample| hit.spawn_ray(sample.wi));
+
+            let mut vertex = Vertex {
+                point: hit.hit_position,
+                normal: hit.normal,
+                beta,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+                delta: sample.is_some_and(|sample| sample.lobes.is_specular()),
+                kind: VertexKind::Surface {
+                    hit,
+                    ray,
+                    bsdf,
+                    light_pdf,
+                },
+            };
+            let previous = path.len() - 1;
+            vertex.pdf_fwd = to_area(pdf, &path[previous], &vertex);
+
+            let (sample, scattered_ray) = match (sample, scattered_ray) {
+                (Some(sample), Some(scattered_ray)) => (sample, scattered_ray),
+                _ => {
+                    path.push(vertex);
+                    break;
+                }
+            };
+
+            //the density of scattering back towards the previous vertex
+            let factor = ray.spectrum(sample.f) / sample.pdf;
+            beta *= factor;
+            attenuation *= factor;
+            let reverse_pdf = if vertex.delta {
+                pdf = 0.0;
+                0.0
+            } else {
+                pdf = sample.pdf;
+                match &vertex.kind {
+                    //as if the path had arrived from where it scattered to
+                    VertexKind::Surface {
+                        bsdf: Some(bsdf), ..
+                    } => bsdf.pdf(sample.wi, -ray.direction),
+                    _ => 0.0,
+                }
+            };
+            path[previous].pdf_rev = to_area(reverse_pdf, &vertex, &path[previous]);
+
+            path.push(vertex);
+            ray = scattered_ray.with_wavelengths(ray.wavelengths);
+
+            //russian roulette: subpaths that can not contribute much anymore are likely to end,
+            //the surviving ones are brightened to make up for them
+            if path.len() - 1 > self.roulette_depth as usize {
+                let survival = attenuation.max_component().min(0.95);
+                if sampler.next_1d() >= survival {
+                    break;
+                }
+                beta /= survival;
+                attenuation /= survival;
+            }
+        }
+        None
+    }
+
+    /// connects the first `s` vertices of the light subpath with the first `t` of the camera subpath
+    /// `s` = 1 and `t` = 1 sample a new point on a light or on the lens
+    /// # Return Value
+    /// the weighted contribution, and the pixel it lands on for `t` = 1
+    #[allow(clippy::too_many_arguments)]
+    fn connect(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+        sampler: &mut dyn Sampler,
+        rays: &mut u32,
+    ) -> Option<(Vec3, Option<(f32, f32)>)> {
+        //the camera subpath found a light by itself
+        if s == 0 {
+            let emitted = camera_path[t - 1].emitted();
+            if emitted.len_squared() == 0.0 {
+                return None;
+            }
+            let weight = self.mis_weight(tracer, &[], &camera_path[..t]);
+            return Some((camera_path[t - 1].beta * emitted * weight, None));
+        }
+
+        let sampled_light;
+        let light_path = if s == 1 {
+            sampled_light = [self.sample_light(tracer, ray, sampler)?];
+            &sampled_light[..]
+        } else {
+            &light_path[..s]
+        };
+        let qs = &light_path[s - 1];
+
+        let sampled_camera;
+        let (camera_path, raster) = if t == 1 {
+            let (lens, lens_pdf) = tracer.camera.sample_lens(sampler);
+            let raster = tracer.camera.raster_position(lens, qs.point - lens)?;
+            let beta = Vec3::new(1.0, 1.0, 1.0) / lens_pdf;
+            sampled_camera = [Vertex::camera(lens, tracer, beta, lens_pdf)];
+            (&sampled_camera[..], Some(raster))
+        } else {
+            (&camera_path[..t], None)
+        };
+        let pt = &camera_path[t - 1];
+
+        if qs.delta || pt.delta {
+            return None;
+        }
+
+        let offset = pt.point - qs.point;
+        let distance_squared = offset.len_squared();
+        let contribution = qs.beta * qs.f_cos(tracer, offset) * pt.f_cos(tracer, -offset) * pt.beta
+            / distance_squared;
+        if contribution.max_component() <= 0.0 || distance_squared == 0.0 {
+            return None;
+        }
+
+        //whatever is in between casts a shadow
+        *rays += 1;
+        let distance = distance_squared.sqrt();
+        let shadow_ray = Ray::new(qs.point, offset);
+        if tracer
+            .world()
+            .hit(&shadow_ray, EPSILON, distance - EPSILON, sampler)
+            .is_some()
+        {
+            return None;
+        }
+
+        let weight = self.mis_weight(tracer, light_path, camera_path);
+        Some((contribution * weight, raster))
+    }
+
+    /// the weight of connecting `light_path` and `camera_path` against all other ways
+    /// of making the same path with subpaths of other lengths
+    fn mis_weight(
+        &self,
+        tracer: &PathTracer,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+    ) -> f32 {
+        let (s, t) = (light_path.len(), camera_path.len());
+
+        //(pdf_fwd, pdf_rev, delta) of every vertex, with the reverse densities of the
+        //vertices next to the connection changed to how they would be sampled across it
+        let pdfs = |path: &[Vertex]| -> Vec<(f32, f32, bool)> {
+            path.iter()
+                .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+                .collect()
+        };
+        let mut camera_pdfs = pdfs(camera_path);
+        let mut light_pdfs = pdfs(light_path);
+
+        let pt = &camera_path[t - 1];
+        camera_pdfs[t - 1].2 = false;
+        if s > 0 {
+            let qs = &light_path[s - 1];
+            light_pdfs[s - 1].2 = false;
+            camera_pdfs[t - 1].1 = qs.pdf_to(tracer, pt);
+            if t > 1 {
+                camera_pdfs[t - 2].1 = pt.pdf_from_to(tracer, qs, &camera_path[t - 2]);
+            }
+            light_pdfs[s - 1].1 = pt.pdf_to(tracer, qs);
+            if s > 1 {
+                light_pdfs[s - 2].1 = qs.pdf_from_to(tracer, pt, &light_path[s - 2]);
+            }
+        } else {
+            camera_pdfs[t - 1].1 = pt.light_pdf();
+            if t > 1 {
+                camera_pdfs[t - 2].1 = pt.emission_pdf_to(&camera_path[t - 2]);
+            }
+        }
+
+        //densities of 0 come from delta vertices, those strategies are skipped anyway
+        let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+        let heuristic = |ratio: f32| match self.mis {
+            MisHeuristic::Balance => ratio,
+            MisHeuristic::Power => ratio * ratio,
+        };
+
+        //the ratio of the density of a strategy to the one of this strategy,
+        //moving the connection towards the camera and then towards the light
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+            if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+                sum += heuristic(ratio);
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+            let previous_delta = i > 0 && light_pdfs[i - 1].2;
+            if !light_pdfs[i].2 && !previous_delta {
+                sum += heuristic(ratio);
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Integrator for BdptIntegrator {
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32) {
+        let max_depth = self.bounce_limits.total as usize;
+        let mut rays = 0;
+        let mut color = Vec3::new(0.0, 0.0, 0.0);
+
+        //the camera subpath: the lens, then up to max_depth + 1 hits
+        let camera = &tracer.camera;
+        let mut camera_path = Vec::with_capacity(max_depth + 2);
+        camera_path.push(Vertex::camera(
+            ray.origin,
+            tracer,
+            Vec3::new(1.0, 1.0, 1.0),
+            camera.lens_pdf(),
+        ));
+        let pdf = camera.direction_pdf(ray.origin, ray.direction);
+        let escaped = self.random_walk(
+            tracer,
+            *ray,
+            Vec3::new(1.0, 1.0, 1.0),
+            pdf,
+            max_depth + 2,
+            &mut camera_path,
+            sampler,
+            &mut rays,
+        );
+        //only camera paths can find the sky
+        if let Some((beta, direction)) = escaped {
+            color += beta * ray.illuminant(tracer.sky_color(direction));
+        }
+
+        //the light subpath: a point on a light, then up to max_depth hits
+        let mut light_path = Vec::with_capacity(max_depth + 1);
+        sampler.start_vertex(1);
+        if let Some(light) = self.sample_light(tracer, ray, sampler) {
+            let normal = if sampler.next_1d() < 0.5 {
+                light.normal
+            } else {
+                -light.normal
+            };
+            let direction = CosinePDF::new(normal).generate(sampler);
+            let pdf = emission_pdf(normal, direction);
+            let beta = light.beta * light.f_cos(tracer, direction) / pdf;
+            let light_ray = Ray::new(light.point, direction).with_wavelengths(ray.wavelengths);
+
+            light_path.push(light);
+            if pdf > 0.0 {
+                self.random_walk(
+                    tracer,
+                    light_ray,
+                    beta,
+                    pdf,
+                    max_depth + 1,
+                    &mut light_path,
+                    sampler,
+                    &mut rays,
+                );
+            }
+        }
+
+        //once either subpath split up by wavelength, all of them only hold for the hero
+        let dispersed = camera_path
+            .iter()
+            .chain(&light_path)
+            .any(Vertex::is_dispersive);
+
+        //every connection of a camera and a light vertex, with no more than max_depth bounces
+        sampler.start_vertex(2 * (max_depth as u32 + 2));
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || s + t - 2 > max_depth {
+                    continue;
+                }
+                let connection = self.connect(
+                    tracer,
+                    ray,
+                    &light_path,
+                    &camera_path,
+                    s,
+                    t,
+                    sampler,
+                    &mut rays,
+                );
+                match connection {
+                    Some((contribution, Some((x, y)))) if dispersed => splats.push(Splat {
+                        x: x as u32,
+                        y: y as u32,
+                        color: ray.to_rgb(ray.terminate_secondary(contribution)),
+                    }),
+                    Some((contribution, Some((x, y)))) => splats.push(Splat {
+                        x: x as u32,
+                        y: y as u32,
+                        color: ray.to_rgb(contribution),
+                    }),
+                    Some((contribution, None)) => color += contribution,
+                    None => {}
+                }
+            }
+        }
+
+        //the auxiliary buffers get the first surface the camera sees
+        let (albedo, normal, depth) = match camera_path.get(1).map(|v| &v.kind) {
+            Some(VertexKind::Surface { hit, bsdf, .. }) => match (bsdf, &hit.material) {
+                (Some(bsdf), _) => (bsdf.albedo(), bsdf.normal(), 1.0 / hit.ray_param),
+                //lights do not scatter, they show what they emit
+                (None, Some(mat)) => (mat.emitted(hit), hit.normal, 1.0 / hit.ray_param),
+                (None, None) => (Vec3::new(0.0, 0.0, 0.0), hit.normal, 1.0 / hit.ray_param),
+            },
+            _ => {
+                let sky = tracer.sky_color(ray.direction);
+                (sky, -ray.direction, 0.0)
+            }
+        };
+
+        if dispersed {
+            color = ray.terminate_secondary(color);
+        }
+        (color, albedo, normal, depth, rays)
+    }
+}
@@ -0,0 +1,43 @@
+use crate::film::{self, Splat};
+use crate::hit::Hit;
+use crate::integrators::integrator::Integrator;
+use crate::math::vec3::Vec3;
+use crate::pathtracer::PathTracer;
+use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
+
+/// shows how expensive the camera rays are to trace,
+/// from blue (nothing tested) to red (`max_cost` or more bounding boxes and objects tested)
+pub struct BvhHeatmap {
+    max_cost: u32,
+}
+
+impl BvhHeatmap {
+    pub fn new(max_cost: u32) -> Self {
+        BvhHeatmap {
+            max_cost: max_cost.max(1),
+        }
+    }
+}
+
+impl Integrator for BvhHeatmap {
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        _splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32) {
+        sampler.start_vertex(0);
+        let world = tracer.world();
+
+        let cost = world.traversal_cost(ray, 0.0001, f32::MAX, sampler);
+        let color = film::heat_color(cost as f32 / self.max_cost as f32);
+
+        //the heatmap is the albedo too, the denoiser should keep it as it is
+        match world.hit(ray, 0.0001, f32::MAX, sampler) {
+            Some(hit) => (color, color, hit.normal, 1.0 / hit.ray_param, 1),
+            None => (color, color, -ray.direction, 0.0, 1),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::film::Splat;
+use crate::hit::Hit;
+use crate::integrators::integrator::{normal_color, Integrator};
+use crate::math::vec3::Vec3;
+use crate::pathtracer::PathTracer;
+use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
+
+/// shows the albedo of the surfaces the camera sees, lights show their emitted color
+pub struct AlbedoIntegrator;
+
+impl Integrator for AlbedoIntegrator {
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        _splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32) {
+        sampler.start_vertex(0);
+        let hit = match tracer.world().hit(ray, 0.0001, f32::MAX, sampler) {
+            Some(hit) => hit,
+            None => {
+                let sky = tracer.sky_color(ray.direction);
+                return (sky, sky, -ray.direction, 0.0, 1);
+            }
+        };
+
+        let mat = hit
+            .material
+            .as_ref()
+            .expect("How did you manage to not have a material?!");
+
+        //lights do not scatter, they show what they emit
+        let (albedo, normal) = match mat.bsdf(&hit, None) {
+            Some(bsdf) => (bsdf.albedo(), bsdf.normal()),
+            None => (mat.emitted(&hit), hit.normal),
+        };
+
+        (albedo, albedo, normal, 1.0 / hit.ray_param, 1)
+    }
+}
+
+/// shows the normals of the surfaces the camera sees, mapped from [-1, 1] to colors
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator {
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        _splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32) {
+        sampler.start_vertex(0);
+        match tracer.world().hit(ray, 0.0001, f32::MAX, sampler) {
+            Some(hit) => {
+                let color = normal_color(hit.normal);
+                (color, color, hit.normal, 1.0 / hit.ray_param, 1)
+            }
+            None => {
+                let color = normal_color(-ray.direction);
+                (color, color, -ray.direction, 0.0, 1)
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::film::Splat;
+use crate::math::vec3::Vec3;
+use crate::pathtracer::PathTracer;
+use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// a light transport algorithm, computes what a camera ray sees
+pub trait Integrator: Send + Sync {
+    /// traces one sample through the scene of `tracer`
+    /// light that reaches the camera on another pixel than the one of `ray` is added to `splats`
+    /// in spectral mode, `ray` carries wavelengths and the returned color holds the light at them,
+    /// the splats are converted to rgb already
+    /// # Return Value
+    /// Returns Tuple of (Color, Albedo, Normal, Depth, number of traced rays)
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32);
+}
+
+/// the available integrators, selected in the scene settings, on the command line
+/// or cycled through in the preview window
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegratorKind {
+    /// unidirectional path tracing
+    #[default]
+    Path,
+    /// bidirectional path tracing, connects paths from the camera with paths from the lights
+    Bidirectional,
+    /// primary sample space Metropolis light transport, explores bright paths once found
+    Metropolis,
+    /// progressive photon mapping, photons from the lights bring the indirect light and caustics;
+    /// the sky only lights surfaces directly
+    PhotonMapping,
+    /// only light that reaches the camera after at most one bounce
+    Direct,
+    AmbientOcclusion,
+    /// the albedo of the surfaces the camera sees, without any lighting
+    Albedo,
+    /// the normals of the surfaces the camera sees, mapped to colors
+    Normal,
+    /// how many bounding boxes and objects a camera ray is tested against
+    BvhHeatmap,
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(IntegratorKind::Path),
+            "bidirectional" => Ok(IntegratorKind::Bidirectional),
+            "metropolis" => Ok(IntegratorKind::Metropolis),
+            "photon_mapping" => Ok(IntegratorKind::PhotonMapping),
+            "direct" => Ok(IntegratorKind::Direct),
+            "ambient_occlusion" => Ok(IntegratorKind::AmbientOcclusion),
+            "albedo" => Ok(IntegratorKind::Albedo),
+            "normal" => Ok(IntegratorKind::Normal),
+            "bvh_heatmap" => Ok(IntegratorKind::BvhHeatmap),
+            _ => Err(format!(
+                "unknown integrator '{}', expected path, bidirectional, metropolis, photon_mapping, direct, ambient_occlusion, albedo, normal or bvh_heatmap",
+                s
+            )),
+        }
+    }
+}
+
+impl IntegratorKind {
+    /// true if the integrator computes light transport, only those can trace wavelengths
+    pub fn transports_light(self) -> bool {
+        match self {
+            IntegratorKind::Path
+            | IntegratorKind::Bidirectional
+            | IntegratorKind::Metropolis
+            | IntegratorKind::PhotonMapping
+            | IntegratorKind::Direct => true,
+            IntegratorKind::AmbientOcclusion
+            | IntegratorKind::Albedo
+            | IntegratorKind::Normal
+            | IntegratorKind::BvhHeatmap => false,
+        }
+    }
+
+    /// true if light reaches the pixels through splats, which the statistics of the pixels do not
+    /// see: those can not tell how noisy the image is, for adaptive sampling or a noise target
+    pub fn splats(self) -> bool {
+        matches!(
+            self,
+            IntegratorKind::Bidirectional | IntegratorKind::Metropolis
+        )
+    }
+
+    /// the integrator after this one, wraps around
+    pub fn next(self) -> Self {
+        match self {
+            IntegratorKind::Path => IntegratorKind::Bidirectional,
+            IntegratorKind::Bidirectional => IntegratorKind::Metropolis,
+            IntegratorKind::Metropolis => IntegratorKind::PhotonMapping,
+            IntegratorKind::PhotonMapping => IntegratorKind::Direct,
+            IntegratorKind::Direct => IntegratorKind::AmbientOcclusion,
+            IntegratorKind::AmbientOcclusion => IntegratorKind::Albedo,
+            IntegratorKind::Albedo => IntegratorKind::Normal,
+            IntegratorKind::Normal => IntegratorKind::BvhHeatmap,
+            IntegratorKind::BvhHeatmap => IntegratorKind::Path,
+        }
+    }
+}
+
+/// maps a normal from [-1, 1] to a color in [0, 1]
+pub fn normal_color(normal: Vec3) -> Vec3 {
+    0.5 * (normal + Vec3::new(1.0, 1.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_integrators() {
+        //cycling visits every integrator once before coming back
+        let mut kind = IntegratorKind::default();
+        let mut seen = vec![];
+        loop {
+            seen.push(kind);
+            kind = kind.next();
+            if kind == IntegratorKind::default() {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 9);
+
+        assert_eq!(
+            "ambient_occlusion".parse::<IntegratorKind>(),
+            Ok(IntegratorKind::AmbientOcclusion)
+        );
+        assert!("whitted".parse::<IntegratorKind>().is_err());
+    }
+}
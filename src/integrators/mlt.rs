@@ -0,0 +1,259 @@
+use crate::film::Splat;
+use crate::integrators::debug::AlbedoIntegrator;
+use crate::integrators::integrator::Integrator;
+use crate::integrators::path::PathIntegrator;
+use crate::math::distribution::Distribution1D;
+use crate::math::random;
+use crate::math::vec3::Vec3;
+use crate::pathtracer::PathTracer;
+use crate::ray::Ray;
+use crate::sampling::mlt::MltSampler;
+use crate::sampling::sampler::Sampler;
+use crate::tiles::Tile;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// how many paths are traced at least every frame to estimate the brightness of the image,
+/// the chains start on those of the first frame
+const BOOTSTRAP_SAMPLES: u32 = 1024;
+/// standard deviation of a small step in primary sample space
+const SIGMA: f32 = 0.01;
+/// how often all random numbers of a path are replaced instead of moved a little
+const LARGE_STEP_PROBABILITY: f32 = 0.3;
+
+/// a path of the chain: where it lands on the image, and the light it carries
+#[derive(Copy, Clone)]
+struct ChainState {
+    x: f32,
+    y: f32,
+    color: Vec3,
+}
+
+/// a Markov chain over the random numbers of paths
+#[derive(Clone)]
+struct Chain {
+    sampler: MltSampler,
+    current: ChainState,
+}
+
+/// makes a path from the numbers of `sampler`, the first two pick the point on the image
+fn sample_path(
+    tracer: &PathTracer,
+    path: &PathIntegrator,
+    sampler: &mut MltSampler,
+) -> (ChainState, u32) {
+    let (u, v) = sampler.next_2d();
+    let x = u * tracer.width() as f32;
+    let y = v * tracer.height() as f32;
+    let ray = tracer.camera.get_ray(x, y, sampler);
+    let ray = ray.with_wavelengths(tracer.sample_wavelengths(sampler));
+
+    //the path integrator only adds to the pixel it was called for
+    let (color, _, _, _, rays) = path.trace(tracer, &ray, sampler, &mut Vec::new());
+    let color = ray.to_rgb(color);
+    (ChainState { x, y, color }, rays)
+}
+
+/// the chains of the Metropolis integrator, they are kept over the frames
+/// so they can stay on the bright paths they found
+/// every tile has its own chain, the image does not depend on the order the tiles are rendered in
+pub struct MltChains {
+    seed: u64,
+    /// the luminance of every bootstrap path, chains start on them as likely as they are bright
+    bootstrap: Distribution1D,
+    /// the average luminance of all paths, the chains only know how bright paths are
+    /// relative to each other. refined every frame, the estimate of a single frame would
+    /// scale the whole image by its error
+    brightness: f32,
+    /// the number of bootstrap paths `brightness` averages
+    bootstrap_paths: u64,
+    /// by the corner of their tile, those being rendered are taken out
+    chains: Mutex<HashMap<(u32, u32), Chain>>,
+}
+
+impl Clone for MltChains {
+    fn clone(&self) -> Self {
+        MltChains {
+            seed: self.seed,
+            bootstrap: self.bootstrap.clone(),
+            brightness: self.brightness,
+            bootstrap_paths: self.bootstrap_paths,
+            chains: Mutex::new(self.chains.lock().unwrap().clone()),
+        }
+    }
+}
+
+impl MltChains {
+    /// traces the bootstrap paths that estimate the brightness of the image
+    /// # Return Value
+    /// the chains, none started yet, and the number of traced rays
+    pub fn bootstrap(tracer: &PathTracer, path: &PathIntegrator, seed: u64) -> (Self, u64) {
+        let (luminances, rays) = Self::trace_bootstrap(tracer, path, seed, 0);
+        let chains = MltChains {
+            seed,
+            brightness: luminances.iter().sum::<f32>() / luminances.len() as f32,
+            bootstrap_paths: luminances.len() as u64,
+            bootstrap: Distribution1D::new(luminances),
+            chains: Mutex::new(HashMap::new()),
+        };
+        (chains, rays)
+    }
+
+    /// traces more bootstrap paths for `frame` and adds them to the brightness
+    /// returns the number of traced rays
+    pub fn refine(&mut self, tracer: &PathTracer, path: &PathIntegrator, frame: u32) -> u64 {
+        let (luminances, rays) = Self::trace_bootstrap(tracer, path, self.seed, frame);
+        let paths = self.bootstrap_paths + luminances.len() as u64;
+        let sum = self.brightness as f64 * self.bootstrap_paths as f64
+            + luminances.iter().map(|l| *l as f64).sum::<f64>();
+        self.brightness = (sum / paths as f64) as f32;
+        self.bootstrap_paths = paths;
+        rays
+    }
+
+    /// the luminance of the bootstrap paths of `frame`, 0 for the first one,
+    /// and the number of traced rays
+    fn trace_bootstrap(
+        tracer: &PathTracer,
+        path: &PathIntegrator,
+        seed: u64,
+        frame: u32,
+    ) -> (Vec<f32>, u64) {
+        //a small fraction of the paths a frame traces anyway
+        let count = BOOTSTRAP_SAMPLES.max(tracer.width() * tracer.height() / 16);
+        let mut rays = 0;
+        let mut luminances = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let mut sampler = Self::bootstrap_sampler(seed, frame, index as usize);
+            let (state, traced) = sample_path(tracer, path, &mut sampler);
+            luminances.push(state.color.luminance().max(0.0));
+            rays += traced as u64;
+        }
+        (luminances, rays)
+    }
+
+    /// the seed makes the same path again
+    fn bootstrap_sampler(seed: u64, frame: u32, index: usize) -> MltSampler {
+        MltSampler::new(
+            random::hash(&[seed, frame as u64, index as u64]),
+            SIGMA,
+            LARGE_STEP_PROBABILITY,
+        )
+    }
+
+    /// takes the chain of `tile` out, or starts it on one of the bootstrap paths
+    /// # Return Value
+    /// the chain and the number of traced rays
+    fn take(&self, tracer: &PathTracer, path: &PathIntegrator, tile: (u32, u32)) -> (Chain, u32) {
+        if let Some(chain) = self.chains.lock().unwrap().remove(&tile) {
+            return (chain, 0);
+        }
+
+        let u = random::hash(&[self.seed, tile.0 as u64, tile.1 as u64]) >> 40;
+        let (_, _, chosen) = self.bootstrap.sample(u as f32 / (1u64 << 24) as f32);
+        let mut sampler = Self::bootstrap_sampler(self.seed, 0, chosen);
+        let (current, rays) = sample_path(tracer, path, &mut sampler);
+        (Chain { sampler, current }, rays)
+    }
+}
+
+/// primary sample space Metropolis light transport
+///
+/// every pixel sample mutates the random numbers of the last path of a Markov chain,
+/// the chain visits paths as often as they are bright: paths that are hard to find,
+/// like light through a small opening, are explored once found instead of found again by chance.
+/// the paths are made by the path integrator, their light lands anywhere on the image and is splatted.
+/// every tile continues its own chain of `MltChains`, it is returned once the tile is done
+pub struct MltIntegrator<'a> {
+    path: PathIntegrator,
+    chains: &'a MltChains,
+    /// the corner of the tile
+    tile: (u32, u32),
+    /// taken out of `chains` by the first sample
+    chain: Mutex<Option<Chain>>,
+}
+
+impl<'a> MltIntegrator<'a> {
+    pub fn new(path: PathIntegrator, chains: &'a MltChains, tile: &Tile) -> Self {
+        MltIntegrator {
+            path,
+            chains,
+            tile: (tile.x, tile.y),
+            chain: Mutex::new(None),
+        }
+    }
+
+    fn splat(tracer: &PathTracer, state: &ChainState, color: Vec3, splats: &mut Vec<Splat>) {
+        splats.push(Splat {
+            x: (state.x as u32).min(tracer.width() - 1),
+            y: (state.y as u32).min(tracer.height() - 1),
+            color,
+        });
+    }
+}
+
+impl Drop for MltIntegrator<'_> {
+    fn drop(&mut self) {
+        if let Some(chain) = self.chain.get_mut().unwrap().take() {
+            self.chains.chains.lock().unwrap().insert(self.tile, chain);
+        }
+    }
+}
+
+impl Integrator for MltIntegrator<'_> {
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32) {
+        //the pixel itself only gets the auxiliary buffers, for the denoiser
+        let (_, albedo, normal, depth, mut rays) =
+            AlbedoIntegrator.trace(tracer, ray, sampler, splats);
+
+        let brightness = self.chains.brightness;
+        if brightness <= 0.0 {
+            return (Vec3::new(0.0, 0.0, 0.0), albedo, normal, depth, rays);
+        }
+
+        let mut chain = self.chain.lock().unwrap();
+        if chain.is_none() {
+            let (taken, traced) = self.chains.take(tracer, &self.path, self.tile);
+            *chain = Some(taken);
+            rays += traced;
+        }
+        let chain = chain.as_mut().unwrap();
+
+        chain.sampler.start_iteration();
+        let (proposed, traced) = sample_path(tracer, &self.path, &mut chain.sampler);
+        rays += traced;
+
+        let current_luminance = chain.current.color.luminance();
+        let proposed_luminance = proposed.color.luminance().max(0.0);
+        let acceptance = if current_luminance > 0.0 {
+            (proposed_luminance / current_luminance).min(1.0)
+        } else {
+            1.0
+        };
+
+        //both paths are splatted, weighted by how likely each one is the next state of the chain
+        if proposed_luminance > 0.0 {
+            let weight = acceptance * brightness / proposed_luminance;
+            Self::splat(tracer, &proposed, proposed.color * weight, splats);
+        }
+        if current_luminance > 0.0 {
+            let weight = (1.0 - acceptance) * brightness / current_luminance;
+            Self::splat(tracer, &chain.current, chain.current.color * weight, splats);
+        }
+
+        if sampler.next_1d() < acceptance {
+            chain.sampler.accept();
+            chain.current = proposed;
+        } else {
+            chain.sampler.reject();
+        }
+
+        (Vec3::new(0.0, 0.0, 0.0), albedo, normal, depth, rays)
+    }
+}
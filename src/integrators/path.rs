@@ -0,0 +1,310 @@
+use crate::film::Splat;
+use crate::gfx::bsdf::{Bsdf, BsdfSample, Lobes};
+use crate::gfx::material::BounceKind;
+use crate::hit::{Hit, HitResult};
+use crate::integrators::integrator::Integrator;
+use crate::math::pdf::{BsdfPDF, MisHeuristic, MixturePDF, PDF};
+use crate::math::vec3::Vec3;
+use crate::path_guide::GuideRecord;
+use crate::pathtracer::{BounceLimits, PathTracer};
+use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
+use crate::tiles::Tile;
+
+/// unidirectional path tracing, with next event estimation and multiple importance sampling
+pub struct PathIntegrator {
+    /// paths end once they reach any of these
+    bounce_limits: BounceLimits,
+    /// paths with little throughput may randomly end after this many bounces
+    roulette_depth: u32,
+    /// combines sampling the material with sampling the lights
+    mis: MisHeuristic,
+    /// the corner of the tile it renders, the path guide keeps what it learns by tile
+    tile: (u32, u32),
+}
+
+impl PathIntegrator {
+    pub fn new(bounce_limits: BounceLimits, roulette_depth: u32, mis: MisHeuristic) -> Self {
+        PathIntegrator {
+            bounce_limits,
+            roulette_depth,
+            mis,
+            tile: (0, 0),
+        }
+    }
+
+    /// renders the tile `tile`
+    pub fn for_tile(mut self, tile: &Tile) -> Self {
+        self.tile = (tile.x, tile.y);
+        self
+    }
+
+    /// samples a direction from `hit` towards one of the lights or the sky
+    /// and traces a shadow ray
+    /// # Return Value
+    /// the light arriving from there, times `bsdf` and divided by the light pdf,
+    /// weighted against `sampling_pdf`, with which the path samples its next direction;
+    /// multiplied with the throughput this is the contribution of the light to `hit`
+    pub fn sample_light(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        hit: &HitResult,
+        bsdf: &Bsdf,
+        sampling_pdf: &dyn PDF<Vec3>,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+
+        let light_pdf = tracer.light_pdf(hit.hit_position);
+        let direction = light_pdf.generate(sampler);
+        let pdf = light_pdf.value_at(direction);
+        if pdf <= 0.0 {
+            return black;
+        }
+
+        let f = ray.spectrum(bsdf.eval(-ray.direction, direction));
+        if f.max_component() <= 0.0 {
+            return black;
+        }
+
+        //the path could have found the light too
+        let weight = self.mis.weight(pdf, sampling_pdf.value_at(direction));
+
+        //whatever is hit first is what we see, if it is not the light it casts a shadow
+        let shadow_ray = hit.spawn_ray(direction);
+        match tracer.world().hit(&shadow_ray, 0.0001, f32::MAX, sampler) {
+            Some(light_hit) => match &light_hit.material {
+                Some(light_mat) => ray.illuminant(light_mat.emitted(&light_hit)) * f * weight / pdf,
+                None => black,
+            },
+            None => ray.illuminant(tracer.sky_color(direction)) * f * weight / pdf,
+        }
+    }
+}
+
+impl Integrator for PathIntegrator {
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        _splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32) {
+        let object = tracer.world();
+        // recursively, this was:
+        // return emitted + bsdf() * trace_color() / pdf
+        // -> e1 + f1 * (1/pdf1) * ( e2 + f2 * (1/pdf2) * (...) )
+        // -> 1 * (...)
+        // -> 0 + 1*e1 + (f1*(1/pdf1))*e2 + (f1*(1/pdf1))*(f2*(1/pdf2)) ...
+        // that's a sum!
+
+        let mut ray_to_use = *ray;
+        let mut final_attenuation = Vec3::new(1.0, 1.0, 1.0);
+
+        let mut bounces: u32 = 0;
+        //bounces of every kind, indexed by `BounceKind`
+        let mut kind_bounces = [0u32; 4];
+        let mut rays: u32 = 0;
+
+        let mut out_color = Vec3::new(0.0, 0.0, 0.0);
+        let mut out_albedo = None;
+        let mut out_normal = None;
+        let mut out_depth = None;
+        //set once the path split up by wavelength, from then on it only holds for the hero
+        let mut dispersed = false;
+
+        //set if the last vertex sampled the lights directly:
+        //where it did that from, and the pdf with which it scattered the current ray
+        let mut light_sampled = None;
+
+        //what the path guide learns from every vertex it could guide: where the path scattered,
+        //the direction and its pdf, the color before any light arrived from there and the
+        //throughput after it
+        let guide = tracer.path_guide();
+        let mut guided_vertices = Vec::new();
+
+        loop {
+            rays += 1;
+            sampler.start_vertex(bounces);
+            let hit = match object.hit(&ray_to_use, 0.0001, f32::MAX, sampler) {
+                Some(hit) => hit,
+                None => break,
+            };
+
+            let mat = hit
+                .material
+                .as_ref()
+                .expect("How did you manage to not have a material?!");
+
+            //emitted is even added if we do not scatter!
+            //if the last vertex sampled the lights too, this is weighted against that
+            let emitted = ray_to_use.illuminant(mat.emitted(&hit));
+            let weight = match light_sampled {
+                Some((origin, scattered_pdf)) if emitted.len_squared() > 0.0 => {
+                    let light_pdf = tracer.light_pdf(origin).value_at(ray_to_use.direction);
+                    self.mis.weight(scattered_pdf, light_pdf)
+                }
+                _ => 1.0,
+            };
+            out_color += final_attenuation * emitted * weight;
+
+            //the path was cut off, it can not reach the sky anymore
+            let kind = mat.bounce_kind(&hit);
+            if bounces >= self.bounce_limits.total
+                || kind_bounces[kind as usize] >= self.bounce_limits.of_kind(kind)
+            {
+                final_attenuation = Vec3::new(0.0, 0.0, 0.0);
+                break;
+            }
+            bounces += 1;
+            kind_bounces[kind as usize] += 1;
+
+            let bsdf = match mat.bsdf(&hit, ray_to_use.wavelengths) {
+                Some(bsdf) => bsdf,
+                None => {
+                    //absorbed, e.g. by a light; tracing the same ray again would hit the same surface
+                    final_attenuation = Vec3::new(0.0, 0.0, 0.0);
+                    break;
+                }
+            };
+            dispersed |= bsdf.is_dispersive();
+            let wo = -ray_to_use.direction;
+
+            if out_albedo.is_none() {
+                out_albedo = Some(bsdf.albedo())
+            }
+            if out_normal.is_none() {
+                out_normal = Some(bsdf.normal())
+            }
+            if out_depth.is_none() {
+                out_depth = Some(1.0 / hit.ray_param)
+            } // x/0 = inf !
+
+            //path guiding: half of the directions go where light arrived from in earlier frames
+            //the guide can not find the single directions of specular lobes
+            let can_guide = !bsdf.lobes().intersects(Lobes::SPECULAR) && kind != BounceKind::Volume;
+            let bsdf_pdf = BsdfPDF::new(&bsdf, wo);
+            let guide_pdf = match guide {
+                Some(guide) if can_guide => guide.pdf_at(hit.hit_position),
+                _ => None,
+            };
+            let mixture = guide_pdf
+                .as_ref()
+                .map(|guide_pdf| MixturePDF::new(&bsdf_pdf, guide_pdf));
+            let sampling_pdf: &dyn PDF<Vec3> = match &mixture {
+                Some(mixture) => mixture,
+                None => &bsdf_pdf,
+            };
+            let sample = match &mixture {
+                Some(mixture) => {
+                    let wi = mixture.generate(sampler);
+                    Some(BsdfSample {
+                        wi,
+                        f: bsdf.eval(wo, wi),
+                        pdf: mixture.value_at(wi),
+                        lobes: bsdf.lobes(),
+                    })
+                }
+                None => bsdf.sample(wo, sampler),
+            };
+
+            //next event estimation: connect to a light instead of hoping to hit one
+            light_sampled = None;
+            let specular = bsdf.lobes().is_specular();
+            if tracer.has_lights() && !specular {
+                let direct =
+                    self.sample_light(tracer, &ray_to_use, &hit, &bsdf, sampling_pdf, sampler);
+                out_color += final_attenuation * direct;
+            }
+
+            //e.g. guided below the surface, or absorbed
+            let sample = match sample {
+                Some(sample) if sample.pdf > 0.0 && sample.f.max_component() > 0.0 => sample,
+                _ => {
+                    final_attenuation = Vec3::new(0.0, 0.0, 0.0);
+                    break;
+                }
+            };
+            //a light found by a specular lobe could not have been sampled
+            if tracer.has_lights() && !specular && !sample.lobes.is_specular() {
+                light_sampled = Some((hit.hit_position, sample.pdf));
+            }
+
+            final_attenuation *= ray_to_use.spectrum(sample.f) / sample.pdf;
+            ray_to_use = hit.spawn_ray(sample.wi).with_wavelengths(ray.wavelengths);
+
+            //russian roulette: paths that can not contribute much anymore are likely to end,
+            //the surviving ones are brightened to make up for them
+            if bounces > self.roulette_depth {
+                let survival = final_attenuation.max_component().min(0.95);
+                if sampler.next_1d() >= survival {
+                    final_attenuation = Vec3::new(0.0, 0.0, 0.0);
+                    break;
+                }
+                final_attenuation /= survival;
+            }
+
+            if guide.is_some() && can_guide {
+                guided_vertices.push((
+                    hit.hit_position,
+                    ray_to_use.direction,
+                    sample.pdf,
+                    out_color,
+                    final_attenuation,
+                ));
+            }
+        }
+
+        let skycolor = tracer.sky_color(ray_to_use.direction);
+
+        if out_albedo.is_none() {
+            out_albedo = Some(skycolor)
+        }
+        if out_normal.is_none() {
+            out_normal = Some(-ray_to_use.direction)
+        }
+        if out_depth.is_none() {
+            out_depth = Some(0.0)
+        }
+
+        //the sky is sampled like the lights, weighted the same way
+        let weight = match light_sampled {
+            Some((origin, scattered_pdf)) if final_attenuation.max_component() > 0.0 => {
+                let light_pdf = tracer.light_pdf(origin).value_at(ray_to_use.direction);
+                self.mis.weight(scattered_pdf, light_pdf)
+            }
+            _ => 1.0,
+        };
+        out_color += ray_to_use.illuminant(skycolor) * final_attenuation * weight;
+
+        //everything added after a vertex arrived at it from the direction it scattered into
+        if let Some(guide) = guide {
+            let mut records = Vec::with_capacity(guided_vertices.len());
+            for (point, direction, pdf, color, throughput) in guided_vertices {
+                let throughput = throughput.luminance();
+                if throughput > 0.0 {
+                    let radiance = (out_color - color).luminance() / throughput;
+                    records.push(GuideRecord {
+                        point,
+                        direction,
+                        radiance,
+                        pdf,
+                    });
+                }
+            }
+            guide.record(self.tile, records);
+        }
+
+        if dispersed {
+            out_color = ray.terminate_secondary(out_color);
+        }
+        (
+            out_color,
+            out_albedo.unwrap(),
+            out_normal.unwrap(),
+            out_depth.unwrap(),
+            rays,
+        )
+    }
+}
@@ -0,0 +1,204 @@
+use crate::film::Splat;
+use crate::gfx::bsdf::{Bsdf, BsdfSample};
+use crate::gfx::material::BounceKind;
+use crate::hit::{Hit, HitResult};
+use crate::integrators::integrator::Integrator;
+use crate::integrators::path::PathIntegrator;
+use crate::math::pdf::{BsdfPDF, MisHeuristic, PDF};
+use crate::math::vec3::Vec3;
+use crate::pathtracer::PathTracer;
+use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
+
+/// progressive photon mapping: the camera ray is followed through mirrors, glass and volumes
+/// to the first diffuse surface, which gets the direct light by sampling the lights and the
+/// indirect light from the photons of the frame around it
+/// the photons make caustics visible that paths from the camera can hardly find.
+/// the sky only lights diffuse surfaces directly, photons are only emitted by objects
+pub struct PhotonIntegrator {
+    /// the camera ray is followed through this many specular bounces at most
+    max_depth: u32,
+    /// samples the lights at the diffuse surface
+    direct: PathIntegrator,
+    /// combines sampling the material with sampling the lights
+    mis: MisHeuristic,
+}
+
+impl PhotonIntegrator {
+    pub fn new(max_depth: u32, direct: PathIntegrator, mis: MisHeuristic) -> Self {
+        PhotonIntegrator {
+            max_depth,
+            direct,
+            mis,
+        }
+    }
+
+    /// the light `hit` reflects along `ray` that arrives from the lights or the sky directly,
+    /// `sample` was picked by `bsdf`
+    #[allow(clippy::too_many_arguments)]
+    fn direct_light(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        hit: &HitResult,
+        bsdf: &Bsdf,
+        sample: Option<&BsdfSample>,
+        sampler: &mut dyn Sampler,
+        rays: &mut u32,
+    ) -> Vec3 {
+        let mut color = Vec3::new(0.0, 0.0, 0.0);
+        if tracer.has_lights() {
+            *rays += 1;
+            let bsdf_pdf = BsdfPDF::new(bsdf, -ray.direction);
+            color += self
+                .direct
+                .sample_light(tracer, ray, hit, bsdf, &bsdf_pdf, sampler);
+        }
+        let sample = match sample {
+            Some(sample) => sample,
+            None => return color,
+        };
+
+        //the direction the bsdf sampled can find the lights and the sky too
+        *rays += 1;
+        let f = ray.spectrum(sample.f) / sample.pdf;
+        let weight = self.weight(tracer, hit, sample);
+        let scattered_ray = hit.spawn_ray(sample.wi);
+        match tracer
+            .world()
+            .hit(&scattered_ray, 0.0001, f32::MAX, sampler)
+        {
+            Some(light_hit) => {
+                let emitted = match &light_hit.material {
+                    Some(light_mat) => ray.illuminant(light_mat.emitted(&light_hit)),
+                    None => return color,
+                };
+                if emitted.len_squared() > 0.0 {
+                    color += emitted * f * weight;
+                }
+            }
+            None => {
+                let sky = ray.illuminant(tracer.sky_color(scattered_ray.direction));
+                color += sky * f * weight;
+            }
+        }
+        color
+    }
+
+    /// the weight of the bsdf having picked `sample` at `hit`,
+    /// against sampling the lights and the sky
+    fn weight(&self, tracer: &PathTracer, hit: &HitResult, sample: &BsdfSample) -> f32 {
+        //specular lobes can not be found by sampling the lights
+        if !tracer.has_lights() || sample.lobes.is_specular() {
+            return 1.0;
+        }
+        let light_pdf = tracer.light_pdf(hit.hit_position).value_at(sample.wi);
+        self.mis.weight(sample.pdf, light_pdf)
+    }
+
+    /// the light `hit` reflects along `ray` that the photons around it brought
+    fn indirect_light(tracer: &PathTracer, ray: &Ray, hit: &HitResult, bsdf: &Bsdf) -> Vec3 {
+        let mut power = Vec3::new(0.0, 0.0, 0.0);
+        let map = match tracer.photon_map() {
+            Some(map) => map,
+            None => return power,
+        };
+
+        let normal = bsdf.normal();
+        map.gather(hit.hit_position, |photon| {
+            //the bsdf includes the cosine, the power of a photon already does
+            let cosine = normal.dot(photon.direction).abs();
+            if cosine > 0.0 {
+                power += photon.power * bsdf.eval(-ray.direction, photon.direction) / cosine;
+            }
+        });
+
+        //the density of the photons on the disk around the hit,
+        //they were traced in rgb
+        let radius = map.radius();
+        ray.illuminant(power) / (std::f32::consts::PI * radius * radius)
+    }
+}
+
+impl Integrator for PhotonIntegrator {
+    fn trace(
+        &self,
+        tracer: &PathTracer,
+        ray: &Ray,
+        sampler: &mut dyn Sampler,
+        _splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u32) {
+        let mut ray = *ray;
+        let mut beta = Vec3::new(1.0, 1.0, 1.0);
+        let mut color = Vec3::new(0.0, 0.0, 0.0);
+        let mut rays = 0;
+        //the albedo, normal and depth of the first surface
+        let mut first = None;
+        //set once the path split up by wavelength, from then on it only holds for the hero
+        let mut dispersed = false;
+
+        for depth in 0..=self.max_depth {
+            rays += 1;
+            sampler.start_vertex(depth);
+            let hit = match tracer.world().hit(&ray, 0.0001, f32::MAX, sampler) {
+                Some(hit) => hit,
+                None => {
+                    color += beta * ray.illuminant(tracer.sky_color(ray.direction));
+                    break;
+                }
+            };
+            let mat = hit
+                .material
+                .as_ref()
+                .expect("How did you manage to not have a material?!");
+
+            //only reached through specular bounces, so no light sample could have found it
+            let emitted = mat.emitted(&hit);
+            color += beta * ray.illuminant(emitted);
+            if depth == self.max_depth {
+                break;
+            }
+
+            let bsdf = match mat.bsdf(&hit, ray.wavelengths) {
+                Some(bsdf) => bsdf,
+                None => {
+                    first.get_or_insert((emitted, hit.normal, 1.0 / hit.ray_param));
+                    break;
+                }
+            };
+            first.get_or_insert((bsdf.albedo(), bsdf.normal(), 1.0 / hit.ray_param));
+            dispersed |= bsdf.is_dispersive();
+            let sample = bsdf.sample(-ray.direction, sampler);
+
+            if bsdf.lobes().is_specular() || mat.bounce_kind(&hit) == BounceKind::Volume {
+                let sample = match sample {
+                    Some(sample) => sample,
+                    None => break,
+                };
+                beta *= ray.spectrum(sample.f) / sample.pdf;
+                ray = hit.spawn_ray(sample.wi).with_wavelengths(ray.wavelengths);
+                continue;
+            }
+
+            let direct = self.direct_light(
+                tracer,
+                &ray,
+                &hit,
+                &bsdf,
+                sample.as_ref(),
+                sampler,
+                &mut rays,
+            );
+            let indirect = Self::indirect_light(tracer, &ray, &hit, &bsdf);
+            color += beta * (direct + indirect);
+            break;
+        }
+
+        let (albedo, normal, depth) =
+            first.unwrap_or_else(|| (tracer.sky_color(ray.direction), -ray.direction, 0.0));
+        if dispersed {
+            color = ray.terminate_secondary(color);
+        }
+        (color, albedo, normal, depth, rays)
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use crate::hit::{Hit, HitResult};
+use crate::hittables::bvh::BvhTree;
+use crate::math::distribution::Distribution1D;
+use crate::math::vec3::Vec3;
+use crate::ray::Ray;
+use crate::sampling::sampler::{RandomSampler, Sampler};
+
+/// at how many random points the emission of every light is measured to estimate its power
+const POWER_SAMPLES: u32 = 16;
+/// the share of the average power every light is picked with at least,
+/// lights that looked dark at the measured points may still emit elsewhere
+const MIN_POWER: f32 = 0.01;
+
+/// the emissive objects that are sampled directly
+/// every light is picked as likely as the light it emits, and they are kept in their own bvh,
+/// so finding the lights a direction points at does not test all of them
+#[derive(Clone)]
+pub struct Lights {
+    /// none if there are no lights
+    tree: Option<BvhTree<Arc<dyn Hit>>>,
+    /// picks the lights in the order of the tree
+    power: Option<Distribution1D>,
+}
+
+impl Lights {
+    /// every light must return true for `is_light`
+    pub fn new(lights: Vec<Arc<dyn Hit>>) -> Self {
+        if lights.is_empty() {
+            return Lights {
+                tree: None,
+                power: None,
+            };
+        }
+
+        let tree = BvhTree::from_hittables(lights);
+        let mut sampler = RandomSampler::new(0);
+        let power: Vec<f32> = tree
+            .objects()
+            .iter()
+            .enumerate()
+            .map(|(index, light)| {
+                let mut emitted = 0.0;
+                for sample in 0..POWER_SAMPLES {
+                    sampler.start_sample(index as u32, 0, sample);
+                    sampler.start_vertex(0);
+                    let point = light.random_point(&mut sampler);
+                    if let Some(mat) = &point.material {
+                        emitted += mat.emitted(&point).luminance();
+                    }
+                }
+                light.area() * emitted / POWER_SAMPLES as f32
+            })
+            .collect();
+        let average = power.iter().sum::<f32>() / power.len() as f32;
+        let power = power
+            .iter()
+            .map(|power| power.max(MIN_POWER * average))
+            .collect();
+
+        Lights {
+            tree: Some(tree),
+            power: Some(Distribution1D::new(power)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_none()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.as_ref().map_or(0, |tree| tree.objects().len())
+    }
+
+    /// picks one of the lights by its power
+    /// # Return Value
+    /// Returns Tuple of (the light, the probability of picking it)
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> Option<(&Arc<dyn Hit>, f32)> {
+        let (tree, power) = (self.tree.as_ref()?, self.power.as_ref()?);
+        let (_, _, index) = power.sample(sampler.next_1d());
+        Some((&tree.objects()[index], power.probability(index)))
+    }
+
+    /// picks one of the lights by its power, then a point on it uniformly
+    /// # Return Value
+    /// Returns Tuple of (the point, the density per area of picking it)
+    pub fn sample_point(&self, sampler: &mut dyn Sampler) -> Option<(HitResult, f32)> {
+        let (light, probability) = self.sample(sampler)?;
+        let area = light.area();
+        if area <= 0.0 {
+            return None;
+        }
+
+        let hit = light.random_point(sampler);
+        Some((hit, probability / area))
+    }
+
+    /// the solid angle density with which picking a light, then a direction towards it,
+    /// picks `direction` from `origin`; any of the lights along it could have
+    pub fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let (tree, power) = match (&self.tree, &self.power) {
+            (Some(tree), Some(power)) => (tree, power),
+            _ => return 0.0,
+        };
+
+        let ray = Ray::new(origin, direction);
+        let mut pdf = 0.0;
+        tree.for_each_along(&ray, 0.0001, f32::MAX, |index, light| {
+            pdf += power.probability(index) * light.pdf_value(origin, direction);
+        });
+        pdf
+    }
+
+    /// the density per area with which `sample_point` picks the point where `ray` hit `hit`,
+    /// 0 if it is on none of the lights
+    pub fn point_pdf(&self, ray: &Ray, hit: &HitResult, sampler: &mut dyn Sampler) -> f32 {
+        let (tree, power) = match (&self.tree, &self.power) {
+            (Some(tree), Some(power)) => (tree, power),
+            _ => return 0.0,
+        };
+
+        //the light that was hit is the one hit at the same distance
+        let tolerance = 1e-3 * hit.ray_param.max(1.0);
+        let mut pdf = 0.0;
+        tree.for_each_along(ray, 0.0001, f32::MAX, |index, light| {
+            if pdf > 0.0 || light.area() <= 0.0 {
+                return;
+            }
+            if let Some(light_hit) = light.hit(ray, 0.0001, f32::MAX, sampler) {
+                if (light_hit.ray_param - hit.ray_param).abs() <= tolerance {
+                    pdf = power.probability(index) / light.area();
+                }
+            }
+        });
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::material::Emissive;
+    use crate::gfx::texture::ConstantTexture;
+    use crate::hittables::primitives::{Sphere, Triangle};
+
+    #[test]
+    fn test_pdf_integrates_to_one() {
+        let emissive = |strength: f32| {
+            Arc::new(Emissive::new(Arc::new(ConstantTexture::new(Vec3::new(
+                strength, strength, strength,
+            )))))
+        };
+        let lights: Vec<Arc<dyn Hit>> = vec![
+            Arc::new(Sphere {
+                center: Vec3::new(0.0, 3.0, 0.0),
+                radius: 1.0,
+                material: emissive(1.0),
+            }),
+            Arc::new(Sphere {
+                center: Vec3::new(2.0, 0.0, 3.0),
+                radius: 0.5,
+                material: emissive(8.0),
+            }),
+            Arc::new(Triangle {
+                llc: Vec3::new(-2.0, -1.0, -1.0),
+                span_a: Vec3::new(0.0, 2.0, 0.0),
+                span_b: Vec3::new(0.0, 0.0, 2.0),
+                material: emissive(2.0),
+            }),
+        ];
+        let lights = Lights::new(lights);
+
+        //uniform directions, the density of each is 1 / 4pi
+        let count = 200_000;
+        let mut sampler = RandomSampler::new(7);
+        let mut sum = 0.0;
+        for index in 0..count {
+            sampler.start_sample(index, 0, 0);
+            let (r1, r2) = sampler.next_2d();
+            let z = 1.0 - 2.0 * r2;
+            let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+            let phi = 2.0 * std::f32::consts::PI * r1;
+            let direction = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z);
+            sum += lights.pdf_value(Vec3::new(0.0, 0.0, 0.0), direction) as f64;
+        }
+        let integral = sum * 4.0 * std::f64::consts::PI / count as f64;
+        assert!((integral - 1.0).abs() < 0.02, "{}", integral);
+    }
+}
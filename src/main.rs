@@ -1,24 +1,72 @@
+use crate::cli::{
+    BenchArgs, Cli, Command, InfoArgs, PreviewArgs, ProgressiveArgs, RenderArgs, SamplingArgs,
+    SceneArgs,
+};
+use crate::film::AdaptiveSampling;
+use crate::hit::Hit;
+use crate::integrators::integrator::IntegratorKind;
+use crate::math::vec3::Vec3;
 use crate::renderer::Renderer;
+use crate::scene::{SceneDescription, Settings};
+use crate::stop::StopConditions;
+use clap::Parser;
+use std::time::{Duration, Instant};
 
 mod camera;
+mod checkpoint;
+mod cli;
+mod film;
+mod lights;
+mod output;
+mod path_guide;
 mod pathtracer;
+mod photon_map;
 mod ray;
 mod renderer;
+mod scene;
+mod stop;
+mod tiles;
 
 mod gfx {
+    pub mod bsdf;
+    pub mod environment;
     pub mod material;
+    pub mod microfacet;
+    pub mod spectrum;
     pub mod texture;
 }
 
 mod math {
+    pub mod distribution;
     pub mod mat3;
     pub mod onb;
     pub mod pdf;
     pub mod quat;
+    pub mod random;
     pub mod transform;
     pub mod vec3;
 }
 
+mod sampling {
+    pub mod blue_noise;
+    pub mod halton;
+    pub mod mlt;
+    pub mod sampler;
+    pub mod sobol;
+    pub mod stratified;
+}
+
+mod integrators {
+    pub mod ambient_occlusion;
+    pub mod bdpt;
+    pub mod bvh_heatmap;
+    pub mod debug;
+    pub mod integrator;
+    pub mod mlt;
+    pub mod path;
+    pub mod photon;
+}
+
 mod hit;
 mod hittables {
     pub mod aabb;
@@ -32,67 +80,331 @@ mod hittables {
 enum ErrorCodes {
     Success = 0,
     Usage = 1,
+    Output = 2,
+    Scene = 3,
+    Checkpoint = 4,
 }
 
 fn usage_err(message: &str) -> ! {
     println!("Error: {}", message);
     println!();
-    usage();
+    println!("For more information, try '--help'.");
+    std::process::exit(ErrorCodes::Usage as i32);
 }
 
-fn usage() -> ! {
-    println!("Usage: ./raytrace [OPTIONS]");
-    println!("Available Options:");
-    println!("    -w, --width NUMBER        set window width");
-    println!("    -h, --height NUMBER       set window height");
-    println!("    -s, --samples NUMBER      number of samples per pixel");
-    println!("    -i, --incremental         render the picture incrementally");
-    println!("    --help                    show this help");
-    std::process::exit(ErrorCodes::Usage as i32);
+/// sets the number of render threads, must be called before anything is rendered
+fn set_threads(args: &SceneArgs) {
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+            .expect("the render threads were already started");
+    }
 }
 
-fn parse_u32(opt: &Option<String>) -> u32 {
-    if let Some(string) = opt {
-        if let Ok(int) = string.parse::<u32>() {
-            int
-        } else {
-            usage_err("Argument was not a number!");
-        }
-    } else {
-        usage_err("Argument missing!");
+/// reads the scene file, if any, and applies the command line to its settings
+/// `incremental` overrides whether frames are accumulated, the scene file decides if it is None
+fn load_scene(args: &SceneArgs, incremental: Option<bool>) -> (Option<SceneDescription>, Settings) {
+    let mut description = args.scene.as_ref().map(|path| {
+        SceneDescription::from_file(path).unwrap_or_else(|e| {
+            println!("Error: {}", e);
+            std::process::exit(ErrorCodes::Scene as i32);
+        })
+    });
+
+    //command line arguments override the settings of the scene file
+    let mut settings = match &description {
+        Some(description) => description.settings,
+        None => Settings::default(),
+    };
+    settings.width = args.width.unwrap_or(settings.width);
+    settings.height = args.height.unwrap_or(settings.height);
+    settings.samples = args.samples.unwrap_or(settings.samples);
+    settings.seed = args.seed.unwrap_or(settings.seed);
+    settings.sampler = args.sampler.unwrap_or(settings.sampler);
+    settings.max_depth = args.max_depth.unwrap_or(settings.max_depth);
+    settings.max_diffuse_depth = args.max_diffuse_depth.unwrap_or(settings.max_diffuse_depth);
+    settings.max_glossy_depth = args.max_glossy_depth.unwrap_or(settings.max_glossy_depth);
+    settings.max_transmission_depth = args
+        .max_transmission_depth
+        .unwrap_or(settings.max_transmission_depth);
+    settings.max_volume_depth = args.max_volume_depth.unwrap_or(settings.max_volume_depth);
+    settings.roulette_depth = args.roulette_depth.unwrap_or(settings.roulette_depth);
+    settings.mis = args.mis.unwrap_or(settings.mis);
+    settings.integrator = args.integrator.unwrap_or(settings.integrator);
+    settings.photons = args.photons.unwrap_or(settings.photons);
+    settings.photon_radius = args.photon_radius.unwrap_or(settings.photon_radius);
+    settings.spectral |= args.spectral;
+    settings.guiding |= args.guiding;
+    settings.incremental = incremental.unwrap_or(settings.incremental);
+
+    if let Some(description) = &mut description {
+        description.settings = settings;
     }
+    (description, settings)
 }
 
-fn main() {
-    let mut args = std::env::args();
+/// path guiding learns from the earlier frames, a single frame does not have any
+/// `options` are the ones that make the frames incremental, None if there are none
+fn check_guiding(settings: &Settings, options: Option<&str>) {
+    if settings.guiding && settings.integrator == IntegratorKind::Path && !settings.incremental {
+        match options {
+            Some(options) => usage_err(&format!(
+                "Path guiding needs incremental frames, add {}!",
+                options
+            )),
+            None => usage_err("Path guiding needs incremental frames, these are not!"),
+        }
+    }
+}
 
-    let mut width = 800;
-    let mut height = 600;
-    let mut samples = 48;
-    let mut incremental = false;
+/// the noise of splatted light is not known, nothing can depend on it
+fn check_noise_estimates(settings: &Settings, sampling: &SamplingArgs, noise_target: Option<f32>) {
+    if settings.integrator.splats() && (sampling.adaptive.is_some() || noise_target.is_some()) {
+        usage_err(&format!(
+            "The {:?} integrator can not estimate the noise of the pixels, adaptive sampling and noise targets need another one!",
+            settings.integrator
+        ));
+    }
+}
 
-    while let Some(arg) = args.next() {
-        match arg.as_str() {
-            "-w" | "--width" => {
-                width = parse_u32(&args.next());
-            }
-            "-h" | "--height" => {
-                height = parse_u32(&args.next());
+/// builds the scene file, or the built-in scene if there is none
+fn build_renderer(description: Option<&SceneDescription>, settings: &Settings) -> Renderer {
+    match description {
+        Some(description) => match description.build() {
+            Ok(path_tracer) => {
+                Renderer::from_path_tracer(settings.width, settings.height, path_tracer)
+                    .with_scene_hash(description.hash())
             }
-            "-s" | "--samples" => {
-                samples = parse_u32(&args.next());
+            Err(e) => {
+                println!("Error: {}", e);
+                std::process::exit(ErrorCodes::Scene as i32);
             }
-            "-i" | "--incremental" => {
-                incremental = true;
+        },
+        None => {
+            if settings.width == 0 || settings.height == 0 {
+                usage_err("Width and height must be greater than 0!");
             }
-            "--help" => {
-                usage();
-            }
-            _ => {}
+            Renderer::new(
+                settings.width,
+                settings.height,
+                settings.samples,
+                settings.incremental,
+            )
+            .build_scene()
+            .with_seed(settings.seed)
+            .with_sampler(settings.sampler)
+            .with_bounce_limits(settings.bounce_limits())
+            .with_roulette_depth(settings.roulette_depth)
+            .with_mis_heuristic(settings.mis)
+            .with_integrator(settings.integrator)
+            .with_photons(settings.photons, settings.photon_radius)
+            .with_spectral(settings.spectral)
+            .with_guiding(settings.guiding)
+        }
+    }
+}
+
+fn apply_sampling(mut renderer: Renderer, args: &SamplingArgs) -> Renderer {
+    renderer = renderer.with_tiles(args.tile_size, args.tile_order);
+
+    if let Some(threshold) = args.adaptive {
+        if args.min_samples == 0 || args.min_samples > args.max_samples {
+            usage_err("Minimum samples must be between 1 and the maximum samples!");
+        }
+        renderer = renderer.with_adaptive_sampling(AdaptiveSampling {
+            min_samples: args.min_samples,
+            max_samples: args.max_samples,
+            threshold,
+        });
+    }
+    renderer
+}
+
+fn stop_conditions(args: &ProgressiveArgs) -> StopConditions {
+    StopConditions {
+        time_budget: args.time_budget,
+        noise_target: args.noise_target,
+    }
+}
+
+/// sets up the stop conditions, checkpoints and resumes from a checkpoint
+fn apply_progressive(mut renderer: Renderer, args: &ProgressiveArgs) -> Renderer {
+    renderer = renderer.with_stop_conditions(stop_conditions(args));
+
+    if let Some(path) = args.checkpoint.as_ref().or(args.resume.as_ref()) {
+        let interval = Duration::from_secs(args.checkpoint_interval);
+        renderer = renderer.with_checkpoints(path.clone(), interval);
+    }
+
+    if let Some(resume) = &args.resume {
+        if let Err(e) = renderer.resume(resume) {
+            println!("Error: can not resume from {}: {}", resume.display(), e);
+            std::process::exit(ErrorCodes::Checkpoint as i32);
         }
     }
+    renderer
+}
+
+fn preview(args: PreviewArgs) {
+    set_threads(&args.scene);
+
+    //stop conditions only make sense if the frames add up
+    let progressive = args.incremental || !stop_conditions(&args.progressive).is_empty();
+    let (description, settings) = load_scene(&args.scene, Some(true).filter(|_| progressive));
+    check_guiding(
+        &settings,
+        Some("--incremental, --time-budget or --noise-target"),
+    );
+    check_noise_estimates(&settings, &args.sampling, args.progressive.noise_target);
+
+    let mut renderer =
+        build_renderer(description.as_ref(), &settings).with_denoising(!args.no_denoise);
+    renderer = apply_sampling(renderer, &args.sampling);
+    renderer = apply_progressive(renderer, &args.progressive);
+    renderer.run();
+}
+
+fn render(args: RenderArgs) {
+    set_threads(&args.scene);
+
+    //headless rendering renders all samples in a single pass, unless it has stop conditions
+    let progressive = !stop_conditions(&args.progressive).is_empty();
+    let (description, settings) = load_scene(&args.scene, Some(progressive));
+    check_guiding(&settings, Some("--time-budget or --noise-target"));
+    check_noise_estimates(&settings, &args.sampling, args.progressive.noise_target);
+
+    let mut renderer =
+        build_renderer(description.as_ref(), &settings).with_denoising(!args.no_denoise);
+    renderer = apply_sampling(renderer, &args.sampling);
+    renderer = apply_progressive(renderer, &args.progressive);
+
+    if let Err(e) = renderer.render_to_file(&args.output, &args.aov) {
+        println!("Error: failed to write {}: {}", args.output.display(), e);
+        std::process::exit(ErrorCodes::Output as i32);
+    }
+}
+
+fn info(args: InfoArgs) {
+    let start = Instant::now();
+    let (description, settings) = load_scene(&args.scene, None);
+    let renderer = build_renderer(description.as_ref(), &settings);
+    let load_time = start.elapsed();
+
+    let format = |v: Vec3| format!("({:.2}, {:.2}, {:.2})", v.x, v.y, v.z);
+    let path_tracer = renderer.path_tracer();
+    let bvh = path_tracer.bvh.as_ref().expect("did not call finalise()!");
+    let stats = bvh.stats();
+
+    match &args.scene.scene {
+        Some(path) => println!("Scene:      {}", path.display()),
+        None => println!("Scene:      built-in"),
+    }
+    println!(
+        "Image:      {}x{}, {} samples per pixel{}",
+        settings.width,
+        settings.height,
+        settings.samples,
+        if settings.incremental {
+            ", incremental"
+        } else {
+            ""
+        },
+    );
+    println!(
+        "Sampling:   {:?} integrator, {:?} sampler, seed {}, {:?} heuristic{}",
+        settings.integrator,
+        settings.sampler,
+        settings.seed,
+        settings.mis,
+        if settings.spectral { ", spectral" } else { "" },
+    );
+    if settings.guiding && settings.integrator == IntegratorKind::Path {
+        println!(
+            "Guiding:    learned over the frames{}",
+            if settings.incremental {
+                ""
+            } else {
+                ", but they are not incremental"
+            }
+        );
+    }
+    println!(
+        "Bounces:    at most {} ({} diffuse, {} glossy, {} transmission, {} volume), roulette after {}",
+        settings.max_depth,
+        settings.max_diffuse_depth,
+        settings.max_glossy_depth,
+        settings.max_transmission_depth,
+        settings.max_volume_depth,
+        settings.roulette_depth
+    );
+    if settings.integrator == IntegratorKind::PhotonMapping {
+        println!(
+            "Photons:    {} per frame, gathered within {}",
+            settings.photons, settings.photon_radius
+        );
+    }
+    if let Some(description) = &description {
+        println!(
+            "Contents:   {} objects, {} materials, {} textures",
+            description.object_count(),
+            description.material_count(),
+            description.texture_count()
+        );
+    }
+    println!("Primitives: {}", bvh.primitive_count());
+    println!(
+        "BVH:        {} nodes, {} leaves, depth {}",
+        stats.nodes, stats.leaves, stats.depth
+    );
+    if let Some(bounds) = bvh.bounding_box() {
+        println!(
+            "Bounds:     {} to {}",
+            format(bounds.start),
+            format(bounds.end)
+        );
+    }
+    println!(
+        "Camera:     at {}, looking along {}",
+        format(path_tracer.camera.position),
+        format(path_tracer.camera.direction)
+    );
+    println!("Loaded in:  {:.2}s", load_time.as_secs_f64());
+}
 
-    Renderer::new(width, height, samples, incremental)
-        .build_scene()
-        .run();
+fn bench(args: BenchArgs) {
+    set_threads(&args.scene);
+
+    let (description, settings) = load_scene(&args.scene, Some(false));
+    //the frames are timed one by one, they never add up
+    check_guiding(&settings, None);
+    check_noise_estimates(&settings, &args.sampling, None);
+
+    let mut renderer = build_renderer(description.as_ref(), &settings);
+    renderer = apply_sampling(renderer, &args.sampling);
+    renderer.bench(args.frames);
+}
+
+fn main() {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            //--help and --version end up here as well, but are no errors
+            let code = if e.use_stderr() {
+                ErrorCodes::Usage
+            } else {
+                ErrorCodes::Success
+            };
+            //nothing we can do if the console is gone
+            let _ = e.print();
+            std::process::exit(code as i32);
+        }
+    };
+
+    match cli.command {
+        Command::Preview(args) => preview(args),
+        Command::Render(args) => render(args),
+        Command::Info(args) => info(args),
+        Command::Bench(args) => bench(args),
+    }
 }
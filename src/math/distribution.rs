@@ -0,0 +1,156 @@
+/// a piecewise constant density over [0, 1), every one of the `n` pieces is as likely
+/// as its function value
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    function: Vec<f32>,
+    /// n + 1 values, from 0 to 1
+    cdf: Vec<f32>,
+    /// the average of the function values
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// `function` must not be empty, nor negative
+    /// if it is all 0, every piece is equally likely
+    pub fn new(function: Vec<f32>) -> Self {
+        let count = function.len() as f32;
+        let mut cdf = Vec::with_capacity(function.len() + 1);
+        cdf.push(0.0);
+        for value in &function {
+            cdf.push(cdf.last().unwrap() + value / count);
+        }
+
+        let integral = *cdf.last().unwrap();
+        for (index, value) in cdf.iter_mut().enumerate() {
+            *value = if integral > 0.0 {
+                *value / integral
+            } else {
+                index as f32 / count
+            };
+        }
+
+        Distribution1D {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// maps the uniform random number `u` to a value in [0, 1) with this density
+    /// # Return Value
+    /// Returns Tuple of (the value, its density, the index of its piece)
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let count = self.function.len();
+        //the last piece that starts at or before u
+        let index = self
+            .cdf
+            .partition_point(|start| *start <= u)
+            .saturating_sub(1)
+            .min(count - 1);
+
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.0
+        };
+        let value = ((index as f32 + offset) / count as f32).min(1.0 - f32::EPSILON);
+        (value, self.pdf_of(index), index)
+    }
+
+    /// the density of the values in piece `index`
+    fn pdf_of(&self, index: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.function[index] / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    /// how likely `sample` picks a value in piece `index`
+    pub fn probability(&self, index: usize) -> f32 {
+        self.pdf_of(index) / self.function.len() as f32
+    }
+
+    /// the density of `value`
+    pub fn pdf(&self, value: f32) -> f32 {
+        let count = self.function.len();
+        self.pdf_of(((value * count as f32) as usize).min(count - 1))
+    }
+}
+
+/// a piecewise constant density over [0, 1)², picks the row first, then the column in it
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    /// the columns of every row
+    rows: Vec<Distribution1D>,
+    /// the rows, as likely as the sum of their function values
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `function` holds `height` rows of `width` values each
+    pub fn new(function: &[f32], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = function
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.marginal.integral()
+    }
+
+    /// maps the uniform random numbers `u` to a point in [0, 1)² with this density
+    /// # Return Value
+    /// Returns Tuple of (the point, its density)
+    pub fn sample(&self, u: (f32, f32)) -> ((f32, f32), f32) {
+        let (y, row_pdf, row) = self.marginal.sample(u.1);
+        let (x, column_pdf, _) = self.rows[row].sample(u.0);
+        ((x, y), row_pdf * column_pdf)
+    }
+
+    /// the density of `point`
+    pub fn pdf(&self, point: (f32, f32)) -> f32 {
+        let count = self.rows.len();
+        let row = ((point.1 * count as f32) as usize).min(count - 1);
+        self.marginal.pdf(point.1) * self.rows[row].pdf(point.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_matches_pdf() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0]);
+        assert_eq!(distribution.integral(), 4.0 / 3.0);
+
+        //a quarter of the numbers land in the first piece, the rest in the last one
+        let (value, pdf, index) = distribution.sample(0.125);
+        assert_eq!(index, 0);
+        assert!((value - 1.0 / 6.0).abs() < 1e-6);
+        assert!((pdf - 0.75).abs() < 1e-6);
+
+        let (value, pdf, index) = distribution.sample(0.625);
+        assert_eq!(index, 2);
+        assert!((value - 5.0 / 6.0).abs() < 1e-6);
+        assert!((pdf - 2.25).abs() < 1e-6);
+        assert_eq!(distribution.pdf(0.5), 0.0);
+
+        //every row is picked by its sum, then its columns by their values
+        let distribution = Distribution2D::new(&[0.0, 0.0, 1.0, 3.0], 2, 2);
+        let ((x, y), pdf) = distribution.sample((0.5, 0.5));
+        assert!(y >= 0.5 && x >= 0.5);
+        assert!((pdf - distribution.pdf((x, y))).abs() < 1e-6);
+        assert!((pdf - 3.0).abs() < 1e-6);
+    }
+}
@@ -7,16 +7,17 @@ pub struct ONB {
 }
 
 impl ONB {
-    pub fn from_axes(u: Vec3, v: Vec3, w: Vec3) -> Self {
-        ONB { u, v, w }
-    }
-
     //w should be normalised
     pub fn from_w(w: Vec3) -> Self {
-        // 1) permute w to make sure we have a vector pointing *anywhere* else
-        let _temp = Vec3::new(w.y, w.z, w.x);
-        // 2) calculate any vector perpendicular to w => u (w x temp)
-        let u = w.cross(_temp);
+        // 1) pick an axis that is not (almost) parallel to w
+        let axis = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        // 2) calculate any vector perpendicular to w => u (w x axis)
+        //    it has to be normalised, else the basis would stretch the vectors it transforms
+        let u = w.cross(axis).normalised();
         // 3) calculate v (perpendicular to w and u)
         let v = w.cross(u);
 
@@ -26,4 +27,9 @@ impl ONB {
     pub fn to_local(&self, n: Vec3) -> Vec3 {
         n.x * self.u + n.y * self.v + n.z * self.w
     }
+
+    /// the coordinates of `n` in this basis, the inverse of `to_local`
+    pub fn coordinates(&self, n: Vec3) -> Vec3 {
+        Vec3::new(n.dot(self.u), n.dot(self.v), n.dot(self.w))
+    }
 }
@@ -1,18 +1,25 @@
+use crate::gfx::bsdf::Bsdf;
+use crate::gfx::environment::Environment;
+use crate::lights::Lights;
 use crate::math::onb::ONB;
 use crate::math::vec3::Vec3;
+use crate::sampling::sampler::Sampler;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 /// trait describing a probability density function
-trait PDF<T> {
+pub trait PDF<T> {
     /// generates a random value distributed with this PDF
     /// this is the inverse of the distribution, P(x)
-    fn generate(&self) -> T;
+    fn generate(&self, sampler: &mut dyn Sampler) -> T;
 
     /// returns the value of the pdf at the given input
     /// this is density, p(x)
     fn value_at(&self, p: T) -> f32;
 }
 
-struct CosinePDF {
+/// directions around `normal`, with a density proportional to their cosine to it
+pub struct CosinePDF {
     onb: ONB,
 }
 
@@ -25,8 +32,8 @@ impl CosinePDF {
 }
 
 impl PDF<Vec3> for CosinePDF {
-    fn generate(&self) -> Vec3 {
-        self.onb.to_local(Vec3::random_cosine_direction())
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.onb.to_local(Vec3::random_cosine_direction(sampler))
     }
 
     fn value_at(&self, p: Vec3) -> f32 {
@@ -39,7 +46,8 @@ impl PDF<Vec3> for CosinePDF {
     }
 }
 
-struct MixturePDF<'a, T> {
+/// picks one of two pdfs with equal probability
+pub struct MixturePDF<'a, T> {
     a: &'a dyn PDF<T>,
     b: &'a dyn PDF<T>,
 }
@@ -51,11 +59,11 @@ impl<'a> MixturePDF<'a, Vec3> {
 }
 
 impl<'a> PDF<Vec3> for MixturePDF<'a, Vec3> {
-    fn generate(&self) -> Vec3 {
-        if rand::random() {
-            self.a.generate()
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        if sampler.next_1d() < 0.5 {
+            self.a.generate(sampler)
         } else {
-            self.b.generate()
+            self.b.generate(sampler)
         }
     }
 
@@ -63,3 +71,145 @@ impl<'a> PDF<Vec3> for MixturePDF<'a, Vec3> {
         0.5 * self.a.value_at(p) + 0.5 * self.b.value_at(p)
     }
 }
+
+/// the directions `bsdf` scatters the light leaving into `wo` from
+pub struct BsdfPDF<'a> {
+    bsdf: &'a Bsdf,
+    wo: Vec3,
+}
+
+impl<'a> BsdfPDF<'a> {
+    pub fn new(bsdf: &'a Bsdf, wo: Vec3) -> Self {
+        BsdfPDF { bsdf, wo }
+    }
+}
+
+impl<'a> PDF<Vec3> for BsdfPDF<'a> {
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        match self.bsdf.sample(self.wo, sampler) {
+            Some(sample) => sample.wi,
+            //absorbed, any direction has no contribution
+            None => self.bsdf.normal(),
+        }
+    }
+
+    fn value_at(&self, p: Vec3) -> f32 {
+        self.bsdf.pdf(self.wo, p)
+    }
+}
+
+/// directions from `origin` towards the lights of the scene
+/// picks the sky as often as a single light, or one of the lights by its power,
+/// then a direction towards it
+pub struct LightPDF<'a> {
+    lights: &'a Lights,
+    /// only set if the sky can be sampled
+    sky: Option<&'a Environment>,
+    origin: Vec3,
+}
+
+impl<'a> LightPDF<'a> {
+    /// `lights` and `sky` must not both be empty
+    pub fn new(lights: &'a Lights, sky: Option<&'a Environment>, origin: Vec3) -> Self {
+        LightPDF {
+            lights,
+            sky: sky.filter(|sky| sky.can_sample()),
+            origin,
+        }
+    }
+
+    /// how likely the sky is picked instead of one of the lights
+    fn sky_probability(&self) -> f32 {
+        match self.sky {
+            Some(_) => 1.0 / (self.lights.len() + 1) as f32,
+            None => 0.0,
+        }
+    }
+}
+
+impl<'a> PDF<Vec3> for LightPDF<'a> {
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let light = if sampler.next_1d() < self.sky_probability() {
+            None
+        } else {
+            self.lights.sample(sampler)
+        };
+        match light {
+            Some((light, _)) => light.random_direction(self.origin, sampler),
+            None => match self.sky.and_then(|sky| sky.sample(sampler)) {
+                Some((direction, _)) => direction,
+                //any direction, the sky has no density anywhere else
+                None => Vec3::new(0.0, 1.0, 0.0),
+            },
+        }
+    }
+
+    fn value_at(&self, p: Vec3) -> f32 {
+        //any of the lights along the direction could have generated it, or the sky
+        let sky_probability = self.sky_probability();
+        let lights = self.lights.pdf_value(self.origin, p);
+        let sky = self.sky.map_or(0.0, |sky| sky.pdf(p));
+        (1.0 - sky_probability) * lights + sky_probability * sky
+    }
+}
+
+/// how multiple importance sampling weighs two strategies that can generate the same direction
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MisHeuristic {
+    /// weights proportional to the pdfs
+    Balance,
+    /// weights proportional to the squared pdfs, favours the better strategy more
+    #[default]
+    Power,
+}
+
+impl FromStr for MisHeuristic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "balance" => Ok(MisHeuristic::Balance),
+            "power" => Ok(MisHeuristic::Power),
+            _ => Err(format!(
+                "unknown heuristic '{}', expected balance or power",
+                s
+            )),
+        }
+    }
+}
+
+impl MisHeuristic {
+    /// the weight of a sample taken with pdf `pdf`, if `other_pdf` could have taken it too
+    /// the weights of both strategies add up to 1
+    pub fn weight(self, pdf: f32, other_pdf: f32) -> f32 {
+        let (a, b) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b <= 0.0 {
+            return 0.0;
+        }
+        a / (a + b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mis_weights() {
+        for heuristic in &[MisHeuristic::Balance, MisHeuristic::Power] {
+            let sum = heuristic.weight(0.3, 2.0) + heuristic.weight(2.0, 0.3);
+            assert!((sum - 1.0).abs() < 1e-6);
+
+            //a sample only one strategy can take gets the full weight
+            assert_eq!(heuristic.weight(0.5, 0.0), 1.0);
+            assert_eq!(heuristic.weight(0.0, 0.5), 0.0);
+        }
+
+        assert!((MisHeuristic::Balance.weight(1.0, 3.0) - 0.25).abs() < 1e-6);
+        assert!((MisHeuristic::Power.weight(1.0, 3.0) - 0.1).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,79 @@
+use rand_pcg::Pcg32;
+
+/// creates the random number generator for one sample of one pixel
+/// every sample gets its own stream, so the random numbers only depend on
+/// the seed, the pixel and the sample, never on which thread renders it or when
+pub fn sample_rng(seed: u64, x: u32, y: u32, sample: u32) -> Pcg32 {
+    let pixel = ((y as u64) << 32) | x as u64;
+    Pcg32::new(mix(seed ^ mix(pixel)), sample as u64)
+}
+
+/// the splitmix64 finaliser, spreads similar inputs (neighbouring pixels) over the whole state
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// hashes any number of values into one, e.g. a seed, a pixel and a dimension
+pub fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x2545_f491_4f6c_dd1d, |hash, value| mix(hash ^ value))
+}
+
+/// maps `index` to its place in a random permutation of `0..length` chosen by `seed`
+/// (Kensler, "Correlated Multi-Jittered Sampling", 2013)
+pub fn permute(index: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    //cycle walking, repeat until we land inside of 0..length
+    let mut i = index;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < length {
+            return (i.wrapping_add(seed)) % length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permute() {
+        for &length in &[1, 7, 16, 100] {
+            let mut seen = vec![false; length as usize];
+            for index in 0..length {
+                let permuted = permute(index, length, 1234);
+                assert!(!seen[permuted as usize]);
+                seen[permuted as usize] = true;
+            }
+        }
+    }
+}
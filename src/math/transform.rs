@@ -4,6 +4,7 @@ use crate::hittables::aabb::AABB;
 use crate::math::quat::Quaternion;
 use crate::math::vec3::Vec3;
 use crate::ray::Ray;
+use crate::sampling::sampler::Sampler;
 use std::sync::Arc;
 
 // first scale
@@ -19,10 +20,22 @@ pub struct Transform {
 }
 
 impl Hit for Transform {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitResult> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitResult> {
         //first apply inverse, because we transform "the world", not ourselves
+        //the direction stays normalised, so distances shrink with the scale
         let transformed_ray = self.apply_inverse_transform(ray);
-        match self.object.hit(&transformed_ray, t_min, t_max) {
+        match self.object.hit(
+            &transformed_ray,
+            t_min / self.scale,
+            t_max / self.scale,
+            sampler,
+        ) {
             Some(hit) => {
                 //undo the transformation to fix viewpoint
                 let transformed_hit = self.apply_transform(&transformed_ray, &hit);
@@ -33,6 +46,7 @@ impl Hit for Transform {
     }
     fn bounding_box(&self) -> Option<AABB> {
         let bb = self.object.bounding_box()?;
+        let bb = AABB::new(bb.start * self.scale, bb.end * self.scale);
 
         // p3/p7 +-------+ p2/p6
         //       |       |
@@ -94,16 +108,52 @@ impl Hit for Transform {
         let start = Vec3::new(min_x, min_y, min_z);
         let end = Vec3::new(max_x, max_y, max_z);
 
-        //TODO: apply scale
         Some(AABB::new(start + self.position, end + self.position))
     }
     fn center(&self) -> Vec3 {
-        //center does not change with scale or rotation
-        self.object.center() + self.position
+        self.rotation
+            .rotate_vector(self.object.center() * self.scale)
+            + self.position
+    }
+
+    fn primitive_count(&self) -> usize {
+        self.object.primitive_count()
+    }
+
+    fn is_light(&self) -> bool {
+        self.object.is_light()
+    }
+
+    //scaling, rotating and moving does not change solid angles, so the pdf does not change either
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let local_origin = self.rotation.unrotate_vector(origin - self.position) / self.scale;
+        let local_direction = self.rotation.unrotate_vector(direction);
+        self.object.pdf_value(local_origin, local_direction)
+    }
+
+    fn random_direction(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let local_origin = self.rotation.unrotate_vector(origin - self.position) / self.scale;
+        let local_direction = self.object.random_direction(local_origin, sampler);
+        self.rotation.rotate_vector(local_direction)
+    }
+
+    fn area(&self) -> f32 {
+        self.object.area() * self.scale * self.scale
+    }
+
+    fn random_point(&self, sampler: &mut dyn Sampler) -> HitResult {
+        let hit = self.object.random_point(sampler);
+        HitResult {
+            hit_position: self.rotation.rotate_vector(hit.hit_position * self.scale)
+                + self.position,
+            normal: self.rotation.rotate_vector(hit.normal),
+            ..hit
+        }
     }
 }
 
 impl Transform {
+    /// `scale` is uniform and must be greater than 0
     pub fn new(object: Arc<dyn Hit>, position: Vec3, rotation: Quaternion, scale: f32) -> Self {
         Self {
             object,
@@ -113,24 +163,24 @@ impl Transform {
         }
     }
 
-    //TODO: does not apply scale!
     fn apply_transform(&self, _ray: &Ray, hit: &HitResult) -> HitResult {
         HitResult {
-            ray_param: hit.ray_param,
-            hit_position: self.rotation.rotate_vector(hit.hit_position) + self.position,
+            ray_param: hit.ray_param * self.scale,
+            hit_position: self.rotation.rotate_vector(hit.hit_position * self.scale)
+                + self.position,
             normal: self.rotation.rotate_vector(hit.normal),
             material: hit.material.clone(),
             uv_coords: hit.uv_coords,
         }
     }
 
-    //TODO: does not apply scale!
     //invert direction AND ORDER OF OPERATIONS!
     //translate, rotate, scale
     fn apply_inverse_transform(&self, ray: &Ray) -> Ray {
         Ray {
-            origin: self.rotation.unrotate_vector(ray.origin - self.position),
+            origin: self.rotation.unrotate_vector(ray.origin - self.position) / self.scale,
             direction: self.rotation.unrotate_vector(ray.direction),
+            wavelengths: ray.wavelengths,
         }
     }
 }
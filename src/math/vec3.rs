@@ -1,10 +1,11 @@
-use rand::Rng;
+use crate::sampling::sampler::Sampler;
+use serde::{Deserialize, Serialize};
 use std::iter::Sum;
 use std::ops::DivAssign;
 use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
 
 //auto-implement printing
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -35,6 +36,16 @@ impl Vec3 {
         (self.x * self.x) + (self.y * self.y) + (self.z * self.z)
     }
 
+    /// perceived brightness of a linear RGB color (Rec. 709 weights)
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
+    /// the largest of x, y and z
+    pub fn max_component(&self) -> f32 {
+        self.x.max(self.y).max(self.z)
+    }
+
     /// normalise the vector (length = 1)
     pub fn normalised(&self) -> Vec3 {
         let ilen = 1.0 / self.len();
@@ -132,39 +143,38 @@ impl Vec3 {
         Some(scaled_direction - normal_scaled_out) //final refracted vector
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
-        let mut rng = rand::thread_rng();
-        let mut random_dir;
-        loop {
-            random_dir = Vec3::new(
-                rng.gen_range(-1.0, 1.0),
-                rng.gen_range(-1.0, 1.0),
-                rng.gen_range(-1.0, 1.0),
-            );
+    pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+        //uniform direction, and a radius that puts as many points into every shell as its volume
+        let (r1, r2) = sampler.next_2d();
+        let z = 1.0 - 2.0 * r1;
+        let radius = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * r2;
+        let direction = Vec3::new(radius * phi.cos(), radius * phi.sin(), z);
 
-            if random_dir.len() <= 1.0 {
-                break;
-            }
-        }
-        random_dir
+        direction * sampler.next_1d().cbrt()
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
-        let mut random_dir;
-        loop {
-            random_dir = Vec3::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), 0.0);
-            if random_dir.len() <= 1.0 {
-                break;
-            }
+    /// concentric mapping of the unit square to the disk, keeps the samples stratified
+    /// (Shirley, Chiu, "A Low Distortion Map Between Disk and Square", 1997)
+    pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.next_2d();
+        let (a, b) = (2.0 * r1 - 1.0, 2.0 * r2 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
         }
-        random_dir
+
+        let quarter_pi = std::f32::consts::FRAC_PI_4;
+        let (radius, phi) = if a.abs() > b.abs() {
+            (a, quarter_pi * (b / a))
+        } else {
+            (b, 2.0 * quarter_pi - quarter_pi * (a / b))
+        };
+        Vec3::new(radius * phi.cos(), radius * phi.sin(), 0.0)
     }
 
     /// calculates a random direction with pdf `p(dir) = cos(theta) / pi` (-> Lambert)
-    pub fn random_cosine_direction() -> Vec3 {
-        let r1: f32 = rand::random();
-        let r2: f32 = rand::random();
+    pub fn random_cosine_direction(sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.next_2d();
 
         // spherical coordinates -> cartesian
         // we pretend as if z axis was normal vector!
@@ -358,6 +368,16 @@ impl From<&[f32]> for Vec3 {
     }
 }
 
+impl From<[f32; 3]> for Vec3 {
+    fn from(array: [f32; 3]) -> Self {
+        Vec3 {
+            x: array[0],
+            y: array[1],
+            z: array[2],
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,343 @@
+use image2::{ImageBuf, Rgb};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const GAMMA: f32 = 1.0 / 2.2;
+
+/// the arbitrary output variables a render can write in addition to the final image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aov {
+    /// the noisy color, before denoising
+    Color,
+    Albedo,
+    Normal,
+    /// the distance to the camera
+    Depth,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 4] = [Aov::Color, Aov::Albedo, Aov::Normal, Aov::Depth];
+
+    /// the name of the layer, or the suffix of the file
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Color => "color",
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .iter()
+            .find(|aov| aov.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "unknown aov '{}', expected color, albedo, normal or depth",
+                    s
+                )
+            })
+    }
+}
+
+/// one layer of a multi-layer image
+pub struct Layer<'a> {
+    /// the channels are called `name.R`, `name.G`, ... ; an empty name gives plain `R`, `G`, ...
+    pub name: &'a str,
+    /// names of the channels, in the order they are interleaved in `data`
+    pub channels: &'a [&'a str],
+    /// interleaved pixel data, row by row, starting at the top left
+    pub data: &'a [f32],
+}
+
+impl<'a> Layer<'a> {
+    /// a layer with interleaved R, G and B channels
+    pub fn rgb(name: &'a str, data: &'a [f32]) -> Self {
+        Layer {
+            name,
+            channels: &["R", "G", "B"],
+            data,
+        }
+    }
+
+    /// a layer with a single depth channel
+    pub fn depth(name: &'a str, data: &'a [f32]) -> Self {
+        Layer {
+            name,
+            channels: &["Z"],
+            data,
+        }
+    }
+}
+
+/// returns true if `path` has the given extension, ignoring case
+pub fn extension_is(path: &Path, expected: &str) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case(expected),
+        None => false,
+    }
+}
+
+/// writes a f32-RGB buffer to disk, the format is determined by the file extension
+/// HDR formats (exr, pfm, hdr) get the linear values, all other formats are gamma corrected and clamped
+pub fn write_image<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    raw: &[f32],
+) -> io::Result<()> {
+    let path = path.as_ref();
+
+    if extension_is(path, "exr") {
+        return write_exr(path, width, height, &[Layer::rgb("", raw)]);
+    }
+    if extension_is(path, "pfm") {
+        return write_pfm(path, width, height, 3, raw);
+    }
+
+    let data = if extension_is(path, "hdr") {
+        raw.to_vec()
+    } else {
+        raw.iter()
+            .map(|value| value.max(0.0).powf(GAMMA).min(1.0))
+            .collect()
+    };
+
+    let image = ImageBuf::<f32, Rgb>::new_from(width as usize, height as usize, data);
+    image2::io::write(path, &image).map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// writes a portable float map, `channels` must be 1 (grayscale) or 3 (RGB)
+pub fn write_pfm<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    channels: usize,
+    data: &[f32],
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    encode_pfm(&mut writer, width, height, channels, data)?;
+    writer.flush()
+}
+
+fn encode_pfm<W: Write>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    channels: usize,
+    data: &[f32],
+) -> io::Result<()> {
+    let magic = match channels {
+        1 => "Pf",
+        3 => "PF",
+        _ => panic!("pfm only supports 1 or 3 channels"),
+    };
+    assert_eq!(data.len(), (width * height) as usize * channels);
+
+    //negative scale => little endian
+    write!(writer, "{}\n{} {}\n-1.0\n", magic, width, height)?;
+
+    //pfm stores the bottom row first
+    let row_length = width as usize * channels;
+    for row in data.chunks(row_length).rev() {
+        for value in row {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// writes an uncompressed, single part, scanline OpenEXR file
+/// every layer must contain `width * height` pixels
+pub fn write_exr<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    layers: &[Layer<'_>],
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    encode_exr(&mut writer, width, height, layers)?;
+    writer.flush()
+}
+
+fn write_attribute<W: Write>(
+    writer: &mut W,
+    name: &str,
+    kind: &str,
+    value: &[u8],
+) -> io::Result<()> {
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(kind.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(&(value.len() as i32).to_le_bytes())?;
+    writer.write_all(value)
+}
+
+fn encode_exr<W: Write>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    layers: &[Layer<'_>],
+) -> io::Result<()> {
+    const FLOAT: i32 = 2;
+    let pixels = (width * height) as usize;
+
+    //(full channel name, layer index, channel index within the layer)
+    let mut channels = Vec::new();
+    for (layer_index, layer) in layers.iter().enumerate() {
+        assert_eq!(layer.data.len(), pixels * layer.channels.len());
+        for (channel_index, channel) in layer.channels.iter().enumerate() {
+            let name = if layer.name.is_empty() {
+                channel.to_string()
+            } else {
+                format!("{}.{}", layer.name, channel)
+            };
+            channels.push((name, layer_index, channel_index));
+        }
+    }
+    //exr wants the channels sorted by name, in the header *and* the pixel data
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut chlist = Vec::new();
+    for (name, _, _) in &channels {
+        if name.len() > 31 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("exr channel name '{}' is too long", name),
+            ));
+        }
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&FLOAT.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    chlist.push(0);
+
+    let mut window = Vec::new();
+    for value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    //the header is assembled in memory first, we need its size for the offset table
+    let mut header = Vec::new();
+    // magic number, then version 2 with no flags (single part scanline, short names)
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    header.extend_from_slice(&2u32.to_le_bytes());
+    write_attribute(&mut header, "channels", "chlist", &chlist)?;
+    write_attribute(&mut header, "compression", "compression", &[0])?;
+    write_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    )?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    )?;
+    header.push(0); // end of header
+    writer.write_all(&header)?;
+
+    //one scanline per block without compression
+    let line_size = width as usize * channels.len() * 4;
+    let block_size = 4 + 4 + line_size;
+    let table_size = height as usize * 8;
+    for y in 0..height as usize {
+        let offset = (header.len() + table_size + y * block_size) as u64;
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+
+    for y in 0..height as usize {
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&(line_size as i32).to_le_bytes())?;
+
+        for (_, layer_index, channel_index) in &channels {
+            let layer = &layers[*layer_index];
+            let stride = layer.channels.len();
+            let row = &layer.data[y * width as usize * stride..(y + 1) * width as usize * stride];
+            for pixel in row.chunks(stride) {
+                writer.write_all(&pixel[*channel_index].to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pfm() {
+        let data = [1.0, 2.0, 3.0, 4.0];
+        let mut buffer = Vec::new();
+        encode_pfm(&mut buffer, 2, 2, 1, &data).unwrap();
+
+        let header = b"Pf\n2 2\n-1.0\n";
+        assert_eq!(&buffer[..header.len()], header);
+
+        //bottom row first
+        let pixels: Vec<f32> = buffer[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(pixels, vec![3.0, 4.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_exr_layout() {
+        let color = [0.5; 2 * 3];
+        let depth = [7.0, 8.0];
+        let mut buffer = Vec::new();
+        encode_exr(
+            &mut buffer,
+            2,
+            1,
+            &[Layer::rgb("", &color), Layer::depth("depth", &depth)],
+        )
+        .unwrap();
+
+        assert_eq!(&buffer[..4], &[0x76, 0x2f, 0x31, 0x01]);
+
+        //the offset table starts right after the header and points to the only scanline
+        let header_end = buffer.len() - 8 - (4 + 4 + 2 * 4 * 4);
+        let offset = u64::from_le_bytes([
+            buffer[header_end],
+            buffer[header_end + 1],
+            buffer[header_end + 2],
+            buffer[header_end + 3],
+            buffer[header_end + 4],
+            buffer[header_end + 5],
+            buffer[header_end + 6],
+            buffer[header_end + 7],
+        ]);
+        assert_eq!(offset as usize, header_end + 8);
+        assert_eq!(buffer[header_end - 1], 0);
+
+        //channels are sorted: B, G, R, depth.Z
+        let line = &buffer[header_end + 8 + 8..];
+        let values: Vec<f32> = line
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values, vec![0.5, 0.5, 0.5, 0.5, 0.5, 0.5, 7.0, 8.0]);
+    }
+}
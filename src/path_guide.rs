@@ -0,0 +1,502 @@
+use crate::hittables::aabb::AABB;
+use crate::math::pdf::PDF;
+use crate::math::vec3::Vec3;
+use crate::sampling::sampler::Sampler;
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::sync::Mutex;
+
+/// a region of space is split in half once more samples than this, times the square root of the
+/// frames of the iteration, were recorded in it
+/// (Müller et al., "Practical Path Guiding for Efficient Light-Transport Simulation", 2017)
+const SPATIAL_THRESHOLD: f32 = 12000.0;
+/// a quadrant of the directions is split once it received more than this fraction of the energy
+const DIRECTIONAL_THRESHOLD: f32 = 0.01;
+/// how many times the directions may be split into quadrants
+const MAX_DIRECTIONAL_DEPTH: u32 = 20;
+
+/// splits a square into 4 quadrants
+#[derive(Debug, Clone, Default)]
+struct QuadNode {
+    /// the energy that arrived in every quadrant
+    sums: [f32; 4],
+    /// the node every quadrant is split into, 0 if it is not split
+    children: [usize; 4],
+}
+
+impl QuadNode {
+    fn total(&self) -> f32 {
+        self.sums.iter().sum()
+    }
+}
+
+/// the quadrant of the unit square `point` is in, `point` is moved to where it is in the quadrant
+fn quadrant(point: &mut (f32, f32)) -> usize {
+    let right = point.0 >= 0.5;
+    let top = point.1 >= 0.5;
+    point.0 = point.0 * 2.0 - right as u32 as f32;
+    point.1 = point.1 * 2.0 - top as u32 as f32;
+    right as usize + 2 * top as usize
+}
+
+/// the energy arriving at a region of space from every direction, in a quadtree over the
+/// square `to_square` maps the directions to
+/// the quadtree is finer where more energy arrives
+#[derive(Debug, Clone)]
+struct DTree {
+    /// the root first
+    nodes: Vec<QuadNode>,
+    /// how many samples were recorded
+    samples: f32,
+}
+
+impl DTree {
+    fn new() -> Self {
+        DTree {
+            nodes: vec![QuadNode::default()],
+            samples: 0.0,
+        }
+    }
+
+    fn total(&self) -> f32 {
+        self.nodes[0].total()
+    }
+
+    /// adds `energy` to every quadrant `point` is in
+    fn record(&mut self, mut point: (f32, f32), energy: f32) {
+        self.samples += 1.0;
+        let mut node = 0;
+        loop {
+            let quadrant = quadrant(&mut point);
+            self.nodes[node].sums[quadrant] += energy;
+            match self.nodes[node].children[quadrant] {
+                0 => break,
+                child => node = child,
+            }
+        }
+    }
+
+    /// picks a point in the unit square, quadrants as likely as their energy
+    /// one pair of random numbers is rescaled on every level, first picking the column of the
+    /// quadrant, then its row
+    fn sample(&self, sampler: &mut dyn Sampler) -> (f32, f32) {
+        //picks the first or the second of two halves by their energy,
+        //and rescales the random number to the half it picked
+        let pick = |first: f32, second: f32, u: &mut f32| {
+            let total = first + second;
+            let probability = if total > 0.0 { first / total } else { 0.5 };
+            //rounding must not push it to 1, that would pick halves without any energy
+            let below_one = 1.0 - f32::EPSILON;
+            if *u < probability {
+                *u = (*u / probability).min(below_one);
+                0
+            } else {
+                *u = ((*u - probability) / (1.0 - probability)).min(below_one);
+                1
+            }
+        };
+
+        let (mut u, mut v) = sampler.next_2d();
+        let mut origin = (0.0, 0.0);
+        let mut size = 1.0;
+        let mut node = 0;
+        loop {
+            let sums = self.nodes[node].sums;
+            let column = pick(sums[0] + sums[2], sums[1] + sums[3], &mut u);
+            let row = pick(sums[column], sums[column + 2], &mut v);
+            let quadrant = column + 2 * row;
+
+            size *= 0.5;
+            origin.0 += size * column as f32;
+            origin.1 += size * row as f32;
+            match self.nodes[node].children[quadrant] {
+                0 => break,
+                child => node = child,
+            }
+        }
+
+        (origin.0 + u * size, origin.1 + v * size)
+    }
+
+    /// the density per area of the unit square with which `sample` picks `point`
+    fn pdf(&self, mut point: (f32, f32)) -> f32 {
+        let mut pdf = 1.0;
+        let mut node = 0;
+        loop {
+            let total = self.nodes[node].total();
+            if total <= 0.0 {
+                //uniform from here on
+                return pdf;
+            }
+            let quadrant = quadrant(&mut point);
+            pdf *= 4.0 * self.nodes[node].sums[quadrant] / total;
+            match self.nodes[node].children[quadrant] {
+                0 => return pdf,
+                child => node = child,
+            }
+        }
+    }
+
+    /// an empty quadtree, split wherever this one received much of its energy
+    /// quadrants that were not split yet are assumed to have received it evenly
+    fn refined(&self) -> DTree {
+        let mut refined = DTree::new();
+        let total = self.total();
+        if total <= 0.0 {
+            return refined;
+        }
+
+        //(the node of this tree, None below its leaves, the energy of the node,
+        // the refined node, its depth)
+        let mut stack = vec![(Some(0), total, 0, 1)];
+        while let Some((node, energy, refined_node, depth)) = stack.pop() {
+            for quadrant in 0..4 {
+                let quadrant_energy = match node {
+                    Some(node) => self.nodes[node].sums[quadrant],
+                    None => energy / 4.0,
+                };
+                if depth >= MAX_DIRECTIONAL_DEPTH
+                    || quadrant_energy <= total * DIRECTIONAL_THRESHOLD
+                {
+                    continue;
+                }
+
+                let child = refined.nodes.len();
+                refined.nodes.push(QuadNode::default());
+                refined.nodes[refined_node].children[quadrant] = child;
+                let old_child = node
+                    .map(|node| self.nodes[node].children[quadrant])
+                    .filter(|child| *child != 0);
+                stack.push((old_child, quadrant_energy, child, depth + 1));
+            }
+        }
+        refined
+    }
+}
+
+/// a box of the scene, split in half or holding the energy that arrived in it
+#[derive(Debug, Clone)]
+struct SpatialNode {
+    /// the axis the box is split along, 0 to 2 for x to z
+    axis: usize,
+    /// the lower and the upper half, None for leaves
+    children: Option<[usize; 2]>,
+    /// only recorded into in leaves
+    dtree: DTree,
+}
+
+/// a binary tree over the scene, with a quadtree of the arriving energy in every leaf
+/// regions where more paths pass through are split more finely
+#[derive(Debug, Clone)]
+struct SdTree {
+    bounds: AABB,
+    /// the root first
+    nodes: Vec<SpatialNode>,
+}
+
+impl SdTree {
+    fn new(bounds: AABB) -> Self {
+        SdTree {
+            bounds,
+            nodes: vec![SpatialNode {
+                axis: 0,
+                children: None,
+                dtree: DTree::new(),
+            }],
+        }
+    }
+
+    /// the quadtree of the leaf `point` is in
+    fn leaf(&self, point: Vec3) -> &DTree {
+        &self.nodes[self.leaf_index(point)].dtree
+    }
+
+    fn leaf_mut(&mut self, point: Vec3) -> &mut DTree {
+        let index = self.leaf_index(point);
+        &mut self.nodes[index].dtree
+    }
+
+    fn leaf_index(&self, point: Vec3) -> usize {
+        let size = self.bounds.end - self.bounds.start;
+        let relative = |value: f32, start: f32, size: f32| {
+            if size > 0.0 {
+                ((value - start) / size).clamp(0.0, 1.0)
+            } else {
+                0.5
+            }
+        };
+        let mut point = [
+            relative(point.x, self.bounds.start.x, size.x),
+            relative(point.y, self.bounds.start.y, size.y),
+            relative(point.z, self.bounds.start.z, size.z),
+        ];
+
+        let mut index = 0;
+        while let Some(children) = self.nodes[index].children {
+            let value = &mut point[self.nodes[index].axis];
+            let upper = *value >= 0.5;
+            *value = *value * 2.0 - upper as u32 as f32;
+            index = children[upper as usize];
+        }
+        index
+    }
+
+    /// an empty tree with the leaves that got more than `threshold` samples split,
+    /// and all the quadtrees refined
+    fn refined(&self, threshold: f32) -> SdTree {
+        let mut nodes = Vec::with_capacity(self.nodes.len());
+        self.refine_node(0, &mut nodes, threshold);
+        SdTree {
+            bounds: self.bounds,
+            nodes,
+        }
+    }
+
+    /// appends the refined `index` and its children to `nodes`, returns where it went
+    fn refine_node(&self, index: usize, nodes: &mut Vec<SpatialNode>, threshold: f32) -> usize {
+        let node = &self.nodes[index];
+        let position = nodes.len();
+        nodes.push(SpatialNode {
+            axis: node.axis,
+            children: None,
+            dtree: DTree::new(),
+        });
+
+        match node.children {
+            Some([lower, upper]) => {
+                let lower = self.refine_node(lower, nodes, threshold);
+                let upper = self.refine_node(upper, nodes, threshold);
+                nodes[position].children = Some([lower, upper]);
+            }
+            None => split_leaf(
+                nodes,
+                position,
+                node.dtree.refined(),
+                node.dtree.samples,
+                threshold,
+            ),
+        }
+        position
+    }
+}
+
+/// gives the leaf at `position` the quadtree `dtree`, or splits it in half until every half
+/// would have gotten at most `threshold` of its `samples`
+fn split_leaf(
+    nodes: &mut Vec<SpatialNode>,
+    position: usize,
+    dtree: DTree,
+    samples: f32,
+    threshold: f32,
+) {
+    if samples <= threshold {
+        nodes[position].dtree = dtree;
+        return;
+    }
+
+    let axis = (nodes[position].axis + 1) % 3;
+    let mut children = [0; 2];
+    for child in children.iter_mut() {
+        *child = nodes.len();
+        nodes.push(SpatialNode {
+            axis,
+            children: None,
+            dtree: DTree::new(),
+        });
+        split_leaf(nodes, *child, dtree.clone(), samples / 2.0, threshold);
+    }
+    nodes[position].children = Some(children);
+}
+
+/// the square [0, 1)² a direction is mapped to: the cosine of its angle to the z axis,
+/// and its angle around it
+/// equal areas of the square are equal solid angles
+fn to_square(direction: Vec3) -> (f32, f32) {
+    let direction = direction.normalised();
+    let cos_theta = direction.z.clamp(-1.0, 1.0);
+    let mut phi = direction.y.atan2(direction.x) / (2.0 * PI);
+    if phi < 0.0 {
+        phi += 1.0;
+    }
+    let below_one = 1.0 - f32::EPSILON;
+    (((cos_theta + 1.0) / 2.0).min(below_one), phi.min(below_one))
+}
+
+/// the direction at `point`, the inverse of `to_square`
+fn from_square(point: (f32, f32)) -> Vec3 {
+    let cos_theta = 2.0 * point.0 - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * point.1;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// path guiding: learns where the light arriving at the surfaces of the scene comes from,
+/// over the progressive frames, so paths can follow it instead of only their materials
+/// the frames are split into iterations that double in length: frame 1, frames 2 and 3,
+/// 4 to 7, and so on. the light of every iteration is recorded into a tree that guides the paths
+/// of the next one, while a finer tree records it again
+#[derive(Debug)]
+pub struct PathGuide {
+    /// samples directions, None until the first iteration ended
+    sampling: Option<SdTree>,
+    /// records the light of the current iteration
+    building: SdTree,
+    /// the light found in the current frame, by the corner of the tile that found it
+    /// only added to `building` once the frame is done, tile after tile: the sums of the
+    /// quadtrees would depend on which thread got there first
+    recorded: Mutex<BTreeMap<(u32, u32), Vec<GuideRecord>>>,
+}
+
+impl Clone for PathGuide {
+    fn clone(&self) -> Self {
+        PathGuide {
+            sampling: self.sampling.clone(),
+            building: self.building.clone(),
+            recorded: Mutex::new(self.recorded.lock().unwrap().clone()),
+        }
+    }
+}
+
+/// light a path found while rendering a tile: `radiance` arrived at `point` from `direction`,
+/// which the path sampled with `pdf`
+#[derive(Debug, Copy, Clone)]
+pub struct GuideRecord {
+    pub point: Vec3,
+    pub direction: Vec3,
+    pub radiance: f32,
+    pub pdf: f32,
+}
+
+impl PathGuide {
+    /// `bounds` should contain everything paths can hit
+    pub fn new(bounds: AABB) -> Self {
+        PathGuide {
+            sampling: None,
+            building: SdTree::new(bounds),
+            recorded: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// ends the iteration before `frame` if there is one, frame 1 starts learning from scratch
+    pub fn prepare_frame(&mut self, frame: u32) {
+        self.add_recorded();
+        if frame <= 1 {
+            *self = PathGuide::new(self.building.bounds);
+            return;
+        }
+        if !frame.is_power_of_two() {
+            return;
+        }
+
+        //the iteration that ended was half as long as the frames so far
+        let threshold = SPATIAL_THRESHOLD * (frame as f32 / 2.0).sqrt();
+        let refined = self.building.refined(threshold);
+        self.sampling = Some(std::mem::replace(&mut self.building, refined));
+    }
+
+    /// the directions in which light arrived around `point`, None if nothing is known there yet
+    pub fn pdf_at(&self, point: Vec3) -> Option<GuidePDF<'_>> {
+        let dtree = self.sampling.as_ref()?.leaf(point);
+        if dtree.total() > 0.0 {
+            Some(GuidePDF { dtree })
+        } else {
+            None
+        }
+    }
+
+    /// keeps what a path found in the tile with the corner `tile`, until the frame is done
+    pub fn record(&self, tile: (u32, u32), records: Vec<GuideRecord>) {
+        self.recorded
+            .lock()
+            .unwrap()
+            .entry(tile)
+            .or_default()
+            .extend(records);
+    }
+
+    /// adds the light found in the last frame to `building`
+    fn add_recorded(&mut self) {
+        let recorded = std::mem::take(self.recorded.get_mut().unwrap());
+        for record in recorded.values().flatten() {
+            let energy = record.radiance / record.pdf;
+            if record.pdf > 0.0 && energy.is_finite() && energy >= 0.0 {
+                self.building
+                    .leaf_mut(record.point)
+                    .record(to_square(record.direction), energy);
+            }
+        }
+    }
+}
+
+/// directions as likely as the light that arrived from them
+pub struct GuidePDF<'a> {
+    dtree: &'a DTree,
+}
+
+impl<'a> PDF<Vec3> for GuidePDF<'a> {
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        from_square(self.dtree.sample(sampler))
+    }
+
+    fn value_at(&self, p: Vec3) -> f32 {
+        //the square covers the 4 pi of the sphere evenly
+        self.dtree.pdf(to_square(p)) / (4.0 * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::sampler::RandomSampler;
+
+    #[test]
+    fn test_square_round_trip() {
+        for &(u, v) in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.3, 0.95)] {
+            let (u2, v2) = to_square(from_square((u, v)));
+            assert!((u - u2).abs() < 1e-4 && (v - v2).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_guide_follows_the_light() {
+        let bounds = AABB::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let mut guide = PathGuide::new(bounds);
+        let point = Vec3::new(0.2, 0.3, 0.4);
+        let light = Vec3::new(0.0, 0.6, 0.8);
+
+        //light from one direction, a little from everywhere else
+        let mut sampler = RandomSampler::new(3);
+        sampler.start_sample(0, 0, 0);
+        for iteration in 1..4 {
+            guide.prepare_frame(1 << iteration);
+            for _ in 0..1000 {
+                let direction = from_square(sampler.next_2d());
+                let record = |direction, radiance| GuideRecord {
+                    point,
+                    direction,
+                    radiance,
+                    pdf: 1.0 / (4.0 * PI),
+                };
+                guide.record((0, 0), vec![record(direction, 0.1), record(light, 10.0)]);
+            }
+        }
+        guide.prepare_frame(16);
+
+        let pdf = guide.pdf_at(point).unwrap();
+        assert!(pdf.value_at(light) > 10.0 / (4.0 * PI));
+        assert!(pdf.value_at(-light) < 1.0 / (4.0 * PI));
+
+        //most samples go towards the light, with the density they are generated with
+        let mut towards_light = 0;
+        for _ in 0..100 {
+            let direction = pdf.generate(&mut sampler);
+            if direction.dot(light) > 0.9 {
+                towards_light += 1;
+            }
+            let square = to_square(direction);
+            let expected = guide.sampling.as_ref().unwrap().leaf(point).pdf(square);
+            assert!((pdf.value_at(direction) - expected / (4.0 * PI)).abs() < 1e-3 * expected);
+        }
+        assert!(towards_light > 50);
+    }
+}
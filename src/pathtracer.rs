@@ -1,12 +1,79 @@
+use crate::gfx::environment::Environment;
 use crate::gfx::texture::Texture;
-use rand::{prelude::ThreadRng, Rng};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 use crate::camera::Camera;
-use crate::hit::Hit;
+use crate::checkpoint::RenderSettings;
+use crate::film::{AdaptiveSampling, PixelStats, Splat};
+use crate::gfx::material::BounceKind;
+use crate::gfx::spectrum::Wavelengths;
+use crate::hit::{Hit, HitResult};
+use crate::hittables::aabb::AABB;
 use crate::hittables::bvh::BvhTree;
+use crate::integrators::ambient_occlusion::AmbientOcclusion;
+use crate::integrators::bdpt::BdptIntegrator;
+use crate::integrators::bvh_heatmap::BvhHeatmap;
+use crate::integrators::debug::{AlbedoIntegrator, NormalIntegrator};
+use crate::integrators::integrator::{Integrator, IntegratorKind};
+use crate::integrators::mlt::{MltChains, MltIntegrator};
+use crate::integrators::path::PathIntegrator;
+use crate::integrators::photon::PhotonIntegrator;
+use crate::lights::Lights;
+use crate::math::pdf::{LightPDF, MisHeuristic};
+use crate::math::random;
 use crate::math::vec3::Vec3;
-use crate::ray::Ray;
+use crate::path_guide::PathGuide;
+use crate::photon_map::{self, PhotonMap};
+use crate::sampling::sampler::{Sampler, SamplerKind};
+use crate::tiles::{RenderedTile, Tile};
+
+/// how many times a path may bounce unless configured otherwise
+pub const DEFAULT_MAX_DEPTH: u32 = 100;
+/// after how many bounces paths may be ended by russian roulette unless configured otherwise
+pub const DEFAULT_ROULETTE_DEPTH: u32 = 3;
+/// how many photons every frame emits with photon mapping unless configured otherwise
+pub const DEFAULT_PHOTONS: u32 = 100_000;
+/// how far photons are gathered from in the first frame unless configured otherwise
+pub const DEFAULT_PHOTON_RADIUS: f32 = 0.1;
+
+/// how many bounces a path may take in total, and of each kind, before it ends
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BounceLimits {
+    pub total: u32,
+    pub diffuse: u32,
+    pub glossy: u32,
+    pub transmission: u32,
+    pub volume: u32,
+}
+
+impl BounceLimits {
+    /// the same limit for everything
+    pub fn new(max_depth: u32) -> Self {
+        BounceLimits {
+            total: max_depth,
+            diffuse: max_depth,
+            glossy: max_depth,
+            transmission: max_depth,
+            volume: max_depth,
+        }
+    }
+
+    pub fn of_kind(&self, kind: BounceKind) -> u32 {
+        match kind {
+            BounceKind::Diffuse => self.diffuse,
+            BounceKind::Glossy => self.glossy,
+            BounceKind::Transmission => self.transmission,
+            BounceKind::Volume => self.volume,
+        }
+    }
+}
+
+impl Default for BounceLimits {
+    fn default() -> Self {
+        BounceLimits::new(DEFAULT_MAX_DEPTH)
+    }
+}
 
 #[derive(Clone)]
 pub struct PathTracer {
@@ -16,9 +83,39 @@ pub struct PathTracer {
     incremental: bool,
     pub camera: Camera,
     objects: Vec<Arc<dyn Hit>>,
-    sky: Arc<dyn Texture>,
+    /// sampled directly like the lights, if it is not black
+    sky: Environment,
     pub bvh: Option<BvhTree<Arc<dyn Hit>>>,
-    pub debug_index: Option<usize>,
+    /// the emissive objects, sampled directly at every diffuse vertex of a path
+    lights: Lights,
+    /// if set, pixels stop being sampled once their noise is low enough
+    adaptive: Option<AdaptiveSampling>,
+    /// all random numbers are derived from this, see `random::sample_rng`
+    seed: u64,
+    /// how the random numbers of the samples are distributed
+    sampler: SamplerKind,
+    /// paths end once they reach any of these
+    bounce_limits: BounceLimits,
+    /// paths with little throughput may randomly end after this many bounces
+    roulette_depth: u32,
+    /// combines sampling the material with sampling the lights
+    mis: MisHeuristic,
+    /// the algorithm that computes what the camera rays see
+    integrator: IntegratorKind,
+    /// how many photons every frame emits with photon mapping
+    photon_count: u32,
+    /// how far photons are gathered from in the first frame, it shrinks over the frames
+    photon_radius: f32,
+    /// the photons of the current frame, only built for photon mapping
+    photon_map: Option<PhotonMap>,
+    /// camera rays carry wavelengths instead of rgb colors
+    spectral: bool,
+    /// the path integrator learns where light comes from over the frames
+    guiding: bool,
+    /// what was learned so far, only kept for the path integrator with guiding
+    path_guide: Option<PathGuide>,
+    /// the Markov chains, only kept for the Metropolis integrator
+    mlt_chains: Option<MltChains>,
 }
 
 impl PathTracer {
@@ -41,12 +138,246 @@ impl PathTracer {
             incremental,
             camera,
             objects: Vec::new(),
-            sky,
+            sky: Environment::new(sky),
             bvh: None,
-            debug_index: None,
+            lights: Lights::new(Vec::new()),
+            adaptive: None,
+            seed: 0,
+            sampler: SamplerKind::default(),
+            bounce_limits: BounceLimits::default(),
+            roulette_depth: DEFAULT_ROULETTE_DEPTH,
+            mis: MisHeuristic::default(),
+            integrator: IntegratorKind::default(),
+            photon_count: DEFAULT_PHOTONS,
+            photon_radius: DEFAULT_PHOTON_RADIUS,
+            photon_map: None,
+            spectral: false,
+            guiding: false,
+            path_guide: None,
+            mlt_chains: None,
+        }
+    }
+
+    /// true if every frame only renders 1 sample and gets averaged with the previous frames
+    /// everything that changes what a frame adds to the image, besides the scene and the camera
+    pub fn render_settings(&self) -> RenderSettings {
+        RenderSettings {
+            samples: self.samples,
+            adaptive: self.adaptive,
+            seed: self.seed,
+            sampler: self.sampler,
+            bounce_limits: self.bounce_limits,
+            roulette_depth: self.roulette_depth,
+            mis: self.mis,
+            integrator: self.integrator,
+            photon_count: self.photon_count,
+            photon_radius: self.photon_radius,
+            spectral: self.spectral,
+            guiding: self.guiding,
+        }
+    }
+
+    pub fn is_incremental(&self) -> bool {
+        self.incremental
+    }
+
+    pub fn set_adaptive_sampling(&mut self, adaptive: Option<AdaptiveSampling>) {
+        self.adaptive = adaptive;
+    }
+
+    pub fn adaptive_sampling(&self) -> Option<AdaptiveSampling> {
+        self.adaptive
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn set_sampler(&mut self, sampler: SamplerKind) {
+        self.sampler = sampler;
+    }
+
+    pub fn set_bounce_limits(&mut self, bounce_limits: BounceLimits) {
+        self.bounce_limits = bounce_limits;
+    }
+
+    pub fn set_roulette_depth(&mut self, roulette_depth: u32) {
+        self.roulette_depth = roulette_depth;
+    }
+
+    pub fn set_mis_heuristic(&mut self, mis: MisHeuristic) {
+        self.mis = mis;
+    }
+
+    pub fn set_integrator(&mut self, integrator: IntegratorKind) {
+        self.integrator = integrator;
+    }
+
+    pub fn integrator_kind(&self) -> IntegratorKind {
+        self.integrator
+    }
+
+    /// `count` photons per frame, gathered within `radius` in the first frame
+    pub fn set_photons(&mut self, count: u32, radius: f32) {
+        self.photon_count = count;
+        self.photon_radius = radius;
+    }
+
+    pub fn set_spectral(&mut self, spectral: bool) {
+        self.spectral = spectral;
+    }
+
+    pub fn set_guiding(&mut self, guiding: bool) {
+        self.guiding = guiding;
+    }
+
+    /// the wavelengths a camera ray carries, None if it carries rgb colors
+    /// only integrators that compute light transport trace wavelengths
+    pub fn sample_wavelengths(&self, sampler: &mut dyn Sampler) -> Option<Wavelengths> {
+        if self.spectral && self.integrator.transports_light() {
+            Some(Wavelengths::sample(sampler.next_1d()))
+        } else {
+            None
+        }
+    }
+
+    /// the photons of the current frame, if the integrator uses them
+    pub fn photon_map(&self) -> Option<&PhotonMap> {
+        self.photon_map.as_ref()
+    }
+
+    /// where light arrived from in the earlier frames, if the paths are guided
+    pub fn path_guide(&self) -> Option<&PathGuide> {
+        self.path_guide.as_ref()
+    }
+
+    /// does the work the integrator needs before the tiles of `frame` are rendered,
+    /// only after `finalise`
+    /// returns the number of traced rays
+    pub fn prepare_frame(&mut self, frame: u32) -> u64 {
+        if self.guiding && self.integrator == IntegratorKind::Path {
+            let bounds = self.world().bounding_box();
+            self.path_guide
+                .get_or_insert_with(|| {
+                    let origin = Vec3::new(0.0, 0.0, 0.0);
+                    PathGuide::new(bounds.unwrap_or_else(|| AABB::new(origin, origin)))
+                })
+                .prepare_frame(frame);
+        } else {
+            self.path_guide = None;
+        }
+
+        //a new view needs new chains, else they go on where the last frame left them
+        let mut rays = 0;
+        if self.integrator != IntegratorKind::Metropolis {
+            self.mlt_chains = None;
+        } else if frame == 1 || self.mlt_chains.is_none() {
+            let seed = random::hash(&[self.seed]);
+            let (chains, traced) = MltChains::bootstrap(self, &self.path_integrator(), seed);
+            self.mlt_chains = Some(chains);
+            rays += traced;
+        } else if let Some(mut chains) = self.mlt_chains.take() {
+            rays += chains.refine(self, &self.path_integrator(), frame);
+            self.mlt_chains = Some(chains);
+        }
+
+        if self.integrator != IntegratorKind::PhotonMapping {
+            self.photon_map = None;
+            return rays;
+        }
+
+        let (photons, photons_rays) = photon_map::trace_photons(
+            self,
+            self.photon_count,
+            self.bounce_limits.total,
+            self.seed,
+            frame,
+        );
+        let radius = photon_map::radius(self.photon_radius, frame);
+        self.photon_map = Some(PhotonMap::new(photons, radius));
+        rays + photons_rays
+    }
+
+    /// the path integrator, configured with the settings of this tracer
+    fn path_integrator(&self) -> PathIntegrator {
+        PathIntegrator::new(self.bounce_limits, self.roulette_depth, self.mis)
+    }
+
+    /// the selected integrator for `tile`, configured with the settings of this tracer
+    fn integrator(&self, tile: &Tile) -> Box<dyn Integrator + '_> {
+        match self.integrator {
+            IntegratorKind::Path => Box::new(self.path_integrator().for_tile(tile)),
+            IntegratorKind::Bidirectional => Box::new(BdptIntegrator::new(
+                self.bounce_limits,
+                self.roulette_depth,
+                self.mis,
+            )),
+            IntegratorKind::Metropolis => Box::new(MltIntegrator::new(
+                self.path_integrator(),
+                self.mlt_chains
+                    .as_ref()
+                    .expect("did not call prepare_frame()!"),
+                tile,
+            )),
+            //a single bounce, the roulette would only add noise there
+            IntegratorKind::PhotonMapping => Box::new(PhotonIntegrator::new(
+                self.bounce_limits.total,
+                PathIntegrator::new(BounceLimits::new(1), 1, self.mis),
+                self.mis,
+            )),
+            IntegratorKind::Direct => {
+                Box::new(PathIntegrator::new(BounceLimits::new(1), 1, self.mis))
+            }
+            IntegratorKind::AmbientOcclusion => Box::new(AmbientOcclusion),
+            IntegratorKind::Albedo => Box::new(AlbedoIntegrator),
+            IntegratorKind::Normal => Box::new(NormalIntegrator),
+            //a ray that hits something tests about 2 boxes on every level of the tree
+            IntegratorKind::BvhHeatmap => {
+                Box::new(BvhHeatmap::new(4 * self.world().stats().depth as u32))
+            }
         }
     }
 
+    /// everything that can be hit, only available after `finalise`
+    pub fn world(&self) -> &BvhTree<Arc<dyn Hit>> {
+        self.bvh.as_ref().expect("did not call finalise()!")
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// the emissive objects that can be sampled directly
+    pub fn lights(&self) -> &Lights {
+        &self.lights
+    }
+
+    /// picks one of the lights by its power, then a point on it uniformly
+    /// # Return Value
+    /// Returns Tuple of (the point, the density per area of picking it)
+    pub fn sample_light_point(&self, sampler: &mut dyn Sampler) -> Option<(HitResult, f32)> {
+        self.lights.sample_point(sampler)
+    }
+
+    /// true if there is anything to sample directly: emissive objects or a sky that is not black
+    pub fn has_lights(&self) -> bool {
+        !self.lights.is_empty() || self.sky.can_sample()
+    }
+
+    /// directions from `origin` towards the lights and the sky, only if `has_lights`
+    pub fn light_pdf(&self, origin: Vec3) -> LightPDF<'_> {
+        LightPDF::new(&self.lights, Some(&self.sky), origin)
+    }
+
+    /// the color of the sky in `direction`
+    pub fn sky_color(&self, direction: Vec3) -> Vec3 {
+        self.sky.color(direction)
+    }
+
     pub fn add_object(&mut self, object: Arc<dyn Hit>) {
         self.objects.push(object);
     }
@@ -55,6 +386,15 @@ impl PathTracer {
     //TODO: make it so that finalise leaves renderer immutable?
     //-> builder pattern?
     pub fn finalise(mut self) -> Self {
+        //the lights are shared with the bvh
+        self.lights = Lights::new(
+            self.objects
+                .iter()
+                .filter(|object| object.is_light())
+                .cloned()
+                .collect(),
+        );
+
         //build the bvh from our objects (MOVED!!!)
         self.bvh = Some(BvhTree::from_hittables(self.objects));
 
@@ -87,160 +427,138 @@ impl PathTracer {
         Vec3::new(buf[0 + position], buf[1 + position], buf[2 + position])
     }*/
 
-    pub fn render_pixel(
-        &self,
-        rng: &mut ThreadRng,
-        index: usize,
-        frame: u32,
-        color_buf: &mut [f32],
-        albedo_buf: &mut [f32],
-        normal_buf: &mut [f32],
-        depth_buf: &mut [f32],
-    ) {
-        let pixel = index as u32; //divided by 3 because RGB
-        let x = pixel % self.width;
-        let y = pixel / self.width; //is floored
-
-        //draw image
-        let bvh = self.bvh.as_ref().expect("did not call finalise()!");
-
-        let mut final_color = Vec3::rgb(0, 0, 0);
-        let mut final_albedo = Vec3::rgb(0, 0, 0);
-        let mut final_normal = Vec3::rgb(0, 0, 0);
-        let mut final_depth = 0.0;
+    /// the adaptive sampling of the pixels, none if the integrator splats the light
+    /// and the statistics of the pixels miss it
+    fn pixel_adaptive(&self) -> Option<&AdaptiveSampling> {
+        self.adaptive.as_ref().filter(|_| !self.integrator.splats())
+    }
 
-        //multisample
-        for _ in 0..self.samples {
-            let ray = self.camera.get_ray(
-                x as f32 + rng.gen_range(0.0, 1.0), //the rng is for random multisampling within the pixel
-                y as f32 + rng.gen_range(0.0, 1.0),
+    /// renders every pixel of `tile`
+    /// `stats` are the sample statistics of the tile's pixels from the previous frames
+    pub fn render_tile(&self, tile: Tile, stats: Vec<PixelStats>) -> RenderedTile {
+        let mut rendered = RenderedTile::new(tile, stats);
+        let mut rays = 0;
+
+        let pixel_samples = match self.pixel_adaptive() {
+            Some(adaptive) => adaptive.max_samples,
+            None => self.samples,
+        };
+        let mut sampler = self.sampler.create(self.seed, pixel_samples);
+        let integrator = self.integrator(&tile);
+
+        let pixels = rendered
+            .color
+            .chunks_mut(3)
+            .zip(rendered.albedo.chunks_mut(3))
+            .zip(rendered.normal.chunks_mut(3))
+            .zip(rendered.depth.chunks_mut(3))
+            .zip(rendered.stats.iter_mut())
+            .enumerate();
+
+        for (index, ((((color_buf, albedo_buf), normal_buf), depth_buf), stats)) in pixels {
+            let x = tile.x + index as u32 % tile.width;
+            let y = tile.y + index as u32 / tile.width;
+            let (color, albedo, normal, depth, traced) = self.render_pixel(
+                x,
+                y,
+                stats,
+                integrator.as_ref(),
+                sampler.as_mut(),
+                &mut rendered.splats,
             );
 
-            let (color, albedo, normal, depth) = self.trace_color(&ray, bvh);
+            color_buf[0] = color.x;
+            color_buf[1] = color.y;
+            color_buf[2] = color.z;
 
-            final_color += color;
-            final_albedo += albedo;
-            final_normal += normal; //[-1,1]
-            final_depth += depth;
-        }
-
-        //normalize color after sampling a lot
-        final_color /= self.samples as f32;
-        final_albedo /= self.samples as f32;
-        final_normal /= self.samples as f32;
-        final_depth /= self.samples as f32;
-
-        if self.incremental && frame > 1 {
-            let k = 1.0 / frame as f32;
-            let km1 = (frame - 1) as f32 / frame as f32;
-
-            final_color =
-                (Vec3::new(color_buf[0], color_buf[1], color_buf[2]) * km1) + (final_color * k);
-            final_albedo =
-                (Vec3::new(albedo_buf[0], albedo_buf[1], albedo_buf[2]) * km1) + (final_albedo * k);
-            final_normal =
-                (Vec3::new(normal_buf[0], normal_buf[1], normal_buf[2]) * km1) + (final_normal * k);
-            final_depth =
-                (Vec3::new(depth_buf[0], depth_buf[1], depth_buf[2]).x * km1) + (final_depth * k);
-        }
+            albedo_buf[0] = albedo.x;
+            albedo_buf[1] = albedo.y;
+            albedo_buf[2] = albedo.z;
 
-        color_buf[0] = final_color.x;
-        color_buf[1] = final_color.y;
-        color_buf[2] = final_color.z;
+            normal_buf[0] = normal.x;
+            normal_buf[1] = normal.y;
+            normal_buf[2] = normal.z;
 
-        albedo_buf[0] = final_albedo.x;
-        albedo_buf[1] = final_albedo.y;
-        albedo_buf[2] = final_albedo.z;
+            depth_buf[0] = depth;
+            depth_buf[1] = depth;
+            depth_buf[2] = depth;
 
-        normal_buf[0] = final_normal.x;
-        normal_buf[1] = final_normal.y;
-        normal_buf[2] = final_normal.z;
+            rays += traced;
+        }
 
-        depth_buf[0] = final_depth;
-        depth_buf[1] = final_depth;
-        depth_buf[2] = final_depth;
+        rendered.rays = rays;
+        rendered
     }
 
+    /// renders the samples of the pixel at `x`, `y` and adds them to `stats`
+    /// light that lands on other pixels is added to `splats`
+    /// with adaptive sampling, this stops as soon as the pixel is converged,
+    /// possibly without taking any sample at all
     /// # Return Value
-    /// Returns Tuple of (Color, Albedo, Normal, Depth)
-    fn trace_color(&self, ray: &Ray, object: &dyn Hit) -> (Vec3, Vec3, Vec3, f32) {
-        // recursively, this was:
-        // return emitted + attenuation * scattering_pdf() * trace_color() / pdf
-        // -> e1 + a1 * s1 * (1/pdf1) * ( e2 + a2 * s2 * (1/pdf2) * (...) )
-        // -> 1 * (...)
-        // -> 0 + 1*e1 + (a1*s1*(1/pdf1))*e2 + (a1*s1*(1/pdf1))*(a2*s2*(1/pdf2)) ...
-        // that's a sum!
-
-        let mut ray_to_use = *ray;
-        let mut final_attenuation = Vec3::new(1.0, 1.0, 1.0);
-
-        let mut bounces: u32 = 0;
-        const MAX_BOUNCES: u32 = 100;
-
-        let mut out_color = Vec3::new(0.0, 0.0, 0.0);
-        let mut out_albedo = None;
-        let mut out_normal = None;
-        let mut out_depth = None;
-
-        while let Some(hit) = object.hit(&ray_to_use, 0.0001, std::f32::MAX) {
-            if bounces > MAX_BOUNCES {
-                break;
-            }
-            bounces += 1;
-
-            let mat = hit
-                .material
-                .as_ref()
-                .expect("How did you manage to not have a material?!");
-
-            //emitted is even added if we do not scatter!
-            let emitted = mat.emitted(&hit);
-            out_color += final_attenuation * emitted;
+    /// Returns Tuple of (Color, Albedo, Normal, Depth, number of traced rays),
+    /// averaged over the new samples
+    pub fn render_pixel(
+        &self,
+        x: u32,
+        y: u32,
+        stats: &mut PixelStats,
+        integrator: &dyn Integrator,
+        sampler: &mut dyn Sampler,
+        splats: &mut Vec<Splat>,
+    ) -> (Vec3, Vec3, Vec3, f32, u64) {
+        let mut final_color = Vec3::rgb(0, 0, 0);
+        let mut final_albedo = Vec3::rgb(0, 0, 0);
+        let mut final_normal = Vec3::rgb(0, 0, 0);
+        let mut final_depth = 0.0;
+        let mut rays = 0;
 
-            if let Some((albedo, normal, scattered_ray, pdf)) = mat.scattered(&ray_to_use, &hit) {
-                let brdf = albedo * mat.scattering_pdf(&ray, &hit, &scattered_ray);
-                final_attenuation *= brdf / pdf;
-                ray_to_use = scattered_ray;
+        //incremental frames keep their single sample, the film decides across frames
+        let budget = match self.pixel_adaptive() {
+            Some(adaptive) if !self.incremental => adaptive.max_samples,
+            _ => self.samples,
+        };
 
-                if out_albedo.is_none() {
-                    out_albedo = Some(albedo)
-                }
-                if out_normal.is_none() {
-                    out_normal = Some(normal)
+        //multisample
+        let mut taken = 0;
+        while taken < budget {
+            if let Some(adaptive) = self.pixel_adaptive() {
+                if adaptive.is_done(stats) {
+                    break;
                 }
-                if out_depth.is_none() {
-                    out_depth = Some(1.0 / hit.ray_param)
-                } // x/0 = inf !
             }
-        }
 
-        //calculate uv coords from ray direction
-        let x = ray_to_use.direction.x;
-        let z = ray_to_use.direction.z;
-        let u = 1.0 - ((z.atan2(x) + std::f32::consts::PI) / (2.0 * std::f32::consts::PI));
+            //the n-th sample of a pixel always gets the same random numbers
+            sampler.start_sample(x, y, stats.samples);
 
-        //clamp to [-1, 1] just in case (asin might return nan)
-        let y = -ray_to_use.direction.y.min(1.0).max(-1.0);
-        let v = (y.asin() + std::f32::consts::FRAC_PI_2) / std::f32::consts::PI;
+            //random multisampling within the pixel
+            let (jitter_x, jitter_y) = sampler.next_2d();
+            let ray = self
+                .camera
+                .get_ray(x as f32 + jitter_x, y as f32 + jitter_y, sampler);
+            let ray = ray.with_wavelengths(self.sample_wavelengths(sampler));
 
-        let skycolor = self.sky.texture((u, v));
+            let (color, albedo, normal, depth, traced) =
+                integrator.trace(self, &ray, sampler, splats);
+            let color = ray.to_rgb(color);
 
-        if out_albedo.is_none() {
-            out_albedo = Some(skycolor)
-        }
-        if out_normal.is_none() {
-            out_normal = Some(-ray_to_use.direction)
+            final_color += color;
+            final_albedo += albedo;
+            final_normal += normal; //[-1,1]
+            final_depth += depth;
+            rays += traced as u64;
+
+            stats.add(color.luminance());
+            taken += 1;
         }
-        if out_depth.is_none() {
-            out_depth = Some(0.0)
+
+        //normalize color after sampling a lot
+        if taken > 0 {
+            final_color /= taken as f32;
+            final_albedo /= taken as f32;
+            final_normal /= taken as f32;
+            final_depth /= taken as f32;
         }
 
-        out_color += skycolor * final_attenuation;
-        (
-            out_color,
-            out_albedo.unwrap(),
-            out_normal.unwrap(),
-            out_depth.unwrap(),
-        )
+        (final_color, final_albedo, final_normal, final_depth, rays)
     }
 }
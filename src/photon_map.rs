@@ -0,0 +1,284 @@
+use crate::gfx::material::BounceKind;
+use crate::hit::Hit;
+use crate::hittables::aabb::{Axis, AABB};
+use crate::math::pdf::{CosinePDF, PDF};
+use crate::math::vec3::Vec3;
+use crate::pathtracer::PathTracer;
+use crate::ray::Ray;
+use crate::sampling::sampler::{RandomSampler, Sampler};
+use rayon::prelude::*;
+
+/// how fast the gather radius shrinks over the frames, between 0 and 1
+/// (Knaus and Zwicker, "Progressive Photon Mapping: A Probabilistic Approach", 2011)
+const ALPHA: f32 = 2.0 / 3.0;
+
+/// light that arrived at a diffuse surface after at least one bounce
+#[derive(Debug, Copy, Clone)]
+pub struct Photon {
+    pub position: Vec3,
+    /// points back to where the photon came from
+    pub direction: Vec3,
+    pub power: Vec3,
+}
+
+/// the photons of a frame in a balanced kd-tree
+/// the tree is implicit: the median of every range is its root, the ranges left and right of it
+/// are its subtrees
+#[derive(Clone)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    /// the axis every photon splits its subtree at
+    axes: Vec<Axis>,
+    /// photons are gathered from within this distance
+    radius: f32,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>, radius: f32) -> Self {
+        let mut axes = vec![Axis::X; photons.len()];
+        build_subtree(&mut photons, &mut axes);
+        PhotonMap {
+            photons,
+            axes,
+            radius,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// calls `found` for every photon closer to `point` than the radius of the map
+    pub fn gather<F: FnMut(&Photon)>(&self, point: Vec3, mut found: F) {
+        self.gather_subtree(0, self.photons.len(), point, &mut found);
+    }
+
+    fn gather_subtree<F: FnMut(&Photon)>(
+        &self,
+        start: usize,
+        end: usize,
+        point: Vec3,
+        found: &mut F,
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        if (photon.position - point).len_squared() < self.radius * self.radius {
+            found(photon);
+        }
+
+        //the side of the split the point is on first, the other one only if the sphere reaches it
+        let axis = self.axes[middle];
+        let offset = coordinate(point, axis) - coordinate(photon.position, axis);
+        let (near, far) = if offset < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.gather_subtree(near.0, near.1, point, found);
+        if offset * offset < self.radius * self.radius {
+            self.gather_subtree(far.0, far.1, point, found);
+        }
+    }
+}
+
+fn coordinate(point: Vec3, axis: Axis) -> f32 {
+    match axis {
+        Axis::X => point.x,
+        Axis::Y => point.y,
+        Axis::Z => point.z,
+    }
+}
+
+/// moves the median along the longest axis of `photons` to the middle, the smaller ones before it
+/// and the larger ones after it, then does the same for both halves
+fn build_subtree(photons: &mut [Photon], axes: &mut [Axis]) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let first = photons[0].position;
+    let bounds = photons
+        .iter()
+        .fold(AABB::new(first, first), |bounds, photon| {
+            let point = AABB::new(photon.position, photon.position);
+            AABB::surrounding_box(&bounds, &point)
+        });
+    let axis = bounds.longest_axis();
+
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        coordinate(a.position, axis)
+            .partial_cmp(&coordinate(b.position, axis))
+            .unwrap()
+    });
+    axes[middle] = axis;
+
+    let (photons_before, photons_after) = photons.split_at_mut(middle);
+    let (axes_before, axes_after) = axes.split_at_mut(middle);
+    build_subtree(photons_before, axes_before);
+    build_subtree(&mut photons_after[1..], &mut axes_after[1..]);
+}
+
+/// the gather radius of `frame`, starting with `initial` in the first one
+/// it shrinks slowly enough that every frame still finds photons, so the average of the frames
+/// converges to the correct image
+pub fn radius(initial: f32, frame: u32) -> f32 {
+    let mut radius_squared = initial * initial;
+    for i in 1..frame.max(1) {
+        radius_squared *= (i as f32 + ALPHA) / (i as f32 + 1.0);
+    }
+    radius_squared.sqrt()
+}
+
+/// emits `count` photons from the lights of `tracer` and follows them for up to `max_depth` bounces
+/// every frame emits different photons
+/// # Return Value
+/// Returns Tuple of (photons that arrived at diffuse surfaces, number of traced rays)
+pub fn trace_photons(
+    tracer: &PathTracer,
+    count: u32,
+    max_depth: u32,
+    seed: u64,
+    frame: u32,
+) -> (Vec<Photon>, u64) {
+    (0..count)
+        .into_par_iter()
+        .fold(
+            || (RandomSampler::new(seed), Vec::new(), 0),
+            |(mut sampler, mut photons, rays), index| {
+                sampler.start_sample(index, frame, 0);
+                let traced = trace_photon(tracer, count, max_depth, &mut sampler, &mut photons);
+                (sampler, photons, rays + traced)
+            },
+        )
+        .map(|(_, photons, rays)| (photons, rays))
+        .reduce(
+            || (Vec::new(), 0),
+            |(mut photons, rays), (other, other_rays)| {
+                photons.extend(other);
+                (photons, rays + other_rays)
+            },
+        )
+}
+
+/// emits one of `count` photons, and adds it to `photons` wherever it lands on a diffuse surface
+/// returns the number of traced rays
+fn trace_photon(
+    tracer: &PathTracer,
+    count: u32,
+    max_depth: u32,
+    sampler: &mut dyn Sampler,
+    photons: &mut Vec<Photon>,
+) -> u64 {
+    sampler.start_vertex(0);
+    let (light, pdf) = match tracer.sample_light_point(sampler) {
+        Some(sampled) => sampled,
+        None => return 0,
+    };
+    let emitted = match &light.material {
+        Some(mat) => mat.emitted(&light),
+        None => return 0,
+    };
+
+    //lights emit to both sides, cosine weighted: the cosine cancels with the direction pdf
+    let normal = if sampler.next_1d() < 0.5 {
+        light.normal
+    } else {
+        -light.normal
+    };
+    let direction = CosinePDF::new(normal).generate(sampler);
+    let mut power = emitted * 2.0 * std::f32::consts::PI / (pdf * count as f32);
+    let mut ray = Ray::new(light.hit_position, direction);
+
+    let mut rays = 0;
+    for depth in 0..max_depth {
+        rays += 1;
+        sampler.start_vertex(depth + 1);
+        let hit = match tracer.world().hit(&ray, 0.0001, f32::MAX, sampler) {
+            Some(hit) => hit,
+            None => break,
+        };
+        let mat = match &hit.material {
+            Some(mat) => mat,
+            None => break,
+        };
+        //photons carry rgb power, not wavelengths
+        let bsdf = match mat.bsdf(&hit, None) {
+            Some(bsdf) => bsdf,
+            None => break,
+        };
+
+        //light arriving directly from a light is sampled by the camera paths
+        if depth > 0 && !bsdf.lobes().is_specular() && mat.bounce_kind(&hit) != BounceKind::Volume {
+            photons.push(Photon {
+                position: hit.hit_position,
+                direction: -ray.direction,
+                power,
+            });
+        }
+
+        //photons travel the other way than the light a bsdf is sampled for:
+        //they arrive from `wo` and leave into `wi`
+        let sample = match bsdf.sample(-ray.direction, sampler) {
+            Some(sample) => sample,
+            None => break,
+        };
+        let factor = sample.f / sample.pdf;
+
+        //russian roulette: photons keep their power, only as many survive as the surface reflects
+        let survival = factor.max_component().min(1.0);
+        if sampler.next_1d() >= survival {
+            break;
+        }
+        power *= factor / survival;
+        ray = hit.spawn_ray(sample.wi);
+    }
+    rays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_radius_shrinks() {
+        assert_eq!(radius(0.5, 1), 0.5);
+
+        //every frame keeps (i + alpha) / (i + 1) of the squared radius of the last one
+        let second = radius(0.5, 2);
+        assert!((second * second - 0.25 * (1.0 + ALPHA) / 2.0).abs() < 1e-6);
+
+        //slower than the photons add up, the radius goes to 0 but never reaches it
+        let late = radius(0.5, 1000);
+        assert!(late < second && late > 0.0);
+    }
+
+    #[test]
+    fn test_gather_finds_photons_in_radius() {
+        let photon = |x: f32, y: f32, z: f32| Photon {
+            position: Vec3::new(x, y, z),
+            direction: Vec3::new(0.0, 1.0, 0.0),
+            power: Vec3::new(1.0, 1.0, 1.0),
+        };
+        let mut photons = vec![];
+        for i in 0..10 {
+            for j in 0..10 {
+                photons.push(photon(i as f32, 0.0, j as f32 * 0.5));
+            }
+        }
+        let map = PhotonMap::new(photons.clone(), 0.6);
+
+        let center = Vec3::new(3.0, 0.0, 2.0);
+        let mut found = 0;
+        map.gather(center, |_| found += 1);
+        let expected = photons
+            .iter()
+            .filter(|p| (p.position - center).len_squared() < 0.36)
+            .count();
+        assert_eq!(found, expected);
+        assert_eq!(found, 3);
+    }
+}
@@ -1,9 +1,12 @@
+use crate::gfx::spectrum::Wavelengths;
 use crate::math::vec3::Vec3;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// in spectral mode, the colors along the ray are the values of their spectra at these
+    pub wavelengths: Option<Wavelengths>,
 }
 
 impl Ray {
@@ -11,10 +14,49 @@ impl Ray {
         Ray {
             origin,
             direction: direction.normalised(),
+            wavelengths: None,
         }
     }
 
+    /// the same ray, carrying `wavelengths`
+    pub fn with_wavelengths(mut self, wavelengths: Option<Wavelengths>) -> Self {
+        self.wavelengths = wavelengths;
+        self
+    }
+
     pub fn point_at(&self, t: f32) -> Vec3 {
         self.origin + (t * self.direction)
     }
+
+    /// the reflectance `rgb` as this ray carries colors: unchanged, or as the values of its spectrum
+    pub fn spectrum(&self, rgb: Vec3) -> Vec3 {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.spectrum(rgb),
+            None => rgb,
+        }
+    }
+
+    /// the light `rgb` as this ray carries colors: unchanged, or as the values of its spectrum
+    pub fn illuminant(&self, rgb: Vec3) -> Vec3 {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.illuminant(rgb),
+            None => rgb,
+        }
+    }
+
+    /// `color` of a path that split up by wavelength, see `Wavelengths::terminate_secondary`
+    pub fn terminate_secondary(&self, color: Vec3) -> Vec3 {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.terminate_secondary(color),
+            None => color,
+        }
+    }
+
+    /// converts a color carried by this ray back to rgb
+    pub fn to_rgb(self, color: Vec3) -> Vec3 {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.to_rgb(color),
+            None => color,
+        }
+    }
 }
@@ -1,64 +1,83 @@
 use crate::camera::{Camera, CropFactor, Focus};
+use crate::checkpoint::{Checkpoint, CheckpointError};
+use crate::film::{self, AdaptiveSampling, Film, PixelStats, Splat};
 use crate::gfx::material::*;
 use crate::gfx::texture::{ConstantTexture, ImageTexture};
 
 use crate::hittables::primitives::*;
 
+use crate::integrators::integrator::IntegratorKind;
+use crate::math::pdf::MisHeuristic;
 use crate::math::vec3::Vec3;
-use crate::pathtracer::PathTracer;
-use rayon::prelude::*;
+use crate::output::{self, Aov, Layer};
+use crate::pathtracer::{BounceLimits, PathTracer};
+use crate::sampling::sampler::SamplerKind;
+use crate::stop::{self, StopConditions};
+use crate::tiles::{self, Progress, RenderedTile, Tile, TileOrder};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::video::Window;
-use sdl2::{EventPump, Sdl};
-use std::sync::Arc;
-
+use sdl2::EventPump;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
+#[derive(Copy, Clone, PartialEq)]
 enum DisplayMode {
     Denoised,
     Color,
     Albedo,
     Normal,
     Depth,
+    /// heatmap of the number of samples per pixel
+    Samples,
 }
 
 const GAMMA: f32 = 1.0 / 2.2;
 
+/// camera distance written for pixels that did not hit anything
+const BACKGROUND_DEPTH: f32 = 1.0e10;
+
+/// how often the window is updated while the tiles of a frame come in
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(33);
+
+/// how long the window waits for events once the render was stopped
+const IDLE_INTERVAL: Duration = Duration::from_millis(16);
+
 pub struct Renderer {
     width: u32,
     height: u32,
 
-    context: Sdl,
-    window: Window,
-
     path_tracer: PathTracer,
     display_mode: DisplayMode,
+    /// false if the color buffer is shown and written as it is
+    denoise_enabled: bool,
     running: bool,
 
     color_buffer: Vec<f32>,
     albedo_buffer: Vec<f32>,
     normal_buffer: Vec<f32>,
     depth_buffer: Vec<f32>,
+    /// how many samples every pixel got, and how noisy it is
+    film: Film,
 
     frame: u32,
+
+    /// the tiles of a frame, in the order they are rendered
+    tiles: Vec<Tile>,
+    /// when to end a progressive render
+    stop: StopConditions,
+
+    /// identifies the scene for checkpoints, 0 for the built-in scene
+    scene_hash: u64,
+    /// where to periodically save the accumulated frames in incremental mode
+    checkpoint_path: Option<PathBuf>,
+    checkpoint_interval: Duration,
 }
 
 impl Renderer {
     pub fn new(width: u32, height: u32, samples: u32, incremental: bool) -> Self {
-        //initialise SDL2
-        let context = sdl2::init().unwrap();
-        let video_subsystem = context.video().unwrap();
-
-        //hide cursor, lock mouse to window
-        //sdl2_context.mouse().set_relative_mouse_mode(true);
-
-        //create a window
-        let window = video_subsystem
-            .window("Raytracer", width, height)
-            .position_centered()
-            .build()
-            .unwrap();
-
         //setup the camera here
         let pos = Vec3::new(-7.0, 12.0, -7.0);
         let target = Vec3::new(0.0, 5.0, 0.0);
@@ -85,20 +104,182 @@ impl Renderer {
         //create the renderer
         let path_tracer = PathTracer::new(width, height, samples, incremental, camera, skybox);
 
+        Self::from_path_tracer(width, height, path_tracer)
+    }
+
+    /// creates a renderer for an already set up path tracer, e.g. one loaded from a scene file
+    /// `width` and `height` must match the ones the path tracer was created with
+    pub fn from_path_tracer(width: u32, height: u32, path_tracer: PathTracer) -> Self {
         let buffer_size = (width * height * 3) as usize;
         Renderer {
             width,
             height,
-            context,
-            window,
             path_tracer,
             display_mode: DisplayMode::Denoised,
+            denoise_enabled: true,
             running: false,
             color_buffer: vec![0f32; buffer_size],
             albedo_buffer: vec![0f32; buffer_size],
             normal_buffer: vec![0f32; buffer_size],
             depth_buffer: vec![0f32; buffer_size],
+            film: Film::new(width, height),
             frame: 1,
+            tiles: tiles::make_tiles(width, height, 32, TileOrder::Spiral),
+            stop: StopConditions::default(),
+            scene_hash: 0,
+            checkpoint_path: None,
+            checkpoint_interval: Duration::from_secs(60),
+        }
+    }
+
+    pub fn path_tracer(&self) -> &PathTracer {
+        &self.path_tracer
+    }
+
+    /// renders the frames in tiles of `size` x `size` pixels, handed out in the given order
+    pub fn with_tiles(mut self, size: u32, order: TileOrder) -> Self {
+        self.tiles = tiles::make_tiles(self.width, self.height, size, order);
+        self
+    }
+
+    /// only keeps sampling the pixels whose estimated error is above the threshold
+    pub fn with_adaptive_sampling(mut self, adaptive: AdaptiveSampling) -> Self {
+        self.path_tracer.set_adaptive_sampling(Some(adaptive));
+        self
+    }
+
+    /// renders the same image for the same seed, scene and settings
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.path_tracer.set_seed(seed);
+        self
+    }
+
+    /// changes how the random numbers of the samples are distributed
+    pub fn with_sampler(mut self, sampler: SamplerKind) -> Self {
+        self.path_tracer.set_sampler(sampler);
+        self
+    }
+
+    /// without denoising, the noisy color buffer is shown and written instead
+    pub fn with_denoising(mut self, enabled: bool) -> Self {
+        self.denoise_enabled = enabled;
+        if !enabled {
+            self.display_mode = DisplayMode::Color;
+        }
+        self
+    }
+
+    /// paths end once they reach any of the limits
+    pub fn with_bounce_limits(mut self, bounce_limits: BounceLimits) -> Self {
+        self.path_tracer.set_bounce_limits(bounce_limits);
+        self
+    }
+
+    /// paths with little throughput may randomly end after `roulette_depth` bounces
+    pub fn with_roulette_depth(mut self, roulette_depth: u32) -> Self {
+        self.path_tracer.set_roulette_depth(roulette_depth);
+        self
+    }
+
+    /// changes how light sampling and material sampling are combined
+    pub fn with_mis_heuristic(mut self, mis: MisHeuristic) -> Self {
+        self.path_tracer.set_mis_heuristic(mis);
+        self
+    }
+
+    /// changes the algorithm that computes what the camera rays see
+    pub fn with_integrator(mut self, integrator: IntegratorKind) -> Self {
+        self.path_tracer.set_integrator(integrator);
+        self
+    }
+
+    /// with photon mapping, emits `count` photons every frame and gathers them within `radius`
+    /// in the first frame
+    pub fn with_photons(mut self, count: u32, radius: f32) -> Self {
+        self.path_tracer.set_photons(count, radius);
+        self
+    }
+
+    /// traces wavelengths instead of rgb colors
+    pub fn with_spectral(mut self, spectral: bool) -> Self {
+        self.path_tracer.set_spectral(spectral);
+        self
+    }
+
+    /// guides the paths of the path integrator towards where light arrived from in earlier frames
+    pub fn with_guiding(mut self, guiding: bool) -> Self {
+        self.path_tracer.set_guiding(guiding);
+        self
+    }
+
+    /// ends the render once any of the conditions is met
+    /// the path tracer should be incremental, else the frames do not add up
+    pub fn with_stop_conditions(mut self, stop: StopConditions) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    /// sets the hash identifying the scene, checkpoints of other scenes are refused
+    pub fn with_scene_hash(mut self, scene_hash: u64) -> Self {
+        self.scene_hash = scene_hash;
+        self
+    }
+
+    /// saves a checkpoint to `path` every `interval` and when the window is closed
+    /// only has an effect in incremental mode
+    pub fn with_checkpoints(mut self, path: PathBuf, interval: Duration) -> Self {
+        self.checkpoint_path = Some(path);
+        self.checkpoint_interval = interval;
+        self
+    }
+
+    /// continues accumulating from a checkpoint saved by an earlier run
+    pub fn resume<P: AsRef<Path>>(&mut self, path: P) -> Result<(), CheckpointError> {
+        if !self.path_tracer.is_incremental() {
+            return Err(CheckpointError::NotIncremental);
+        }
+
+        let checkpoint = Checkpoint::load(path)?;
+        checkpoint.check_compatible(
+            self.scene_hash,
+            &self.path_tracer.camera,
+            &self.path_tracer.render_settings(),
+        )?;
+
+        self.frame = checkpoint.frame;
+        self.color_buffer = checkpoint.color_buffer;
+        self.albedo_buffer = checkpoint.albedo_buffer;
+        self.normal_buffer = checkpoint.normal_buffer;
+        self.depth_buffer = checkpoint.depth_buffer;
+        self.film = checkpoint.film;
+        Ok(())
+    }
+
+    /// saves the accumulated frames, if checkpoints are enabled and there is anything to save
+    fn save_checkpoint(&self) {
+        let path = match &self.checkpoint_path {
+            Some(path) => path,
+            None => return,
+        };
+        if !self.path_tracer.is_incremental() || self.frame < 2 {
+            return;
+        }
+
+        let checkpoint = Checkpoint {
+            frame: self.frame,
+            scene_hash: self.scene_hash,
+            camera: self.path_tracer.camera,
+            settings: self.path_tracer.render_settings(),
+            color_buffer: self.color_buffer.clone(),
+            albedo_buffer: self.albedo_buffer.clone(),
+            normal_buffer: self.normal_buffer.clone(),
+            depth_buffer: self.depth_buffer.clone(),
+            film: self.film.clone(),
+        };
+
+        //a failed checkpoint should not end the render
+        if let Err(e) = checkpoint.save(path) {
+            println!("Error: failed to save checkpoint {}: {}", path.display(), e);
         }
     }
 
@@ -156,7 +337,7 @@ impl Renderer {
                 Event::KeyDown {
                     keycode: Some(Keycode::F1),
                     ..
-                } => self.display_mode = DisplayMode::Denoised,
+                } if self.denoise_enabled => self.display_mode = DisplayMode::Denoised,
                 Event::KeyDown {
                     keycode: Some(Keycode::F2),
                     ..
@@ -173,6 +354,19 @@ impl Renderer {
                     keycode: Some(Keycode::F5),
                     ..
                 } => self.display_mode = DisplayMode::Depth,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => self.display_mode = DisplayMode::Samples,
+                Event::KeyDown {
+                    keycode: Some(Keycode::I),
+                    ..
+                } => {
+                    let integrator = self.path_tracer.integrator_kind().next();
+                    self.path_tracer.set_integrator(integrator);
+                    println!("\nIntegrator: {:?}", integrator);
+                    self.frame = 1;
+                }
                 Event::KeyDown {
                     keycode: Some(Keycode::KpPlus),
                     ..
@@ -290,7 +484,7 @@ impl Renderer {
         let texture = Arc::new(ConstantTexture::new(Vec3::new(1.0, 1.0, 1.0)));
         let material = Arc::new(Lambertian::new(texture, None));
 
-        let dragon = Arc::new(Mesh::new("res/models/dragon_tiny.obj"));
+        let dragon = Arc::new(Mesh::load("res/models/dragon_tiny.obj", material).unwrap());
         let position = Vec3::new(0.0, 0.0, 0.5);
         let rotation = Quaternion::from_euler(-90.0, -0.0, 45.0);
         let scale = 1.0;
@@ -300,7 +494,7 @@ impl Renderer {
         self.path_tracer.add_object(transformed_dragon);
         */
 
-        //let dragon = Arc::new(Mesh::new("res/models/dragon_tiny.obj"));
+        //let dragon = Arc::new(Mesh::load("res/models/dragon_tiny.obj", material).unwrap());
         /*let boundary = Arc::new(Sphere {
             center: Vec3::new(0.0, 0.0, 0.0),
             radius: 0.5,
@@ -344,72 +538,469 @@ impl Renderer {
             .collect()
     }
 
+    /// creates the denoiser and sets it up for our image size
+    fn create_denoise_filter(
+        device: &oidn::Device,
+        width: u32,
+        height: u32,
+    ) -> oidn::filter::RayTracing<'_> {
+        let mut denoise_filter = oidn::filter::RayTracing::new(device);
+        denoise_filter
+            .set_srgb(false)
+            .set_img_dims(width as usize, height as usize);
+        denoise_filter
+    }
+
+    /// the color buffer with the light of the splats added
+    fn color_image(&self) -> Vec<f32> {
+        self.film.with_splats(&self.color_buffer)
+    }
+
+    /// denoises the color image using albedo and normal buffers
+    fn denoise(&self, denoise_filter: &mut oidn::filter::RayTracing<'_>) -> Vec<f32> {
+        let mut denoise_buffer = vec![0f32; self.color_buffer.len()];
+        denoise_filter
+            .execute_with_albedo_normal(
+                &self.color_image()[..],
+                &self.albedo_buffer[..],
+                &self.normal_buffer[..],
+                &mut denoise_buffer[..],
+            )
+            .expect("failed to denoise image");
+        denoise_buffer
+    }
+
+    /// renders a single frame into the color, albedo, normal and depth buffers
+    /// the tiles are rendered in parallel, `tile_done` is called on this thread for every
+    /// finished tile, after it was accumulated into the buffers
+    /// returns the number of traced rays
+    fn render_frame<F: FnMut(&RenderedTile)>(&mut self, mut tile_done: F) -> u64 {
+        //e.g. the photons, the render threads only get to read the tracer
+        let prepare_rays = self.path_tracer.prepare_frame(self.frame);
+        let Renderer {
+            width,
+            path_tracer,
+            tiles,
+            color_buffer,
+            albedo_buffer,
+            normal_buffer,
+            depth_buffer,
+            film,
+            frame,
+            ..
+        } = self;
+        let (width, frame) = (*width, *frame);
+        let tracer = &*path_tracer;
+        let tiles = &tiles[..];
+
+        //only incremental frames add to the previous ones
+        if frame == 1 || !tracer.is_incremental() {
+            film.clear();
+        }
+        //the render threads can not read the film while we write it, so they get a copy
+        let tile_stats: Vec<Vec<PixelStats>> = tiles.iter().map(|t| film.tile_stats(t)).collect();
+        let tile_stats = &tile_stats[..];
+
+        //the splats of every tile, they may land on any pixel and are only added once all tiles
+        //are done, in the order of the tiles: sums of floats depend on the order of the terms
+        let mut tile_splats: Vec<Vec<Splat>> = tiles.iter().map(|_| Vec::new()).collect();
+
+        let next_tile = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+        let mut progress = Progress::new(frame, tiles);
+        let mut rays = prepare_rays;
+
+        std::thread::scope(|scope| {
+            let next_tile = &next_tile;
+
+            //every render thread takes the next tile as soon as it is done with its last one,
+            //so the tiles are started in order (a parallel iterator would split the list instead)
+            scope.spawn(move || {
+                rayon::scope(|rayon_scope| {
+                    for _ in 0..rayon::current_num_threads() {
+                        let sender = sender.clone();
+                        rayon_scope.spawn(move |_| {
+                            loop {
+                                let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                                let tile = match tiles.get(index) {
+                                    Some(tile) => *tile,
+                                    None => break,
+                                };
+                                let stats = tile_stats[index].clone();
+                                let rendered = tracer.render_tile(tile, stats);
+                                //only fails if the receiving thread panicked
+                                if sender.send((index, rendered)).is_err() {
+                                    break;
+                                }
+                            }
+                        });
+                    }
+                });
+            });
+
+            //ends once all render threads are done and dropped their sender
+            for (index, mut rendered) in receiver {
+                let tile = rendered.tile;
+
+                //weight every pixel by how many samples it had before and got now
+                let weights: Vec<(f32, f32)> = film
+                    .tile_stats(&tile)
+                    .iter()
+                    .zip(rendered.stats.iter())
+                    .map(|(old, new)| (old.samples as f32, (new.samples - old.samples) as f32))
+                    .collect();
+                film.store_tile(&tile, &rendered.stats);
+                tile_splats[index] = std::mem::take(&mut rendered.splats);
+
+                Self::accumulate(color_buffer, width, &tile, &weights, &mut rendered.color);
+                Self::accumulate(albedo_buffer, width, &tile, &weights, &mut rendered.albedo);
+                Self::accumulate(normal_buffer, width, &tile, &weights, &mut rendered.normal);
+                Self::accumulate(depth_buffer, width, &tile, &weights, &mut rendered.depth);
+
+                progress.tile_done(&tile, rendered.rays);
+                rays += rendered.rays;
+                tile_done(&rendered);
+            }
+        });
+        for splat in tile_splats.iter().flatten() {
+            film.add_splat(splat);
+        }
+
+        //incremental frames are quick, keep overwriting the same line
+        if tracer.is_incremental() {
+            progress.report();
+        } else {
+            progress.finish();
+        }
+        rays
+    }
+
+    /// writes a rendered tile into the image `buffer`
+    /// `weights` holds the number of (previous, new) samples of every pixel of the tile,
+    /// pixels are averaged accordingly and the averages are written back to `tile_buffer` too
+    fn accumulate(
+        buffer: &mut [f32],
+        width: u32,
+        tile: &Tile,
+        weights: &[(f32, f32)],
+        tile_buffer: &mut [f32],
+    ) {
+        let row_length = tile.width as usize * 3;
+        let rows = tile_buffer
+            .chunks_mut(row_length)
+            .zip(weights.chunks(tile.width as usize));
+
+        for (row, (tile_row, row_weights)) in rows.enumerate() {
+            let start = ((tile.y as usize + row) * width as usize + tile.x as usize) * 3;
+            let image_row = &mut buffer[start..start + row_length];
+
+            let pixels = image_row.chunks_mut(3).zip(tile_row.chunks_mut(3));
+            for ((old, new), (old_weight, new_weight)) in pixels.zip(row_weights) {
+                let total = old_weight + new_weight;
+                //converged pixels did not get any new samples
+                if total > 0.0 {
+                    for (o, n) in old.iter_mut().zip(new.iter_mut()) {
+                        *n = (*o * old_weight + *n * new_weight) / total;
+                        *o = *n;
+                    }
+                } else {
+                    new.copy_from_slice(old);
+                }
+            }
+        }
+    }
+
+    /// the sample count shown as the hottest color of the sample heatmap
+    fn heatmap_max(&self) -> u32 {
+        match self.path_tracer.adaptive_sampling() {
+            Some(adaptive) => adaptive.max_samples,
+            None => self.film.max_samples(),
+        }
+    }
+
+    /// copies the BGRA pixels of a tile into the pixels of the window surface
+    fn draw_tile(pixels: &mut [u8], pitch: usize, tile: &Tile, bgra: &[u8]) {
+        let row_length = tile.width as usize * 4;
+        for (row, tile_row) in bgra.chunks(row_length).enumerate() {
+            let start = (tile.y as usize + row) * pitch + tile.x as usize * 4;
+            pixels[start..start + row_length].copy_from_slice(tile_row);
+        }
+    }
+
+    /// converts the inverse depth of the depth buffer to a single channel of camera distances
+    /// pixels without a hit get `BACKGROUND_DEPTH`
+    fn linear_depth(&self) -> Vec<f32> {
+        self.depth_buffer
+            .chunks(3)
+            .map(|d| {
+                if d[0] > 0.0 {
+                    1.0 / d[0]
+                } else {
+                    BACKGROUND_DEPTH
+                }
+            })
+            .collect()
+    }
+
+    /// renders the scene once, without opening a window, and writes the denoised image to `path`
+    /// the selected `aovs` are written as well, as layers of the same file for exr,
+    /// else as separate files next to `path`
+    pub fn render_to_file<P: AsRef<Path>>(&mut self, path: P, aovs: &[Aov]) -> io::Result<()> {
+        let path = path.as_ref();
+
+        let denoise_device = oidn::Device::new();
+        let mut denoise_filter =
+            Self::create_denoise_filter(&denoise_device, self.width, self.height);
+
+        if self.stop.is_empty() {
+            self.render_frame(|_| {});
+        } else {
+            //render frame after frame until one of the conditions is met
+            let start = Instant::now();
+            let mut last_checkpoint = Instant::now();
+            loop {
+                let samples_before = self.film.total_samples();
+                self.render_frame(|_| {});
+                self.frame += 1;
+
+                let new_samples = self.film.total_samples() - samples_before;
+                if let Some(reason) = self.stop.check(start.elapsed(), &self.film, new_samples) {
+                    stop::report(reason, start.elapsed(), self.frame - 1, &self.film);
+                    break;
+                }
+                if last_checkpoint.elapsed() >= self.checkpoint_interval {
+                    self.save_checkpoint();
+                    last_checkpoint = Instant::now();
+                }
+            }
+            self.save_checkpoint();
+        }
+
+        let color = self.color_image();
+        let image = if self.denoise_enabled {
+            self.denoise(&mut denoise_filter)
+        } else {
+            color.clone()
+        };
+
+        let depth = self.linear_depth();
+        let aov_data = |aov: Aov| match aov {
+            Aov::Color => &color[..],
+            Aov::Albedo => &self.albedo_buffer[..],
+            Aov::Normal => &self.normal_buffer[..],
+            Aov::Depth => &depth[..],
+        };
+
+        if output::extension_is(path, "exr") {
+            let mut layers = vec![Layer::rgb("", &image)];
+            for aov in aovs {
+                layers.push(match aov {
+                    Aov::Depth => Layer::depth(aov.name(), aov_data(*aov)),
+                    _ => Layer::rgb(aov.name(), aov_data(*aov)),
+                });
+            }
+            return output::write_exr(path, self.width, self.height, &layers);
+        }
+
+        output::write_image(path, self.width, self.height, &image)?;
+        for aov in aovs {
+            let aov_path = Self::aov_path(path, aov.name());
+            match aov {
+                Aov::Depth if output::extension_is(&aov_path, "pfm") => {
+                    output::write_pfm(aov_path, self.width, self.height, 1, &depth)?
+                }
+                Aov::Depth => {
+                    let depth_rgb: Vec<f32> = depth.iter().flat_map(|d| vec![*d; 3]).collect();
+                    output::write_image(aov_path, self.width, self.height, &depth_rgb)?
+                }
+                _ => output::write_image(aov_path, self.width, self.height, aov_data(*aov))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// renders `frames` frames without opening a window and prints how long they took
+    pub fn bench(&mut self, frames: u32) {
+        let mut times = Vec::with_capacity(frames as usize);
+        let mut rays = 0;
+
+        for _ in 0..frames {
+            let start = Instant::now();
+            rays += self.render_frame(|_| {});
+            times.push(start.elapsed().as_secs_f64());
+        }
+
+        let total: f64 = times.iter().sum();
+        let fastest = times.iter().cloned().fold(f64::INFINITY, f64::min);
+        let slowest = times.iter().cloned().fold(0.0, f64::max);
+        let pixels = self.film.pixel_count().max(1) as f64;
+
+        println!();
+        println!(
+            "{} frames with {} threads: {:.3}s per frame on average (fastest {:.3}s, slowest {:.3}s)",
+            frames,
+            rayon::current_num_threads(),
+            total / frames.max(1) as f64,
+            fastest,
+            slowest,
+        );
+        println!(
+            "{:.2} Mrays/s, {:.1} rays per pixel and frame",
+            rays as f64 / total.max(f64::EPSILON) / 1.0e6,
+            rays as f64 / pixels / frames.max(1) as f64,
+        );
+    }
+
+    /// `render.png` => `render.albedo.png`
+    fn aov_path(path: &Path, aov: &str) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(ext) => format!("{}.{}.{}", stem, aov, ext.to_string_lossy()),
+            None => format!("{}.{}", stem, aov),
+        };
+        path.with_file_name(name)
+    }
+
     /// MAIN LOOP
     pub fn run(&mut self) {
         self.running = true;
 
+        //initialise SDL2
+        let context = sdl2::init().unwrap();
+        let video_subsystem = context.video().unwrap();
+
+        //hide cursor, lock mouse to window
+        //sdl2_context.mouse().set_relative_mouse_mode(true);
+
+        //create a window
+        let window = video_subsystem
+            .window("Raytracer", self.width, self.height)
+            .position_centered()
+            .build()
+            .unwrap();
+
         //we can't really save any denoiser stuff (really annoying...)
         //create the denoiser stuff
         let denoise_device = oidn::Device::new();
-        let mut denoise_filter = oidn::filter::RayTracing::new(&denoise_device);
-        denoise_filter
-            .set_srgb(false)
-            .set_img_dims(self.width as usize, self.height as usize);
+        let mut denoise_filter =
+            Self::create_denoise_filter(&denoise_device, self.width, self.height);
+
+        let mut last_checkpoint = Instant::now();
+        //false until the first complete frame is on screen
+        let mut has_image = false;
+
+        let mut event_pump = context.event_pump().unwrap();
+        let mut denoise_buffer = Vec::new();
+        //the stop conditions are checked against the time since the last camera change
+        let mut render_start = Instant::now();
+        let mut stopped = false;
+        let mut shown_mode = self.display_mode;
 
-        let mut event_pump = self.context.event_pump().unwrap();
         while self.running {
             self.handle_sdl_events(&mut event_pump);
 
+            //moving the camera starts a new render
+            if self.frame == 1 {
+                render_start = Instant::now();
+                stopped = false;
+            }
+            //once stopped, only redraw if another buffer should be shown
+            if stopped && self.display_mode == shown_mode {
+                std::thread::sleep(IDLE_INTERVAL);
+                continue;
+            }
+
             #[cfg(measure_perf)]
             let render_time = Instant::now();
 
-            let _len = self.color_buffer.len();
-            let cb = &mut self.color_buffer;
-            let ab = &mut self.albedo_buffer;
-            let nb = &mut self.normal_buffer;
-            let db = &mut self.depth_buffer;
-            let tracer = &self.path_tracer;
-
-            let frame = self.frame;
-
-            cb.par_chunks_mut(3)
-                .enumerate()
-                .zip(ab.par_chunks_mut(3))
-                .zip(nb.par_chunks_mut(3))
-                .zip(db.par_chunks_mut(3))
-                .for_each_init(
-                    || rand::thread_rng(),
-                    |rng, ((((index, c), a), n), d)| {
-                        tracer.render_pixel(rng, index, frame, c, a, n, d)
-                    },
-                );
+            if !stopped {
+                //show the tiles as they come in, unless that would replace a denoised image
+                //with a noisy one every frame
+                let display_mode = self.display_mode;
+                let preview =
+                    !has_image || self.frame == 1 || display_mode != DisplayMode::Denoised;
+                let mut last_preview = Instant::now();
+                let heatmap_max = self.heatmap_max();
+                let samples_before = self.film.total_samples();
 
-            #[cfg(measure_perf)]
-            println!("Render took {:?}", render_time.elapsed());
+                self.render_frame(|rendered| {
+                    if !preview {
+                        return;
+                    }
+                    //keep the window responsive during long frames
+                    event_pump.pump_events();
 
-            #[cfg(measure_perf)]
-            let denoise_time = Instant::now();
-
-            //denoise image
-            let mut denoise_buffer = vec![0f32; self.color_buffer.len()];
-            denoise_filter
-                .execute_with_albedo_normal(
-                    &self.color_buffer[..],
-                    &self.albedo_buffer[..],
-                    &self.normal_buffer[..],
-                    &mut denoise_buffer[..],
-                )
-                .expect("failed to denoise image");
+                    let heatmap;
+                    let tile_buffer = match display_mode {
+                        DisplayMode::Denoised | DisplayMode::Color => &rendered.color,
+                        DisplayMode::Albedo => &rendered.albedo,
+                        DisplayMode::Normal => &rendered.normal,
+                        DisplayMode::Depth => &rendered.depth,
+                        DisplayMode::Samples => {
+                            heatmap = film::heatmap(&rendered.stats, heatmap_max);
+                            &heatmap
+                        }
+                    };
+                    let display_buffer = Self::post_process(tile_buffer);
 
-            #[cfg(measure_perf)]
-            println!("Denoising took {:?}", denoise_time.elapsed());
+                    let mut surface = window.surface(&event_pump).unwrap();
+                    let pitch = surface.pitch() as usize;
+                    if let Some(pixel_buffer) = surface.without_lock_mut() {
+                        Self::draw_tile(pixel_buffer, pitch, &rendered.tile, &display_buffer);
+                    }
+                    if last_preview.elapsed() >= PREVIEW_INTERVAL {
+                        surface.update_window().expect("failed to update windows!");
+                        last_preview = Instant::now();
+                    }
+                });
+
+                #[cfg(measure_perf)]
+                println!("Render took {:?}", render_time.elapsed());
+
+                #[cfg(measure_perf)]
+                let denoise_time = Instant::now();
+
+                //denoise image
+                if self.denoise_enabled {
+                    denoise_buffer = self.denoise(&mut denoise_filter);
+                }
+
+                #[cfg(measure_perf)]
+                println!("Denoising took {:?}", denoise_time.elapsed());
+
+                //update frame value
+                self.frame += 1;
+
+                if last_checkpoint.elapsed() >= self.checkpoint_interval {
+                    self.save_checkpoint();
+                    last_checkpoint = Instant::now();
+                }
+
+                if !self.stop.is_empty() {
+                    let new_samples = self.film.total_samples() - samples_before;
+                    let elapsed = render_start.elapsed();
+                    if let Some(reason) = self.stop.check(elapsed, &self.film, new_samples) {
+                        stop::report(reason, elapsed, self.frame - 1, &self.film);
+                        stopped = true;
+                    }
+                }
+            }
 
+            let heatmap;
+            let color;
             let pp_buffer = match &self.display_mode {
                 DisplayMode::Denoised => &denoise_buffer,
-                DisplayMode::Color => &self.color_buffer,
+                DisplayMode::Color => {
+                    color = self.color_image();
+                    &color
+                }
                 DisplayMode::Albedo => &self.albedo_buffer,
                 DisplayMode::Normal => &self.normal_buffer,
                 DisplayMode::Depth => &self.depth_buffer,
+                DisplayMode::Samples => {
+                    heatmap = self.film.sample_heatmap(self.heatmap_max());
+                    &heatmap
+                }
             };
 
             #[cfg(measure_perf)]
@@ -421,7 +1012,7 @@ impl Renderer {
             println!("Post processing took {:?}", convert_time.elapsed());
 
             //write pixels
-            let mut surface = self.window.surface(&event_pump).unwrap();
+            let mut surface = window.surface(&event_pump).unwrap();
             if let Some(pixel_buffer) = surface.without_lock_mut() {
                 pixel_buffer.copy_from_slice(&display_buffer[..]);
             }
@@ -434,9 +1025,109 @@ impl Renderer {
 
             //"swap" images
             surface.update_window().expect("failed to update windows!");
+            has_image = true;
+            shown_mode = self.display_mode;
+        }
+
+        //don't throw away what we have rendered so far
+        self.save_checkpoint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::texture::Texture;
+
+    /// renders two incremental frames of a small scene lit by a sphere light, with the tiles
+    /// spread over several threads; returns the color, albedo, normal and depth buffers
+    fn render(integrator: IntegratorKind, guiding: bool) -> Vec<Vec<f32>> {
+        //the tests share the global thread pool, the first one to get here sets it up
+        let _ = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build_global();
 
-            //update frame value
-            self.frame += 1;
+        let (width, height) = (24, 16);
+        let camera = Camera::new_virtual(
+            Vec3::new(0.0, 1.0, -4.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            60.0,
+            width,
+            height,
+        );
+        let constant = |value: f32| -> Arc<dyn Texture> {
+            Arc::new(ConstantTexture::new(Vec3::new(value, value, value)))
+        };
+        let mut path_tracer = PathTracer::new(width, height, 4, true, camera, constant(0.1));
+        path_tracer.add_object(Arc::new(Sphere {
+            center: Vec3::new(0.0, -100.0, 0.0),
+            radius: 100.0,
+            material: Arc::new(Lambertian::new(constant(0.5), None)),
+        }));
+        path_tracer.add_object(Arc::new(Sphere {
+            center: Vec3::new(0.0, 1.0, 0.0),
+            radius: 1.0,
+            material: Arc::new(Dielectric::new(constant(1.0), None, None, 1.5, 0.0)),
+        }));
+        path_tracer.add_object(Arc::new(Sphere {
+            center: Vec3::new(1.5, 3.0, 0.0),
+            radius: 0.5,
+            material: Arc::new(Emissive::new(constant(10.0))),
+        }));
+        path_tracer.set_seed(7);
+        path_tracer.set_integrator(integrator);
+        path_tracer.set_guiding(guiding);
+
+        let mut renderer = Renderer::from_path_tracer(width, height, path_tracer.finalise())
+            .with_tiles(8, TileOrder::Spiral);
+        for _ in 0..2 {
+            renderer.render_frame(|_| {});
+            renderer.frame += 1;
+        }
+        vec![
+            renderer.color_image(),
+            renderer.albedo_buffer.clone(),
+            renderer.normal_buffer.clone(),
+            renderer.depth_buffer.clone(),
+        ]
+    }
+
+    /// the same seed renders the same image, bit for bit, whatever order the tiles finish in
+    fn check_reproducible(integrator: IntegratorKind, guiding: bool) {
+        let first = render(integrator, guiding);
+        let second = render(integrator, guiding);
+        for (a, b) in first.iter().zip(second.iter()) {
+            let bits = |buffer: &[f32]| buffer.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(a), bits(b), "{:?}", integrator);
+        }
+    }
+
+    #[test]
+    fn test_splats_are_reproducible() {
+        check_reproducible(IntegratorKind::Bidirectional, false);
+    }
+
+    #[test]
+    fn test_metropolis_is_reproducible() {
+        //the chains go on over the frames, and splat onto any pixel
+        check_reproducible(IntegratorKind::Metropolis, false);
+    }
+
+    #[test]
+    fn test_guiding_is_reproducible() {
+        //the second frame is guided by what the tiles of the first one recorded
+        check_reproducible(IntegratorKind::Path, true);
+    }
+
+    #[test]
+    fn test_every_integrator_is_reproducible() {
+        let mut integrator = IntegratorKind::default();
+        loop {
+            check_reproducible(integrator, false);
+            integrator = integrator.next();
+            if integrator == IntegratorKind::default() {
+                break;
+            }
         }
     }
 }
@@ -0,0 +1,196 @@
+use crate::math::random;
+use crate::sampling::sampler::{SampleState, Sampler};
+use rand::Rng;
+use rand_pcg::Pcg32;
+use std::sync::OnceLock;
+
+/// edge length of the blue noise texture
+const SIZE: usize = 64;
+/// width of the gaussian filter that measures how clustered the points are
+const SIGMA: f32 = 1.5;
+/// 1 / golden ratio, consecutive samples of a pixel step through [0, 1) by this
+const GOLDEN_RATIO: f32 = 0.618_034;
+
+/// distributes the error of every sample as blue noise over the screen
+///
+/// each dimension reads a (randomly shifted) blue noise texture at the pixel,
+/// consecutive samples of a pixel add the golden ratio to that value.
+/// at low sample counts the remaining noise is high frequency, it looks less
+/// blotchy than white noise and denoises better.
+pub struct BlueNoiseSampler {
+    state: SampleState,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> Self {
+        //build the texture now instead of in the middle of a tile
+        texture();
+        BlueNoiseSampler {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn state_mut(&mut self) -> &mut SampleState {
+        &mut self.state
+    }
+
+    fn value_1d(&self, dimension: u32) -> f32 {
+        //the shift only depends on the dimension, so the noise stays blue across pixels
+        let shift = random::hash(&[self.state.seed, dimension as u64]);
+        let x = (self.state.x as usize + shift as usize % SIZE) % SIZE;
+        let y = (self.state.y as usize + (shift >> 32) as usize % SIZE) % SIZE;
+
+        let value = texture()[y * SIZE + x] + self.state.index as f32 * GOLDEN_RATIO;
+        value.fract()
+    }
+}
+
+/// the blue noise texture, built on first use
+fn texture() -> &'static [f32] {
+    static TEXTURE: OnceLock<Vec<f32>> = OnceLock::new();
+    TEXTURE.get_or_init(|| {
+        let count = (SIZE * SIZE) as f32;
+        void_and_cluster()
+            .into_iter()
+            .map(|rank| (rank as f32 + 0.5) / count)
+            .collect()
+    })
+}
+
+/// the energy of every pixel, how close it is to the points of a binary pattern
+#[derive(Clone)]
+struct Energy {
+    /// gaussian of the toroidal distance to (0, 0)
+    filter: Vec<f32>,
+    values: Vec<f32>,
+}
+
+impl Energy {
+    fn new() -> Self {
+        let mut filter = vec![0.0; SIZE * SIZE];
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let dx = x.min(SIZE - x) as f32;
+                let dy = y.min(SIZE - y) as f32;
+                filter[y * SIZE + x] = (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        Energy {
+            filter,
+            values: vec![0.0; SIZE * SIZE],
+        }
+    }
+
+    /// adds (`sign` = 1) or removes (`sign` = -1) the point at `index`
+    fn splat(&mut self, index: usize, sign: f32) {
+        let (px, py) = (index % SIZE, index / SIZE);
+        for y in 0..SIZE {
+            let dy = (y + SIZE - py) % SIZE;
+            for x in 0..SIZE {
+                let dx = (x + SIZE - px) % SIZE;
+                self.values[y * SIZE + x] += sign * self.filter[dy * SIZE + dx];
+            }
+        }
+    }
+
+    /// the point with the highest energy
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        self.extreme(pattern, true, |a, b| a > b)
+    }
+
+    /// the empty pixel with the lowest energy
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        self.extreme(pattern, false, |a, b| a < b)
+    }
+
+    fn extreme(&self, pattern: &[bool], set: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best = None;
+        for (index, &value) in self.values.iter().enumerate() {
+            if pattern[index] != set {
+                continue;
+            }
+            match best {
+                Some((_, best_value)) if !better(value, best_value) => {}
+                _ => best = Some((index, value)),
+            }
+        }
+        best.map(|(index, _)| index).unwrap_or(0)
+    }
+}
+
+/// ranks every pixel of the texture with the void and cluster algorithm
+/// (Ulichney, "The void-and-cluster method for dither array generation", 1993)
+fn void_and_cluster() -> Vec<u32> {
+    let total = SIZE * SIZE;
+    let mut energy = Energy::new();
+    let mut pattern = vec![false; total];
+
+    //a random initial pattern, the seed is fixed so the texture is always the same
+    let mut rng = Pcg32::new(0x853c_49e6_748f_ea9b, 0xda3e_39cb_94b9_5bdb);
+    let initial = total / 10;
+    let mut points = 0;
+    while points < initial {
+        let index = rng.gen_range(0, total);
+        if !pattern[index] {
+            pattern[index] = true;
+            energy.splat(index, 1.0);
+            points += 1;
+        }
+    }
+
+    //spread the points evenly: move the tightest cluster into the largest void
+    //until that does not change anything anymore
+    for _ in 0..total {
+        let cluster = energy.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        energy.splat(cluster, -1.0);
+
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.splat(void, 1.0);
+
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; total];
+
+    //rank the initial points, the most clustered one gets the highest rank
+    let mut removed = pattern.clone();
+    let mut removed_energy = energy.clone();
+    for rank in (0..initial).rev() {
+        let cluster = removed_energy.tightest_cluster(&removed);
+        removed[cluster] = false;
+        removed_energy.splat(cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+
+    //fill the voids, the largest one gets the next rank
+    for rank in initial..total {
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.splat(void, 1.0);
+        ranks[void] = rank as u32;
+    }
+
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_texture_is_permutation() {
+        //every value appears exactly once, so every dimension is uniformly distributed
+        let mut values: Vec<f32> = texture().to_vec();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (rank, value) in values.iter().enumerate() {
+            let expected = (rank as f32 + 0.5) / (SIZE * SIZE) as f32;
+            assert!((value - expected).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::math::random;
+use crate::sampling::sampler::{to_unit_float, SampleState, Sampler};
+
+/// the bases of the dimensions, the first 64 primes
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// the smallest intervals the first samples of a pixel are spread over, one per sample:
+/// the sequence stays stratified up to 65536 samples per pixel
+const STRATUM: f64 = 1.0 / 65536.0;
+
+/// the halton sequence, the radical inverse of the sample index in the n-th prime base
+///
+/// every pixel and dimension permutes the digits randomly (Owen scrambling), so neighbouring
+/// pixels do not show the same pattern and the sequence keeps its stratification.
+/// dimensions past the table of primes are independent random numbers.
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        HaltonSampler {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn state_mut(&mut self) -> &mut SampleState {
+        &mut self.state
+    }
+
+    fn value_1d(&self, dimension: u32) -> f32 {
+        let index = self.state.index;
+        let hash = self.state.pixel_hash(&[dimension as u64]);
+
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => scrambled_radical_inverse(base, index, hash),
+            None => {
+                let hash = self.state.pixel_hash(&[dimension as u64, index as u64]);
+                to_unit_float(hash as u32)
+            }
+        }
+    }
+}
+
+/// mirrors the digits of `index` in `base` at the decimal point, every digit shifted by a random
+/// amount picked by `hash` and the digits before it, the same way for every index
+/// (Owen, "Randomly Permuted (t,m,s)-Nets and (t,s)-Sequences", 1995; shifts as in pbrt-v4)
+fn scrambled_radical_inverse(base: u32, mut index: u32, hash: u64) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut factor = inverse_base;
+    let mut result = 0.0;
+    //a leading 1 and the scrambled digits so far, they pick the shift of the next one
+    let mut prefix = 1u64;
+
+    //the zeros after the last digit are shifted too, at least as far as the strata have to stay
+    //apart; below that, one random offset places the value uniformly in its interval
+    while index > 0 || factor * base as f64 > STRATUM {
+        let shift = (random::hash(&[hash ^ prefix]) as u32) % base;
+        let mut digit = index % base + shift;
+        if digit >= base {
+            digit -= base;
+        }
+        result += digit as f64 * factor;
+        prefix = prefix * base as u64 + digit as u64;
+        index /= base;
+        factor *= inverse_base;
+    }
+
+    let offset = to_unit_float(random::hash(&[hash ^ prefix]) as u32) as f64;
+    result += offset * factor * base as f64;
+    (result as f32).min(1.0 - f32::EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrambling_keeps_strata() {
+        //the first base^k indices land in different intervals of width 1 / base^k,
+        //however the digits are permuted
+        for (base, count) in [(2, 16), (3, 27), (5, 25)] {
+            for hash in [1, 42, 0xdead_beef] {
+                let mut strata: Vec<u32> = (0..count)
+                    .map(|i| (scrambled_radical_inverse(base, i, hash) * count as f32) as u32)
+                    .collect();
+                strata.sort_unstable();
+                assert_eq!(strata, (0..count).collect::<Vec<u32>>());
+            }
+        }
+
+        //different hashes permute differently
+        let a: Vec<f32> = (0..8).map(|i| scrambled_radical_inverse(2, i, 1)).collect();
+        let b: Vec<f32> = (0..8).map(|i| scrambled_radical_inverse(2, i, 2)).collect();
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::sampling::sampler::{SampleState, Sampler};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::collections::HashMap;
+
+/// the dimensions that do not fit into the block of their vertex are numbered from here on
+const OVERFLOW_DIMENSIONS: u32 = 1 << 20;
+
+/// one dimension of the primary sample, and when it was last changed
+#[derive(Debug, Copy, Clone, Default)]
+struct PrimarySample {
+    value: f32,
+    /// the iteration that last changed the value
+    modified: u64,
+    /// value and iteration from before the current iteration changed them
+    backup: (f32, u64),
+}
+
+/// primary sample space Metropolis sampling
+/// (Kelemen et al., "A Simple and Robust Mutation Strategy for the Metropolis Light Transport Algorithm", 2002)
+///
+/// every iteration hands out the random numbers of the last accepted iteration, mutated:
+/// a large step replaces all of them, a small step moves each one a little.
+/// dimensions are only mutated once they are used, however long ago they were used last.
+/// the first iteration only depends on the seed, so its path can be made again from the seed.
+#[derive(Clone)]
+pub struct MltSampler {
+    state: SampleState,
+    rng: Pcg32,
+    values: HashMap<u32, PrimarySample>,
+    /// standard deviation of a small step
+    sigma: f32,
+    large_step_probability: f32,
+    iteration: u64,
+    large_step: bool,
+    /// the last accepted iteration that was a large step
+    last_large_step: u64,
+    /// the next dimension to use once the block of a vertex is full
+    overflow: u32,
+}
+
+impl MltSampler {
+    pub fn new(seed: u64, sigma: f32, large_step_probability: f32) -> Self {
+        MltSampler {
+            state: SampleState::new(seed),
+            rng: Pcg32::seed_from_u64(seed),
+            values: HashMap::new(),
+            sigma,
+            large_step_probability,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            overflow: OVERFLOW_DIMENSIONS,
+        }
+    }
+
+    /// mutates the numbers of the last accepted iteration, starting with the camera dimensions
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f32>() < self.large_step_probability;
+        self.start_sample(0, 0, 0);
+        self.overflow = OVERFLOW_DIMENSIONS;
+    }
+
+    /// keeps the numbers of the current iteration
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// goes back to the numbers of the last accepted iteration
+    pub fn reject(&mut self) {
+        let iteration = self.iteration;
+        for sample in self.values.values_mut() {
+            if sample.modified == iteration {
+                sample.value = sample.backup.0;
+                sample.modified = sample.backup.1;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// the dimensions of the next `count` values
+    fn next_dimension(&mut self, count: u32) -> u32 {
+        match self.state.take(count) {
+            Some(dimension) => dimension,
+            None => {
+                let dimension = self.overflow;
+                self.overflow += count;
+                dimension
+            }
+        }
+    }
+
+    /// brings `dimension` up to date with the current iteration
+    fn mutate(&mut self, dimension: u32) {
+        let rng = &mut self.rng;
+        let sample = self.values.entry(dimension).or_default();
+
+        //the value missed a large step while it was not used, it was replaced there
+        if sample.modified < self.last_large_step {
+            sample.value = rng.gen();
+            sample.modified = self.last_large_step;
+        }
+
+        sample.backup = (sample.value, sample.modified);
+        if self.large_step {
+            sample.value = rng.gen();
+        } else {
+            //all the small steps it missed at once, the sum of normal distributions is one
+            let steps = (self.iteration - sample.modified) as f32;
+            sample.value += standard_normal(rng) * self.sigma * steps.sqrt();
+            sample.value -= sample.value.floor();
+            //tiny negative values round up to 1 when wrapped
+            if sample.value >= 1.0 {
+                sample.value = 0.0;
+            }
+        }
+        sample.modified = self.iteration;
+    }
+}
+
+impl Sampler for MltSampler {
+    fn state_mut(&mut self) -> &mut SampleState {
+        &mut self.state
+    }
+
+    fn value_1d(&self, dimension: u32) -> f32 {
+        self.values
+            .get(&dimension)
+            .map_or(0.0, |sample| sample.value)
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.next_dimension(1);
+        self.mutate(dimension);
+        self.value_1d(dimension)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let dimension = self.next_dimension(2);
+        self.mutate(dimension);
+        self.mutate(dimension + 1);
+        self.value_2d(dimension)
+    }
+}
+
+/// a normal distributed random number with mean 0 and standard deviation 1 (Box-Muller)
+fn standard_normal(rng: &mut Pcg32) -> f32 {
+    let radius = (-2.0 * (1.0 - rng.gen::<f32>()).ln()).sqrt();
+    let angle = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+    radius * angle.cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reject_restores_values() {
+        let mut sampler = MltSampler::new(7, 0.01, 0.0);
+        let first: Vec<f32> = (0..6).map(|_| sampler.next_1d()).collect();
+
+        //the same seed makes the same numbers
+        let mut again = MltSampler::new(7, 0.01, 0.0);
+        let second: Vec<f32> = (0..6).map(|_| again.next_1d()).collect();
+        assert_eq!(first, second);
+
+        //small steps stay close, rejecting them brings back the old numbers
+        sampler.start_iteration();
+        let mutated: Vec<f32> = (0..6).map(|_| sampler.next_1d()).collect();
+        assert_ne!(first, mutated);
+        for (a, b) in first.iter().zip(mutated.iter()) {
+            let distance = (a - b).abs();
+            assert!(distance.min(1.0 - distance) < 0.1);
+        }
+        sampler.reject();
+
+        //4 camera dimensions, then the ones that did not fit
+        let dimensions = [0, 1, 2, 3, OVERFLOW_DIMENSIONS, OVERFLOW_DIMENSIONS + 1];
+        let restored: Vec<f32> = dimensions.iter().map(|d| sampler.value_1d(*d)).collect();
+        assert_eq!(first, restored);
+    }
+}
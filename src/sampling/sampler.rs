@@ -0,0 +1,197 @@
+use crate::math::random;
+use crate::sampling::blue_noise::BlueNoiseSampler;
+use crate::sampling::halton::HaltonSampler;
+use crate::sampling::sobol::SobolSampler;
+use crate::sampling::stratified::StratifiedSampler;
+use rand::Rng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// dimensions 0 and 1 are the position inside the pixel, 2 and 3 the position on the lens
+pub const CAMERA_DIMENSIONS: u32 = 4;
+/// dimensions every vertex of a path may use, see `Sampler::start_vertex`
+pub const VERTEX_DIMENSIONS: u32 = 8;
+
+/// hands out the random numbers of a path, one dimension after the other
+///
+/// the dimensions of a path are laid out the same way for every sample:
+/// first the camera dimensions, then a fixed block of dimensions for every vertex.
+/// if a vertex asks for more values than its block holds, the rest are independent
+/// random numbers, so they never correlate with the next vertex.
+pub trait Sampler {
+    fn state_mut(&mut self) -> &mut SampleState;
+
+    /// the value of `dimension` of the current sample, in [0, 1)
+    fn value_1d(&self, dimension: u32) -> f32;
+
+    /// the values of `dimension` and `dimension + 1` of the current sample
+    fn value_2d(&self, dimension: u32) -> (f32, f32) {
+        (self.value_1d(dimension), self.value_1d(dimension + 1))
+    }
+
+    /// starts the `index`-th sample of the pixel at `x`, `y`
+    /// the next values are the camera dimensions
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state_mut().start_sample(x, y, index);
+    }
+
+    /// jumps to the block of dimensions of the vertex `depth` (0 = the one the camera ray hits)
+    fn start_vertex(&mut self, depth: u32) {
+        self.state_mut().start_vertex(depth);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        match self.state_mut().take(1) {
+            Some(dimension) => self.value_1d(dimension),
+            None => self.state_mut().rng.gen(),
+        }
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        match self.state_mut().take(2) {
+            Some(dimension) => self.value_2d(dimension),
+            None => {
+                let rng = &mut self.state_mut().rng;
+                (rng.gen(), rng.gen())
+            }
+        }
+    }
+}
+
+/// the current sample of a sampler, and which of its dimensions comes next
+#[derive(Clone)]
+pub struct SampleState {
+    pub seed: u64,
+    pub x: u32,
+    pub y: u32,
+    pub index: u32,
+    dimension: u32,
+    /// the first dimension that does not belong to the current block anymore
+    end: u32,
+    /// for the values that do not fit into their block
+    rng: Pcg32,
+}
+
+impl SampleState {
+    pub fn new(seed: u64) -> Self {
+        SampleState {
+            seed,
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+            end: CAMERA_DIMENSIONS,
+            rng: random::sample_rng(seed, 0, 0, 0),
+        }
+    }
+
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.x = x;
+        self.y = y;
+        self.index = index;
+        self.dimension = 0;
+        self.end = CAMERA_DIMENSIONS;
+        self.rng = random::sample_rng(self.seed, x, y, index);
+    }
+
+    fn start_vertex(&mut self, depth: u32) {
+        self.dimension = CAMERA_DIMENSIONS + depth * VERTEX_DIMENSIONS;
+        self.end = self.dimension + VERTEX_DIMENSIONS;
+    }
+
+    /// reserves `count` dimensions, None if the current block does not have that many left
+    pub fn take(&mut self, count: u32) -> Option<u32> {
+        if self.dimension + count > self.end {
+            return None;
+        }
+        let dimension = self.dimension;
+        self.dimension += count;
+        Some(dimension)
+    }
+
+    /// a hash of the seed, the pixel and the given values, e.g. the dimension
+    pub fn pixel_hash(&self, values: &[u64]) -> u64 {
+        let pixel = ((self.y as u64) << 32) | self.x as u64;
+        values
+            .iter()
+            .fold(random::hash(&[self.seed, pixel]), |hash, value| {
+                random::hash(&[hash, *value])
+            })
+    }
+}
+
+/// converts 32 random bits to a float in [0, 1)
+pub fn to_unit_float(bits: u32) -> f32 {
+    //only 24 bits fit into the mantissa, more could round up to 1.0
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// independent uniform random numbers for every dimension
+pub struct RandomSampler {
+    state: SampleState,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> Self {
+        RandomSampler {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn state_mut(&mut self) -> &mut SampleState {
+        &mut self.state
+    }
+
+    fn value_1d(&self, dimension: u32) -> f32 {
+        let hash = self
+            .state
+            .pixel_hash(&[self.state.index as u64, dimension as u64]);
+        to_unit_float((hash >> 32) as u32)
+    }
+}
+
+/// the available samplers, selected in the scene settings or on the command line
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Halton,
+    #[default]
+    Sobol,
+    BlueNoise,
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(SamplerKind::Random),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "blue_noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!(
+                "unknown sampler '{}', expected random, stratified, halton, sobol or blue_noise",
+                s
+            )),
+        }
+    }
+}
+
+impl SamplerKind {
+    /// `samples` is the number of samples a pixel will get, only the stratified sampler needs it
+    pub fn create(self, seed: u64, samples: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Box::new(RandomSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::sampling::sampler::{to_unit_float, SampleState, Sampler};
+
+/// the first two dimensions of the sobol sequence, owen-scrambled per pixel
+///
+/// every pair of dimensions uses its own shuffled sample order and scramble (padding),
+/// so the pairs are well distributed on their own but do not correlate with each other.
+/// works with any number of samples, but power of two counts are distributed best.
+/// (Burley, "Practical Hash-based Owen Scrambling", 2020)
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        SobolSampler {
+            state: SampleState::new(seed),
+        }
+    }
+
+    /// the scrambled point of the pair of dimensions starting at `dimension`
+    fn point(&self, dimension: u32) -> (u32, u32) {
+        //dimension 2n and 2n + 1 form a pair and share the shuffled index
+        let pair = dimension / 2;
+        let hash = self.state.pixel_hash(&[pair as u64]);
+
+        let index = nested_uniform_scramble(self.state.index, hash as u32);
+        let x = nested_uniform_scramble(sobol_0(index), (hash >> 32) as u32);
+        let y = nested_uniform_scramble(sobol_1(index), (hash >> 16) as u32 ^ 0x9e37_79b9);
+        (x, y)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn state_mut(&mut self) -> &mut SampleState {
+        &mut self.state
+    }
+
+    fn value_1d(&self, dimension: u32) -> f32 {
+        let (x, y) = self.point(dimension);
+        if dimension.is_multiple_of(2) {
+            to_unit_float(x)
+        } else {
+            to_unit_float(y)
+        }
+    }
+
+    fn value_2d(&self, dimension: u32) -> (f32, f32) {
+        if dimension.is_multiple_of(2) {
+            let (x, y) = self.point(dimension);
+            (to_unit_float(x), to_unit_float(y))
+        } else {
+            //not aligned to a pair, the values come from two different pairs
+            (self.value_1d(dimension), self.value_1d(dimension + 1))
+        }
+    }
+}
+
+/// first dimension of the sobol sequence, the van der corput sequence
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+/// second dimension of the sobol sequence
+fn sobol_1(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut v = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// hash that only lets bits influence more significant bits
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+/// owen scrambling, randomizes the point but keeps its stratification
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stratification() {
+        //the first 16 points of a pair put exactly one point into every cell of a 4x4 grid
+        let mut sampler = SobolSampler::new(42);
+        let mut cells = [0; 16];
+        for index in 0..16 {
+            sampler.start_sample(3, 5, index);
+            let (x, y) = sampler.next_2d();
+            cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&count| count == 1));
+    }
+}
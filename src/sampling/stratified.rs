@@ -0,0 +1,62 @@
+use crate::math::random;
+use crate::sampling::sampler::{to_unit_float, SampleState, Sampler};
+
+/// divides every dimension into as many strata as a pixel gets samples
+/// and puts every sample into its own, randomly jittered stratum
+///
+/// 2d values use a grid of strata, the samples visit the strata in a random order per pixel.
+/// sample `n` and `n + strata` use the same stratum, so incremental renders
+/// (which only know about one sample per frame) do not profit from this sampler.
+pub struct StratifiedSampler {
+    state: SampleState,
+    /// strata per dimension
+    strata: u32,
+    /// columns and rows of the grid of strata for 2d values
+    grid: (u32, u32),
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples: u32) -> Self {
+        let strata = samples.max(1);
+        let columns = (strata as f32).sqrt().ceil() as u32;
+        let rows = strata.div_ceil(columns);
+
+        StratifiedSampler {
+            state: SampleState::new(seed),
+            strata,
+            grid: (columns, rows),
+        }
+    }
+
+    /// the stratum of the current sample in `0..count`, and the random jitter inside of it
+    fn stratum(&self, dimension: u32, count: u32) -> (u32, u64) {
+        let index = self.state.index;
+        //every group of `count` samples visits every stratum once
+        let round = (index / count) as u64;
+        let order = self.state.pixel_hash(&[dimension as u64, round]);
+        let stratum = random::permute(index % count, count, order as u32);
+
+        let jitter = self.state.pixel_hash(&[dimension as u64, index as u64, 1]);
+        (stratum, jitter)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn state_mut(&mut self) -> &mut SampleState {
+        &mut self.state
+    }
+
+    fn value_1d(&self, dimension: u32) -> f32 {
+        let (stratum, jitter) = self.stratum(dimension, self.strata);
+        (stratum as f32 + to_unit_float(jitter as u32)) / self.strata as f32
+    }
+
+    fn value_2d(&self, dimension: u32) -> (f32, f32) {
+        let (columns, rows) = self.grid;
+        let (stratum, jitter) = self.stratum(dimension, columns * rows);
+
+        let x = (stratum % columns) as f32 + to_unit_float(jitter as u32);
+        let y = (stratum / columns) as f32 + to_unit_float((jitter >> 32) as u32);
+        (x / columns as f32, y / rows as f32)
+    }
+}
@@ -0,0 +1,959 @@
+use crate::camera::{Camera, CropFactor, Focus};
+use crate::checkpoint;
+use crate::gfx::material::*;
+use crate::gfx::spectrum::LAMBDA_MAX;
+use crate::gfx::texture::{CheckeredTexture, ConstantTexture, ImageTexture, Texture};
+use crate::hit::Hit;
+use crate::hittables::mesh::Mesh;
+use crate::hittables::primitives::{Sphere, Triangle};
+use crate::hittables::volume::{ConstantVolume, Isotropic};
+use crate::integrators::integrator::IntegratorKind;
+use crate::math::pdf::MisHeuristic;
+use crate::math::quat::Quaternion;
+use crate::math::transform::Transform;
+use crate::math::vec3::Vec3;
+use crate::pathtracer::{self, BounceLimits, PathTracer};
+use crate::sampling::sampler::SamplerKind;
+
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/*
+    A scene file is a TOML document, see `scenes/spheres.toml` for an example.
+
+    textures and materials are named tables, everything else refers to them by name:
+
+    [textures.red]              [materials.red_paint]        [[objects]]
+    type = "constant"           type = "lambertian"          type = "sphere"
+    color = [1.0, 0.0, 0.0]     albedo = "red"               center = [0.0, 1.0, 0.0]
+                                                             radius = 1.0
+                                                             material = "red_paint"
+
+    relative file paths (images, meshes) are relative to the scene file.
+*/
+
+/// everything that can go wrong while loading a scene
+#[derive(Debug)]
+pub enum SceneError {
+    /// the scene file could not be read
+    Io(PathBuf, std::io::Error),
+    /// the scene file is not valid TOML or does not match the scene format
+    Parse(PathBuf, toml::de::Error),
+    /// a texture name was used that is not defined in `[textures]`
+    UnknownTexture { name: String, used_by: String },
+    /// a material name was used that is not defined in `[materials]`
+    UnknownMaterial { name: String, used_by: String },
+    /// a texture (indirectly) references itself
+    TextureCycle(String),
+    /// a material (indirectly) references itself
+    MaterialCycle(String),
+    /// an image texture could not be loaded
+    Image(PathBuf, image2::Error),
+    /// a mesh could not be loaded
+    Mesh(PathBuf, tobj::LoadError),
+    /// the camera has neither a `target` nor a `direction`
+    CameraOrientation,
+    /// a value is outside of its valid range
+    InvalidValue { what: String, reason: &'static str },
+    /// there is nothing to render
+    NoObjects,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            SceneError::Parse(path, e) => write!(f, "invalid scene {}: {}", path.display(), e),
+            SceneError::UnknownTexture { name, used_by } => {
+                write!(f, "unknown texture '{}' used by {}", name, used_by)
+            }
+            SceneError::UnknownMaterial { name, used_by } => {
+                write!(f, "unknown material '{}' used by {}", name, used_by)
+            }
+            SceneError::TextureCycle(name) => {
+                write!(f, "texture '{}' references itself", name)
+            }
+            SceneError::MaterialCycle(name) => {
+                write!(f, "material '{}' references itself", name)
+            }
+            SceneError::Image(path, e) => {
+                write!(f, "could not load image {}: {}", path.display(), e)
+            }
+            SceneError::Mesh(path, e) => write!(f, "could not load mesh {}: {}", path.display(), e),
+            SceneError::CameraOrientation => {
+                write!(f, "camera needs either a 'target' or a 'direction'")
+            }
+            SceneError::InvalidValue { what, reason } => write!(f, "invalid {}: {}", what, reason),
+            SceneError::NoObjects => write!(f, "scene does not contain any objects"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// render settings stored in the scene file, every value is optional
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub incremental: bool,
+    /// the same seed always renders the same image
+    pub seed: u64,
+    /// random, stratified, halton, sobol or blue_noise
+    pub sampler: SamplerKind,
+    /// paths end after this many bounces
+    pub max_depth: u32,
+    /// paths end after this many bounces off diffuse materials
+    pub max_diffuse_depth: u32,
+    /// paths end after this many bounces off glossy materials
+    pub max_glossy_depth: u32,
+    /// paths end after this many times going through transparent materials
+    pub max_transmission_depth: u32,
+    /// paths end after scattering this many times inside volumes
+    pub max_volume_depth: u32,
+    /// paths with little throughput may randomly end after this many bounces
+    pub roulette_depth: u32,
+    /// balance or power, how light sampling and material sampling are combined
+    pub mis: MisHeuristic,
+    /// path, bidirectional, metropolis, photon_mapping, direct, ambient_occlusion, albedo, normal
+    /// or bvh_heatmap
+    pub integrator: IntegratorKind,
+    /// how many photons every frame emits with photon mapping
+    pub photons: u32,
+    /// how far photons are gathered from in the first frame, it shrinks over the frames
+    pub photon_radius: f32,
+    /// trace wavelengths instead of rgb colors, textures are converted to spectra
+    pub spectral: bool,
+    /// the path integrator learns where light comes from over the incremental frames
+    pub guiding: bool,
+}
+
+impl Settings {
+    pub fn bounce_limits(&self) -> BounceLimits {
+        BounceLimits {
+            total: self.max_depth,
+            diffuse: self.max_diffuse_depth,
+            glossy: self.max_glossy_depth,
+            transmission: self.max_transmission_depth,
+            volume: self.max_volume_depth,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: 800,
+            height: 600,
+            samples: 48,
+            incremental: false,
+            seed: 0,
+            sampler: SamplerKind::default(),
+            max_depth: pathtracer::DEFAULT_MAX_DEPTH,
+            max_diffuse_depth: pathtracer::DEFAULT_MAX_DEPTH,
+            max_glossy_depth: pathtracer::DEFAULT_MAX_DEPTH,
+            max_transmission_depth: pathtracer::DEFAULT_MAX_DEPTH,
+            max_volume_depth: pathtracer::DEFAULT_MAX_DEPTH,
+            roulette_depth: pathtracer::DEFAULT_ROULETTE_DEPTH,
+            mis: MisHeuristic::default(),
+            integrator: IntegratorKind::default(),
+            photons: pathtracer::DEFAULT_PHOTONS,
+            photon_radius: pathtracer::DEFAULT_PHOTON_RADIUS,
+            spectral: false,
+            guiding: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum CropFactorDescription {
+    Named(String),
+    Custom(f32),
+}
+
+impl Default for CropFactorDescription {
+    fn default() -> Self {
+        CropFactorDescription::Named("full_format".to_string())
+    }
+}
+
+fn default_focal_length() -> f32 {
+    35.0
+}
+
+fn default_fstop() -> i32 {
+    8
+}
+
+fn default_fov() -> f32 {
+    90.0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum CameraDescription {
+    /// see `Camera::new_physical`
+    Physical {
+        position: [f32; 3],
+        target: Option<[f32; 3]>,
+        direction: Option<[f32; 3]>,
+        /// distance to the focus plane, autofocus if missing
+        focus_distance: Option<f32>,
+        #[serde(default = "default_focal_length")]
+        focal_length: f32,
+        #[serde(default = "default_fstop")]
+        fstop: i32,
+        #[serde(default)]
+        crop_factor: CropFactorDescription,
+    },
+    /// see `Camera::new_virtual`
+    Virtual {
+        position: [f32; 3],
+        target: Option<[f32; 3]>,
+        direction: Option<[f32; 3]>,
+        #[serde(default = "default_fov")]
+        fov: f32,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDescription {
+    /// a single linear RGB color
+    Constant { color: [f32; 3] },
+    /// switches between two other textures
+    Checkered { a: String, b: String },
+    /// an image file, LDR or HDR
+    Image { path: PathBuf },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDescription {
+    Lambertian {
+        albedo: String,
+        normalmap: Option<String>,
+    },
+    Metal {
+        albedo: String,
+        normalmap: Option<String>,
+        metallic: String,
+        roughness: String,
+    },
+    Dielectric {
+        albedo: String,
+        normalmap: Option<String>,
+        /// smooth if not given
+        roughness: Option<String>,
+        refractive_index: f32,
+        /// how much the refractive index grows towards blue, in µm², none if not given
+        dispersion: Option<f32>,
+    },
+    /// the optional parameters default to a plain dielectric:
+    /// specular 0.5, no sheen, clearcoat or transmission
+    Principled {
+        base_color: String,
+        normalmap: Option<String>,
+        metallic: String,
+        roughness: String,
+        specular: Option<String>,
+        sheen: Option<String>,
+        clearcoat: Option<String>,
+        transmission: Option<String>,
+    },
+    /// `first` and `second` blended by the mask `weight`, 0 is all `first`
+    Mix {
+        first: String,
+        second: String,
+        weight: String,
+    },
+    Emissive {
+        emitted: String,
+    },
+    Isotropic {
+        albedo: String,
+    },
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDescription {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: String,
+    },
+    Triangle {
+        /// the corner both spanning vectors start at
+        llc: [f32; 3],
+        span_a: [f32; 3],
+        span_b: [f32; 3],
+        material: String,
+    },
+    /// a wavefront obj file, uses a default material if none is given
+    Mesh {
+        file: PathBuf,
+        material: Option<String>,
+    },
+    Transform {
+        object: Box<ObjectDescription>,
+        #[serde(default)]
+        position: [f32; 3],
+        /// euler angles in degrees: yaw, pitch, roll
+        #[serde(default)]
+        rotation: [f32; 3],
+        /// uniform, greater than 0
+        #[serde(default = "default_scale")]
+        scale: f32,
+    },
+    /// a volume of constant density inside of `boundary`
+    Volume {
+        boundary: Box<ObjectDescription>,
+        density: f32,
+        material: String,
+    },
+}
+
+/// the parsed contents of a scene file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub settings: Settings,
+    camera: CameraDescription,
+    /// name of the texture used as the (equirectangular) sky
+    sky: Option<String>,
+    #[serde(default)]
+    textures: HashMap<String, TextureDescription>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDescription>,
+    #[serde(default)]
+    objects: Vec<ObjectDescription>,
+
+    /// the directory relative paths are resolved against
+    #[serde(skip)]
+    base_dir: PathBuf,
+    /// hash of the scene file, see `checkpoint::hash_scene`
+    #[serde(skip)]
+    hash: u64,
+}
+
+impl SceneDescription {
+    /// reads and parses a scene file, but does not load any textures or meshes yet
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let source =
+            std::fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+        Self::parse(&source, path)
+    }
+
+    /// parses `source` as if it was read from the scene file `path`
+    fn parse(source: &str, path: &Path) -> Result<Self, SceneError> {
+        let mut description: SceneDescription =
+            toml::from_str(source).map_err(|e| SceneError::Parse(path.to_path_buf(), e))?;
+
+        description.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        //the images and meshes are part of the scene too, files that can not be read are
+        //left out, building the scene reports them
+        let mut hash = checkpoint::hash_scene(source.as_bytes());
+        for asset in description.asset_paths() {
+            if let Ok(contents) = std::fs::read(&asset) {
+                hash = checkpoint::extend_hash(hash, &contents);
+            }
+        }
+        description.hash = hash;
+        Ok(description)
+    }
+
+    /// identifies the scene and the files it refers to, e.g. to make sure a checkpoint belongs to it
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    fn resolve_path(&self, path: &Path) -> PathBuf {
+        if path.is_relative() {
+            self.base_dir.join(path)
+        } else {
+            path.to_path_buf()
+        }
+    }
+
+    /// the image and mesh files the scene refers to, sorted and resolved against the scene file
+    fn asset_paths(&self) -> Vec<PathBuf> {
+        fn object_paths(object: &ObjectDescription, paths: &mut Vec<PathBuf>) {
+            match object {
+                ObjectDescription::Mesh { file, .. } => paths.push(file.clone()),
+                ObjectDescription::Transform { object, .. } => object_paths(object, paths),
+                ObjectDescription::Volume { boundary, .. } => object_paths(boundary, paths),
+                ObjectDescription::Sphere { .. } | ObjectDescription::Triangle { .. } => {}
+            }
+        }
+
+        let mut paths = Vec::new();
+        for texture in self.textures.values() {
+            if let TextureDescription::Image { path } = texture {
+                paths.push(path.clone());
+            }
+        }
+        for object in &self.objects {
+            object_paths(object, &mut paths);
+        }
+
+        let mut paths: Vec<PathBuf> = paths.iter().map(|path| self.resolve_path(path)).collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn material_count(&self) -> usize {
+        self.materials.len()
+    }
+
+    /// the number of objects at the top level of the scene
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// loads all resources and builds a finalised path tracer
+    pub fn build(&self) -> Result<PathTracer, SceneError> {
+        if self.settings.width == 0 || self.settings.height == 0 {
+            return Err(SceneError::InvalidValue {
+                what: "settings".to_string(),
+                reason: "width and height must be greater than 0",
+            });
+        }
+        if self.settings.samples == 0 {
+            return Err(SceneError::InvalidValue {
+                what: "settings".to_string(),
+                reason: "samples must be greater than 0",
+            });
+        }
+        if self.objects.is_empty() {
+            return Err(SceneError::NoObjects);
+        }
+
+        let mut builder = SceneBuilder {
+            description: self,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            loading: HashSet::new(),
+            loading_materials: HashSet::new(),
+        };
+
+        let camera = builder.build_camera()?;
+        let sky = match &self.sky {
+            Some(name) => builder.texture(name, "the sky")?,
+            None => Arc::new(ConstantTexture::new(Vec3::new(0.0, 0.0, 0.0))),
+        };
+
+        let mut path_tracer = PathTracer::new(
+            self.settings.width,
+            self.settings.height,
+            self.settings.samples,
+            self.settings.incremental,
+            camera,
+            sky,
+        );
+        path_tracer.set_seed(self.settings.seed);
+        path_tracer.set_sampler(self.settings.sampler);
+        path_tracer.set_bounce_limits(self.settings.bounce_limits());
+        path_tracer.set_roulette_depth(self.settings.roulette_depth);
+        path_tracer.set_mis_heuristic(self.settings.mis);
+        path_tracer.set_integrator(self.settings.integrator);
+        path_tracer.set_photons(self.settings.photons, self.settings.photon_radius);
+        path_tracer.set_spectral(self.settings.spectral);
+        path_tracer.set_guiding(self.settings.guiding);
+
+        for (index, object) in self.objects.iter().enumerate() {
+            let object = builder.object(object, &format!("object #{}", index + 1))?;
+            path_tracer.add_object(object);
+        }
+
+        Ok(path_tracer.finalise())
+    }
+}
+
+/// resolves names to textures and materials, every resource is only loaded once
+struct SceneBuilder<'a> {
+    description: &'a SceneDescription,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    /// textures which are currently being built, used to detect cycles
+    loading: HashSet<String>,
+    /// the same for materials
+    loading_materials: HashSet<String>,
+}
+
+impl<'a> SceneBuilder<'a> {
+    fn build_camera(&self) -> Result<Camera, SceneError> {
+        let settings = &self.description.settings;
+
+        fn direction(
+            position: Vec3,
+            target: Option<[f32; 3]>,
+            direction: Option<[f32; 3]>,
+        ) -> Result<Vec3, SceneError> {
+            let direction = match (target, direction) {
+                (_, Some(direction)) => Vec3::from(direction),
+                (Some(target), None) => Vec3::from(target) - position,
+                (None, None) => return Err(SceneError::CameraOrientation),
+            };
+
+            if direction.len_squared() == 0.0 {
+                return Err(SceneError::InvalidValue {
+                    what: "camera".to_string(),
+                    reason: "the view direction must not be zero",
+                });
+            }
+            Ok(direction)
+        }
+
+        match &self.description.camera {
+            CameraDescription::Physical {
+                position,
+                target,
+                direction: dir,
+                focus_distance,
+                focal_length,
+                fstop,
+                crop_factor,
+            } => {
+                let position = Vec3::from(*position);
+                let dir = direction(position, *target, *dir)?;
+
+                let crop_factor = match crop_factor {
+                    CropFactorDescription::Named(name) => match name.as_str() {
+                        "full_format" => CropFactor::FULL_FORMAT,
+                        "apsc" => CropFactor::APSC,
+                        "apsc_canon" => CropFactor::APSC_CANON,
+                        _ => {
+                            return Err(SceneError::InvalidValue {
+                                what: format!("crop factor '{}'", name),
+                                reason: "expected full_format, apsc, apsc_canon or a number",
+                            })
+                        }
+                    },
+                    CropFactorDescription::Custom(cf) if *cf > 0.0 => CropFactor::custom(*cf),
+                    CropFactorDescription::Custom(_) => {
+                        return Err(SceneError::InvalidValue {
+                            what: "crop factor".to_string(),
+                            reason: "must be greater than 0",
+                        })
+                    }
+                };
+
+                if *focal_length <= 0.0 {
+                    return Err(SceneError::InvalidValue {
+                        what: "focal length".to_string(),
+                        reason: "must be greater than 0",
+                    });
+                }
+
+                let focus = match focus_distance {
+                    Some(d) => Focus::Distance(*d),
+                    None => Focus::AutoFocus,
+                };
+
+                Ok(Camera::new_physical(
+                    position,
+                    dir,
+                    settings.width,
+                    settings.height,
+                    focus,
+                    *focal_length,
+                    *fstop,
+                    crop_factor,
+                ))
+            }
+            CameraDescription::Virtual {
+                position,
+                target,
+                direction: dir,
+                fov,
+            } => {
+                let position = Vec3::from(*position);
+                let dir = direction(position, *target, *dir)?;
+
+                if *fov <= 0.0 || *fov >= 180.0 {
+                    return Err(SceneError::InvalidValue {
+                        what: "field of view".to_string(),
+                        reason: "must be between 0 and 180 degrees",
+                    });
+                }
+
+                Ok(Camera::new_virtual(
+                    position,
+                    dir,
+                    *fov,
+                    settings.width,
+                    settings.height,
+                ))
+            }
+        }
+    }
+
+    fn texture(&mut self, name: &str, used_by: &str) -> Result<Arc<dyn Texture>, SceneError> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone());
+        }
+
+        let description =
+            self.description
+                .textures
+                .get(name)
+                .ok_or_else(|| SceneError::UnknownTexture {
+                    name: name.to_string(),
+                    used_by: used_by.to_string(),
+                })?;
+
+        if !self.loading.insert(name.to_string()) {
+            return Err(SceneError::TextureCycle(name.to_string()));
+        }
+
+        let used_by = format!("texture '{}'", name);
+        let texture: Arc<dyn Texture> = match description {
+            TextureDescription::Constant { color } => {
+                Arc::new(ConstantTexture::new(Vec3::from(*color)))
+            }
+            TextureDescription::Checkered { a, b } => {
+                let a = self.texture(a, &used_by)?;
+                let b = self.texture(b, &used_by)?;
+                Arc::new(CheckeredTexture::new(a, b))
+            }
+            TextureDescription::Image { path } => {
+                let path = self.description.resolve_path(path);
+                let image = ImageTexture::load(&path).map_err(|e| SceneError::Image(path, e))?;
+                Arc::new(image)
+            }
+        };
+
+        self.loading.remove(name);
+        self.textures.insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+
+    fn optional_texture(
+        &mut self,
+        name: &Option<String>,
+        used_by: &str,
+    ) -> Result<Option<Arc<dyn Texture>>, SceneError> {
+        match name {
+            Some(name) => Ok(Some(self.texture(name, used_by)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// a constant texture of `default` if no name is given
+    fn texture_or(
+        &mut self,
+        name: &Option<String>,
+        default: f32,
+        used_by: &str,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        match self.optional_texture(name, used_by)? {
+            Some(texture) => Ok(texture),
+            None => Ok(Arc::new(ConstantTexture::new(Vec3::new(
+                default, default, default,
+            )))),
+        }
+    }
+
+    fn material(&mut self, name: &str, used_by: &str) -> Result<Arc<dyn Material>, SceneError> {
+        if let Some(material) = self.materials.get(name) {
+            return Ok(material.clone());
+        }
+
+        let description =
+            self.description
+                .materials
+                .get(name)
+                .ok_or_else(|| SceneError::UnknownMaterial {
+                    name: name.to_string(),
+                    used_by: used_by.to_string(),
+                })?;
+
+        if !self.loading_materials.insert(name.to_string()) {
+            return Err(SceneError::MaterialCycle(name.to_string()));
+        }
+
+        let used_by = format!("material '{}'", name);
+        let material: Arc<dyn Material> = match description {
+            MaterialDescription::Lambertian { albedo, normalmap } => Arc::new(Lambertian::new(
+                self.texture(albedo, &used_by)?,
+                self.optional_texture(normalmap, &used_by)?,
+            )),
+            MaterialDescription::Metal {
+                albedo,
+                normalmap,
+                metallic,
+                roughness,
+            } => Arc::new(Metal::new(
+                self.texture(albedo, &used_by)?,
+                self.optional_texture(normalmap, &used_by)?,
+                self.texture(metallic, &used_by)?,
+                self.texture(roughness, &used_by)?,
+            )),
+            MaterialDescription::Dielectric {
+                albedo,
+                normalmap,
+                roughness,
+                refractive_index,
+                dispersion,
+            } => {
+                if *refractive_index <= 0.0 {
+                    return Err(SceneError::InvalidValue {
+                        what: format!("refractive index of {}", used_by),
+                        reason: "must be greater than 0",
+                    });
+                }
+                let material = Dielectric::new(
+                    self.texture(albedo, &used_by)?,
+                    self.optional_texture(normalmap, &used_by)?,
+                    self.optional_texture(roughness, &used_by)?,
+                    *refractive_index,
+                    dispersion.unwrap_or(0.0),
+                );
+                if dispersion.is_some_and(|dispersion| dispersion < 0.0)
+                    || material.refractive_index_at(LAMBDA_MAX) <= 0.0
+                {
+                    return Err(SceneError::InvalidValue {
+                        what: format!("dispersion of {}", used_by),
+                        reason: "must not be negative, nor lower the refractive index to 0",
+                    });
+                }
+                Arc::new(material)
+            }
+            MaterialDescription::Principled {
+                base_color,
+                normalmap,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                transmission,
+            } => Arc::new(Principled::new(
+                self.texture(base_color, &used_by)?,
+                self.optional_texture(normalmap, &used_by)?,
+                self.texture(metallic, &used_by)?,
+                self.texture(roughness, &used_by)?,
+                self.texture_or(specular, 0.5, &used_by)?,
+                self.texture_or(sheen, 0.0, &used_by)?,
+                self.texture_or(clearcoat, 0.0, &used_by)?,
+                self.texture_or(transmission, 0.0, &used_by)?,
+            )),
+            MaterialDescription::Mix {
+                first,
+                second,
+                weight,
+            } => Arc::new(MixMaterial::new(
+                self.material(first, &used_by)?,
+                self.material(second, &used_by)?,
+                self.texture(weight, &used_by)?,
+            )),
+            MaterialDescription::Emissive { emitted } => {
+                Arc::new(Emissive::new(self.texture(emitted, &used_by)?))
+            }
+            MaterialDescription::Isotropic { albedo } => {
+                Arc::new(Isotropic::new(self.texture(albedo, &used_by)?))
+            }
+        };
+
+        self.loading_materials.remove(name);
+        self.materials.insert(name.to_string(), material.clone());
+        Ok(material)
+    }
+
+    fn object(
+        &mut self,
+        description: &ObjectDescription,
+        used_by: &str,
+    ) -> Result<Arc<dyn Hit>, SceneError> {
+        let object: Arc<dyn Hit> = match description {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                if radius.is_nan() || *radius <= 0.0 {
+                    return Err(SceneError::InvalidValue {
+                        what: format!("radius of {}", used_by),
+                        reason: "must be greater than 0",
+                    });
+                }
+                Arc::new(Sphere {
+                    center: Vec3::from(*center),
+                    radius: *radius,
+                    material: self.material(material, used_by)?,
+                })
+            }
+            ObjectDescription::Triangle {
+                llc,
+                span_a,
+                span_b,
+                material,
+            } => Arc::new(Triangle {
+                llc: Vec3::from(*llc),
+                span_a: Vec3::from(*span_a),
+                span_b: Vec3::from(*span_b),
+                material: self.material(material, used_by)?,
+            }),
+            ObjectDescription::Mesh { file, material } => {
+                let path = self.description.resolve_path(file);
+                let mesh = match material {
+                    Some(material) => {
+                        let material = self.material(material, used_by)?;
+                        Mesh::load(&path, material)
+                    }
+                    None => Mesh::load(&path, Mesh::default_material()),
+                };
+                Arc::new(mesh.map_err(|e| SceneError::Mesh(path, e))?)
+            }
+            ObjectDescription::Transform {
+                object,
+                position,
+                rotation,
+                scale,
+            } => {
+                if *scale <= 0.0 {
+                    return Err(SceneError::InvalidValue {
+                        what: format!("scale of {}", used_by),
+                        reason: "must be greater than 0",
+                    });
+                }
+                let object = self.object(object, used_by)?;
+                let rotation = Quaternion::from_euler(rotation[0], rotation[1], rotation[2]);
+                Arc::new(Transform::new(
+                    object,
+                    Vec3::from(*position),
+                    rotation,
+                    *scale,
+                ))
+            }
+            ObjectDescription::Volume {
+                boundary,
+                density,
+                material,
+            } => {
+                if *density <= 0.0 {
+                    return Err(SceneError::InvalidValue {
+                        what: format!("density of {}", used_by),
+                        reason: "must be greater than 0",
+                    });
+                }
+                let boundary = self.object(boundary, used_by)?;
+                let material = self.material(material, used_by)?;
+                Arc::new(ConstantVolume::new(boundary, *density, material))
+            }
+        };
+
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a scene with a single sphere made of `material`, followed by `extra`
+    fn scene(radius: &str, material: &str, extra: &str) -> String {
+        format!(
+            r#"
+            [camera]
+            type = "virtual"
+            position = [0.0, 1.0, -5.0]
+            target = [0.0, 1.0, 0.0]
+
+            [textures.white]
+            type = "constant"
+            color = [1.0, 1.0, 1.0]
+
+            [materials.paint]
+            type = "lambertian"
+            albedo = "white"
+
+            [[objects]]
+            type = "sphere"
+            center = [0.0, 1.0, 0.0]
+            radius = {}
+            material = "{}"
+            {}
+            "#,
+            radius, material, extra
+        )
+    }
+
+    /// the message of the error loading `source` gives
+    fn error(source: &str) -> String {
+        let path = Path::new("missing_dir/test.toml");
+        match SceneDescription::parse(source, path).and_then(|description| description.build()) {
+            Ok(_) => panic!("the scene loaded"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_valid_scene() {
+        let description =
+            SceneDescription::parse(&scene("1.0", "paint", ""), Path::new("test.toml")).unwrap();
+        assert!(description.build().is_ok());
+    }
+
+    #[test]
+    fn test_unknown_material() {
+        assert_eq!(
+            error(&scene("1.0", "chrome", "")),
+            "unknown material 'chrome' used by object #1"
+        );
+    }
+
+    #[test]
+    fn test_missing_mesh() {
+        let mesh = r#"
+            [[objects]]
+            type = "mesh"
+            file = "teapot.obj"
+            "#;
+        let message = error(&scene("1.0", "paint", mesh));
+        assert!(
+            message.starts_with("could not load mesh missing_dir/teapot.obj"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_bad_radius() {
+        for radius in ["0.0", "-1.0", "nan"] {
+            assert_eq!(
+                error(&scene(radius, "paint", "")),
+                "invalid radius of object #1: must be greater than 0"
+            );
+        }
+    }
+
+    #[test]
+    fn test_malformed_toml() {
+        let message = error("[camera\ntype = \"virtual\"");
+        assert!(
+            message.starts_with("invalid scene missing_dir/test.toml:"),
+            "{}",
+            message
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use crate::film::Film;
+use std::fmt;
+use std::time::Duration;
+
+/// when a progressive render is good enough to end it
+/// the first condition that is met ends the render, without any the render runs forever
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StopConditions {
+    /// stop once the render took this long
+    pub time_budget: Option<Duration>,
+    /// stop once the estimated relative error of the image is below this
+    pub noise_target: Option<f32>,
+}
+
+/// the condition that ended a render
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StopReason {
+    TimeBudget,
+    NoiseTarget,
+    /// adaptive sampling stopped sampling every pixel, more frames would not change anything
+    Converged,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::TimeBudget => write!(f, "time budget reached"),
+            StopReason::NoiseTarget => write!(f, "noise target reached"),
+            StopReason::Converged => write!(f, "all pixels converged"),
+        }
+    }
+}
+
+impl StopConditions {
+    pub fn is_empty(&self) -> bool {
+        self.time_budget.is_none() && self.noise_target.is_none()
+    }
+
+    /// checks the conditions after a frame
+    /// `new_samples` is the number of samples the last frame added to the film
+    pub fn check(&self, elapsed: Duration, film: &Film, new_samples: u64) -> Option<StopReason> {
+        if new_samples == 0 {
+            return Some(StopReason::Converged);
+        }
+        if let Some(budget) = self.time_budget {
+            if elapsed >= budget {
+                return Some(StopReason::TimeBudget);
+            }
+        }
+        if let Some(target) = self.noise_target {
+            if film.relative_error() <= target {
+                return Some(StopReason::NoiseTarget);
+            }
+        }
+        None
+    }
+}
+
+/// prints why and in which state a render was stopped
+pub fn report(reason: StopReason, elapsed: Duration, frames: u32, film: &Film) {
+    let pixels = film.pixel_count().max(1) as f64;
+    //the last progress report did not end its line
+    println!();
+    println!(
+        "Render stopped: {} after {:.1}s, {} frames, {:.1} samples per pixel on average, estimated relative error {:.2}%",
+        reason,
+        elapsed.as_secs_f64(),
+        frames,
+        film.total_samples() as f64 / pixels,
+        100.0 * film.relative_error(),
+    );
+}
@@ -0,0 +1,304 @@
+use crate::film::{PixelStats, Splat};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// the order in which the tiles of a frame are handed to the render threads
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TileOrder {
+    /// row by row, from the top left
+    Scanline,
+    /// from the center of the image outwards
+    Spiral,
+    /// along a hilbert curve, neighbouring tiles are rendered close in time
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!(
+                "unknown tile order '{}', expected scanline, spiral or hilbert",
+                s
+            )),
+        }
+    }
+}
+
+/// a rectangular part of the image, in pixels
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn pixel_count(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+}
+
+/// the pixels of one rendered tile
+/// the buffers have the same layout as the image buffers, but are only `tile.width` wide
+pub struct RenderedTile {
+    pub tile: Tile,
+    pub color: Vec<f32>,
+    pub albedo: Vec<f32>,
+    pub normal: Vec<f32>,
+    pub depth: Vec<f32>,
+    /// the sample statistics of every pixel, including the samples of this tile
+    pub stats: Vec<PixelStats>,
+    /// how many rays were traced for this tile
+    pub rays: u64,
+    /// light that landed on any pixel of the image, not only on this tile
+    pub splats: Vec<Splat>,
+}
+
+impl RenderedTile {
+    /// `stats` are the statistics of the pixels before this tile was rendered
+    pub fn new(tile: Tile, stats: Vec<PixelStats>) -> Self {
+        let size = tile.pixel_count() * 3;
+        RenderedTile {
+            tile,
+            color: vec![0.0; size],
+            albedo: vec![0.0; size],
+            normal: vec![0.0; size],
+            depth: vec![0.0; size],
+            stats,
+            rays: 0,
+            splats: Vec::new(),
+        }
+    }
+}
+
+/// splits a `width` x `height` image into tiles of (at most) `size` x `size` pixels
+/// tiles at the right and bottom edge are cut to fit the image
+pub fn make_tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+
+    let grid = match order {
+        TileOrder::Scanline => scanline_order(columns, rows),
+        TileOrder::Spiral => spiral_order(columns, rows),
+        TileOrder::Hilbert => hilbert_order(columns, rows),
+    };
+
+    grid.into_iter()
+        .map(|(column, row)| {
+            let x = column * size;
+            let y = row * size;
+            Tile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+fn scanline_order(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect()
+}
+
+/// walks a square spiral around the center tile, skipping positions outside of the grid
+fn spiral_order(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let total = (columns * rows) as usize;
+    let mut order = Vec::with_capacity(total);
+
+    let (mut x, mut y) = ((columns as i64 - 1) / 2, (rows as i64 - 1) / 2);
+    //right, down, left, up
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut direction = 0;
+    let mut run_length = 1;
+
+    while order.len() < total {
+        //every run length is walked twice: 1 right, 1 down, 2 left, 2 up, 3 right, ...
+        for _ in 0..2 {
+            for _ in 0..run_length {
+                if x >= 0 && y >= 0 && x < columns as i64 && y < rows as i64 {
+                    order.push((x as u32, y as u32));
+                }
+                x += directions[direction].0;
+                y += directions[direction].1;
+            }
+            direction = (direction + 1) % 4;
+        }
+        run_length += 1;
+    }
+
+    order
+}
+
+/// follows a hilbert curve over the smallest power of two square covering the grid
+fn hilbert_order(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let n = columns.max(rows).next_power_of_two();
+
+    (0..n * n)
+        .map(|d| hilbert_point(n, d))
+        .filter(|&(x, y)| x < columns && y < rows)
+        .collect()
+}
+
+/// converts a distance along the hilbert curve to a position in an `n` x `n` grid
+fn hilbert_point(n: u32, d: u32) -> (u32, u32) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+
+        //rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    (x, y)
+}
+
+/// prints completed tiles, rays per second and an ETA of the current frame to the console
+pub struct Progress {
+    frame: u32,
+    total_pixels: usize,
+    done_pixels: usize,
+    total_tiles: usize,
+    done_tiles: usize,
+    rays: u64,
+    start: Instant,
+    last_report: Option<Instant>,
+}
+
+impl Progress {
+    /// the console is updated at most this often
+    const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub fn new(frame: u32, tiles: &[Tile]) -> Self {
+        Progress {
+            frame,
+            total_pixels: tiles.iter().map(Tile::pixel_count).sum(),
+            done_pixels: 0,
+            total_tiles: tiles.len(),
+            done_tiles: 0,
+            rays: 0,
+            start: Instant::now(),
+            last_report: None,
+        }
+    }
+
+    pub fn tile_done(&mut self, tile: &Tile, rays: u64) {
+        self.done_tiles += 1;
+        self.done_pixels += tile.pixel_count();
+        self.rays += rays;
+
+        let due = match self.last_report {
+            Some(last) => last.elapsed() >= Self::REPORT_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.report();
+        }
+    }
+
+    /// prints the statistics of the whole frame and ends the line
+    pub fn finish(&mut self) {
+        self.report();
+        println!();
+    }
+
+    /// prints the current statistics, overwriting the last report
+    pub fn report(&mut self) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rays_per_second = if elapsed > 0.0 {
+            self.rays as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        //tiles are not equally expensive, pixels are a better estimate
+        let remaining = if self.done_pixels > 0 {
+            let remaining_pixels = (self.total_pixels - self.done_pixels) as f64;
+            elapsed * remaining_pixels / self.done_pixels as f64
+        } else {
+            0.0
+        };
+
+        print!(
+            "\rframe {}: {}/{} tiles ({:.1}%), {:.2} Mrays/s, {:.1}s elapsed, ETA {:.1}s   ",
+            self.frame,
+            self.done_tiles,
+            self.total_tiles,
+            100.0 * self.done_pixels as f64 / self.total_pixels.max(1) as f64,
+            rays_per_second / 1.0e6,
+            elapsed,
+            remaining,
+        );
+        //nothing we can do if the console is gone
+        let _ = io::stdout().flush();
+
+        self.last_report = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles_cover_image() {
+        for order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = make_tiles(100, 70, 32, *order);
+            assert_eq!(tiles.len(), 4 * 3);
+
+            //every pixel is in exactly one tile
+            let mut covered = vec![0; 100 * 70];
+            for tile in &tiles {
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[(y * 100 + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&count| count == 1), "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn test_tile_orders() {
+        let scanline = make_tiles(3, 3, 1, TileOrder::Scanline);
+        assert_eq!((scanline[1].x, scanline[1].y), (1, 0));
+
+        //the spiral starts in the center
+        let spiral = make_tiles(3, 3, 1, TileOrder::Spiral);
+        assert_eq!((spiral[0].x, spiral[0].y), (1, 1));
+
+        //consecutive hilbert tiles are always neighbours
+        let hilbert = make_tiles(4, 4, 1, TileOrder::Hilbert);
+        for pair in hilbert.windows(2) {
+            let distance = (pair[0].x as i32 - pair[1].x as i32).abs()
+                + (pair[0].y as i32 - pair[1].y as i32).abs();
+            assert_eq!(distance, 1);
+        }
+    }
+}